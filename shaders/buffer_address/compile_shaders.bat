@@ -0,0 +1,2 @@
+glslangValidator -V --target-env vulkan1.2 .\square.comp -o comp.spv
+pause
\ No newline at end of file
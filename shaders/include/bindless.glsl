@@ -0,0 +1,48 @@
+// Declares the arrays of the bindless table, vulkan_core/bindless.rs. Include it with
+// GL_GOOGLE_include_directive, defining BINDLESS_SET first if the table isn't bound at set 0.
+//
+// Sampled images and samplers can always be indexed with nonuniformEXT. Storage images and storage buffers
+// only with the StorageArrayNonUniformIndexing feature, otherwise their indices have to be dynamically uniform.
+#ifndef BINDLESS_GLSL
+#define BINDLESS_GLSL
+
+#extension GL_EXT_nonuniform_qualifier : require
+
+#ifndef BINDLESS_SET
+    #define BINDLESS_SET 0
+#endif
+
+// The binding numbers match BindlessResourceType
+#define BINDLESS_SAMPLED_IMAGE_BINDING 0
+#define BINDLESS_STORAGE_IMAGE_BINDING 1
+#define BINDLESS_SAMPLER_BINDING 2
+#define BINDLESS_STORAGE_BUFFER_BINDING 3
+
+// One array, seen through every image type that can be registered
+layout(set = BINDLESS_SET, binding = BINDLESS_SAMPLED_IMAGE_BINDING) uniform texture2D bindless_textures[];
+layout(set = BINDLESS_SET, binding = BINDLESS_SAMPLED_IMAGE_BINDING) uniform texture2DArray bindless_texture_arrays[];
+layout(set = BINDLESS_SET, binding = BINDLESS_SAMPLED_IMAGE_BINDING) uniform textureCube bindless_cube_textures[];
+layout(set = BINDLESS_SET, binding = BINDLESS_SAMPLED_IMAGE_BINDING) uniform texture3D bindless_3d_textures[];
+
+// Without a format qualifier, which needs the StorageImageWriteWithoutFormat feature.
+// Define BINDLESS_STORAGE_IMAGES before the include only for pipelines of devices that have it.
+#ifdef BINDLESS_STORAGE_IMAGES
+layout(set = BINDLESS_SET, binding = BINDLESS_STORAGE_IMAGE_BINDING) writeonly uniform image2D bindless_storage_images[];
+#endif
+
+layout(set = BINDLESS_SET, binding = BINDLESS_SAMPLER_BINDING) uniform sampler bindless_samplers[];
+
+// Untyped words, shaders reinterpret them per buffer
+layout(set = BINDLESS_SET, binding = BINDLESS_STORAGE_BUFFER_BINDING, std430) buffer BindlessBuffer {
+    uint words[];
+} bindless_buffers[];
+
+// Indices can differ between invocations, for example when they come from a per-draw material ID
+#define bindless_sampler2D(texture_index, sampler_index) \
+    sampler2D(bindless_textures[nonuniformEXT(texture_index)], bindless_samplers[nonuniformEXT(sampler_index)])
+#define bindless_sampler2DArray(texture_index, sampler_index) \
+    sampler2DArray(bindless_texture_arrays[nonuniformEXT(texture_index)], bindless_samplers[nonuniformEXT(sampler_index)])
+#define bindless_samplerCube(texture_index, sampler_index) \
+    samplerCube(bindless_cube_textures[nonuniformEXT(texture_index)], bindless_samplers[nonuniformEXT(sampler_index)])
+
+#endif
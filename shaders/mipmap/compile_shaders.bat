@@ -0,0 +1,4 @@
+glslangValidator -V --target-env vulkan1.2 .\downsample.comp -o downsample_float.spv
+glslangValidator -V --target-env vulkan1.2 -DUINT_TEXELS .\downsample.comp -o downsample_uint.spv
+glslangValidator -V --target-env vulkan1.2 -DSINT_TEXELS .\downsample.comp -o downsample_sint.spv
+pause
\ No newline at end of file
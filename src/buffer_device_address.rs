@@ -0,0 +1,141 @@
+use std::path::Path;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use crate::render_app;
+use crate::vulkan_core::buffer_factory::VulkanBufferConfiguration;
+use crate::vulkan_core::cmd::create_command_buffer;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::features::DeviceFeature;
+use crate::vulkan_core::memory_usage::MemoryUsage;
+use crate::vulkan_core::pipeline::{create_compute_pipeline, ComputePipeline, ComputePipelineConfiguration, PushConstantsLayout};
+use crate::vulkan_core::sync::create_fence;
+use crate::vulkan_core::tools::read_shader_code;
+
+
+const VALUE_COUNT: u32 = 1000;
+const WORKGROUP_SIZE: u32 = 64;
+
+
+/// Matches `PushConstants` in shaders/buffer_address/square.comp.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct PushConstants {
+    source_address: vk::DeviceAddress,
+    destination_address: vk::DeviceAddress,
+    count: u32,
+    _padding: u32
+}
+
+
+/// Squares numbers in a compute shader that reaches both buffers through their device address.
+/// The source is device-local and filled by the upload manager, the results are read back from mapped memory.
+pub fn main() -> Result<(), VulkanError> {
+    let mut vulkan_base = render_app::create_headless_base()?;
+    if !vulkan_base.enabled_features.buffer_device_address {
+        return Err(VulkanError::MissingDeviceFeatures(vec![DeviceFeature::BufferDeviceAddress]));
+    }
+    let device = vulkan_base.device.clone();
+
+    let values: Vec<u32> = (0..VALUE_COUNT).collect();
+    let source_config = VulkanBufferConfiguration {
+        size: (values.len() * std::mem::size_of::<u32>()) as vk::DeviceSize,
+        memory_usage: MemoryUsage::GpuOnly,
+        buffer_usage: vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST |
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+    };
+    let source_buffer = vulkan_base.create_buffer(&source_config, "buffer_address.source")?;
+    vulkan_base.uploads.upload_buffer_now(&source_buffer, 0, bytemuck::cast_slice(&values))?;
+
+    let destination_buffer = vulkan_base.create_mapped_buffer::<u32>(
+        values.len(), MemoryUsage::GpuToCpu,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        "buffer_address.destination"
+    )?;
+
+    let pipeline_config = ComputePipelineConfiguration {
+        shader_code: read_shader_code(Path::new("shaders/buffer_address/comp.spv"))?,
+        set_layouts: Vec::new(),
+        push_constants_layout: PushConstantsLayout {
+            size_bytes: std::mem::size_of::<PushConstants>() as u32,
+            offset: 0,
+            shader_stages: vk::ShaderStageFlags::COMPUTE
+        },
+        spec_constants: vec![]
+    };
+    let pipeline = create_compute_pipeline(&device, &vulkan_base.debug_utils, &pipeline_config, "buffer_address.pipeline")?;
+
+    let missing_feature = || VulkanError::MissingDeviceFeatures(vec![DeviceFeature::BufferDeviceAddress]);
+    let push_constants = PushConstants {
+        source_address: source_buffer.device_address().ok_or_else(missing_feature)?,
+        destination_address: destination_buffer.buffer.device_address().ok_or_else(missing_feature)?,
+        count: VALUE_COUNT,
+        _padding: 0
+    };
+
+    // The upload manager hands buffers over to the graphics family, so the dispatch runs there too
+    let command_buffer = create_command_buffer(
+        &device, &vulkan_base.debug_utils, vulkan_base.command_pool, vk::CommandBufferLevel::PRIMARY, "buffer_address.commands"
+    )?;
+    let fence = create_fence(&device, &vulkan_base.debug_utils, false, "buffer_address.fence")?;
+
+    let result = dispatch_and_wait(&device, command_buffer, &pipeline, &push_constants, vulkan_base.graphics_queue, fence);
+
+    unsafe {
+        device.destroy_fence(fence, None);
+        device.free_command_buffers(vulkan_base.command_pool, std::slice::from_ref(&command_buffer));
+    }
+    result?;
+
+    destination_buffer.invalidate_range(0..destination_buffer.len())?;
+    let mismatch = destination_buffer.as_slice().iter()
+        .zip(&values)
+        .position(|(squared, value)| *squared != value * value);
+    if let Some(index) = mismatch {
+        return Err(VulkanError::WrongResult(format!(
+            "buffer_address.destination[{}]: expected {}, got {}",
+            index, values[index] * values[index], destination_buffer.as_slice()[index]
+        )));
+    }
+
+    println!("All {} values squared through buffer device addresses", VALUE_COUNT);
+    return Ok(());
+}
+
+fn dispatch_and_wait(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline: &ComputePipeline,
+    push_constants: &PushConstants,
+    queue: vk::Queue,
+    fence: vk::Fence
+) -> Result<(), VulkanError> { unsafe {
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &begin_info)
+        .context("vkBeginCommandBuffer", "buffer_address.commands")?;
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline.handle);
+    device.cmd_push_constants(
+        command_buffer, pipeline.layout_handle, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(push_constants)
+    );
+    device.cmd_dispatch(command_buffer, push_constants.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+    // Makes the shader writes available to the host once the fence signals
+    let memory_barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+    device.cmd_pipeline_barrier(
+        command_buffer, vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(), std::slice::from_ref(&memory_barrier), &[], &[]
+    );
+
+    device.end_command_buffer(command_buffer)
+        .context("vkEndCommandBuffer", "buffer_address.commands")?;
+
+    let submit_info = vk::SubmitInfo::builder()
+        .command_buffers(std::slice::from_ref(&command_buffer));
+    device.queue_submit(queue, std::slice::from_ref(&submit_info), fence)
+        .context("vkQueueSubmit", "graphics_queue")?;
+    return device.wait_for_fences(std::slice::from_ref(&fence), true, u64::MAX)
+        .context("vkWaitForFences", "buffer_address.fence");
+} }
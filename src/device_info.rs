@@ -0,0 +1,63 @@
+use std::panic;
+use winit::event_loop::EventLoop;
+use winit::window::{Window, WindowBuilder};
+use crate::vulkan_core::create_surface;
+use crate::vulkan_core::device_info::{query_device_info, DeviceInfoFormat};
+use crate::vulkan_core::error::VulkanError;
+use crate::vulkan_core::instance::InstanceBuilder;
+
+
+/// Prints the capabilities of every physical device. Without `with_surface` no window is created,
+/// so this also works on machines without a windowing system, minus the surface section.
+pub fn main(format: DeviceInfoFormat, with_surface: bool) -> Result<(), VulkanError> {
+    let entry = unsafe { ash::Entry::load().map_err(|e| VulkanError::Loading(e.to_string()))? };
+    let instance = InstanceBuilder::new()
+        .application_name("Device Info")
+        .surface_support(with_surface)
+        .optional_validation(false)
+        .build(&entry)?;
+
+    let window = match with_surface {
+        true => create_hidden_window(),
+        false => None
+    };
+    // Declared after the window, so the surface is dropped first
+    let surface_info = match &window {
+        Some((_, window)) => Some(create_surface(&instance.handle, window)?),
+        None => None
+    };
+
+    let report = query_device_info(&instance.handle, instance.api_version, surface_info.as_ref())?;
+
+    println!("{}", report.format(format));
+    return Ok(());
+}
+
+
+/// Without a display winit panics in `EventLoop::new` or fails to build the window.
+/// Either way the report goes on without the surface section instead of aborting.
+fn create_hidden_window() -> Option<(EventLoop<()>, Window)> {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let event_loop = panic::catch_unwind(EventLoop::new);
+    panic::set_hook(default_hook);
+
+    let event_loop = match event_loop {
+        Ok(event_loop) => event_loop,
+        Err(_) => {
+            println!("No display available, leaving out the surface section (pass --headless to skip the window)");
+            return None;
+        }
+    };
+    let window = WindowBuilder::new()
+        .with_title("Device Info")
+        .with_visible(false)
+        .build(&event_loop);
+    return match window {
+        Ok(window) => Some((event_loop, window)),
+        Err(error) => {
+            println!("Failed to create a window ({}), leaving out the surface section", error);
+            None
+        }
+    };
+}
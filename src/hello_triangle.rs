@@ -2,9 +2,14 @@ use std::path::Path;
 use std::ptr::{null, null_mut};
 use ash::vk;
 use crate::render_app;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
 use crate::vulkan_core::buffer_factory::{VulkanBuffer, VulkanBufferConfiguration};
+use crate::vulkan_core::memory_report::MemoryReportFormat;
+use crate::vulkan_core::memory_usage::MemoryUsage;
 use crate::vulkan_core::pipeline::{create_pipeline, GraphicsPipeline, GraphicsPipelineConfiguration, PushConstantsLayout};
+use crate::vulkan_core::debug::DebugMessage;
 use crate::vulkan_core::tools::read_shader_code;
+use crate::validation_capture::capture_validation_messages;
 use crate::vulkan_render_base::{FramePreparation, FrameSubmitData, VulkanRenderBase};
 
 
@@ -15,21 +20,47 @@ struct HelloTriangle {
 
 static mut HELLO_TRIANGLE: Option<HelloTriangle> = None;
 
-pub fn main() {
-    let render_app = render_app::create_app();
+pub fn main() -> Result<(), VulkanError> {
+    let mut render_app = render_app::create_app()?;
 
-    prepare_vulkan(&render_app.vulkan_base);
+    prepare_vulkan(&mut render_app.vulkan_base)?;
 
-    render_app.main_loop(record_command_buffer);
+    render_app.main_loop(record_command_buffer, destroy_vulkan);
+    return Ok(());
 }
 
-fn prepare_vulkan(vulkan_base: &VulkanRenderBase) {
+/// Prints the memory report after the last frame when `memory_report` is set.
+pub fn main_headless(frame_count: u32, memory_report: Option<MemoryReportFormat>) -> Result<(), VulkanError> {
+    let mut vulkan_base = render_app::create_headless_base()?;
+
+    prepare_vulkan(&mut vulkan_base)?;
+
+    let result = render_app::run_headless(&mut vulkan_base, record_command_buffer, frame_count);
+    if let Some(format) = memory_report {
+        println!("{}", vulkan_base.memory_report().format(format));
+    }
+    destroy_vulkan(&vulkan_base);
+    return result;
+}
+
+/// Renders headless under the validation layer and returns what it reported.
+pub fn capture_validation(frame_count: u32) -> Result<Vec<DebugMessage>, VulkanError> {
+    let extent = vk::Extent2D { width: 1024, height: 600 };
+    return capture_validation_messages(extent, |vulkan_base| {
+        prepare_vulkan(vulkan_base)?;
+        let result = render_app::run_headless(vulkan_base, record_command_buffer, frame_count);
+        destroy_vulkan(vulkan_base);
+        return result;
+    });
+}
+
+fn prepare_vulkan(vulkan_base: &mut VulkanRenderBase) -> Result<(), VulkanError> {
     // Pipeline creation
     let pipeline_config = GraphicsPipelineConfiguration {
         vertex_attributes: vec![],
-        vertex_shader_code: read_shader_code(Path::new("shaders/hello_triangle/vert.spv")),
-        fragment_shader_code: read_shader_code(Path::new("shaders/hello_triangle/frag.spv")),
-        color_format: vulkan_base.swapchain.color_format,
+        vertex_shader_code: read_shader_code(Path::new("shaders/hello_triangle/vert.spv"))?,
+        fragment_shader_code: read_shader_code(Path::new("shaders/hello_triangle/frag.spv"))?,
+        color_format: vulkan_base.render_target.color_format(),
         depth_format: vk::Format::D32_SFLOAT,
         set_layouts: Vec::new(),
         push_constants_layout: PushConstantsLayout {
@@ -47,24 +78,35 @@ fn prepare_vulkan(vulkan_base: &VulkanRenderBase) {
         depth_write: false,
     };
 
-    let pipeline = create_pipeline(&vulkan_base.device, &pipeline_config);
+    let pipeline = create_pipeline(&vulkan_base.device, &vulkan_base.debug_utils, &pipeline_config, "hello_triangle.pipeline")?;
 
     // Vertex Buffer creation
     let vertex_buffer_config = VulkanBufferConfiguration {
         size: 64,
-        memory_property_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        memory_usage: MemoryUsage::GpuOnly,
         buffer_usage: vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST
     };
-    let vertex_buffer = vulkan_base.create_buffer(&vertex_buffer_config);
+    let vertex_buffer = vulkan_base.create_buffer(&vertex_buffer_config, "hello_triangle.vertex_buffer")?;
+
+    // Same positions as the vertex shader, which doesn't read them yet
+    let vertex_positions: [f32; 6] = [-1.0, 1.0, 1.0, 1.0, 0.0, -1.0];
+    let vertex_data: Vec<u8> = vertex_positions.iter().flat_map(|value| value.to_ne_bytes()).collect();
+    vulkan_base.uploads.upload_buffer_now(&vertex_buffer, 0, &vertex_data)?;
 
     unsafe { HELLO_TRIANGLE = Some(HelloTriangle { pipeline, vertex_buffer }) };
+    return Ok(());
+}
+
+fn destroy_vulkan(_vulkan_base: &VulkanRenderBase) {
+    // The device has to be idle, run_headless and the main loop wait for it
+    unsafe { HELLO_TRIANGLE = None };
 }
 
-pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparation) -> FrameSubmitData {
+pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparation) -> Result<FrameSubmitData, VulkanError> {
     frame_process();
 
-    let width = vulkan_base.swapchain.extent.width;
-    let height = vulkan_base.swapchain.extent.height;
+    let width = vulkan_base.render_target.extent().width;
+    let height = vulkan_base.render_target.extent().height;
 
     let cmd_begin_info = vk::CommandBufferBeginInfo {
         s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
@@ -74,8 +116,8 @@ pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparat
     };
 
     let command_buffer = vulkan_base.command_buffers[vulkan_base.frame_in_flight_index as usize];
-    let swapchain_image = vulkan_base.swapchain.images[prep.image_index as usize];
-    let swapchain_image_view = vulkan_base.swapchain.image_views[prep.image_index as usize];
+    let swapchain_image = vulkan_base.render_target.image(prep.image_index);
+    let swapchain_image_view = vulkan_base.render_target.image_view(prep.image_index);
 
     let clear_color = vk::ClearValue { color: vk::ClearColorValue { float32: [0.2, 0.2, 0.2, 0.2] } };
     //let clear_depth = vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 0.0, stencil: 0 } };
@@ -84,7 +126,7 @@ pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparat
         s_type: vk::StructureType::RENDERING_ATTACHMENT_INFO,
         p_next: null_mut(),
         image_view: swapchain_image_view,
-        image_layout: vk::ImageLayout::ATTACHMENT_OPTIMAL,
+        image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
         resolve_mode: vk::ResolveModeFlags::NONE,
         resolve_image_view: vk::ImageView::null(),
         resolve_image_layout: vk::ImageLayout::UNDEFINED,
@@ -109,7 +151,8 @@ pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparat
     };
 
     unsafe {
-        vulkan_base.device.begin_command_buffer(command_buffer, &cmd_begin_info).expect("MEH");
+        vulkan_base.device.begin_command_buffer(command_buffer, &cmd_begin_info)
+            .context("vkBeginCommandBuffer", "frame command buffer")?;
 
         let subresource = vk::ImageSubresourceRange {
             aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -118,11 +161,13 @@ pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparat
             base_array_layer: 0,
             layer_count: 1,
         };
-        let swapchain_barrier_begin_render = vk::ImageMemoryBarrier {
-            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+        let swapchain_barrier_begin_render = vk::ImageMemoryBarrier2 {
+            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER_2,
             p_next: null_mut(),
-            src_access_mask: vk::AccessFlags::empty(),
-            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            src_stage_mask: vk::PipelineStageFlags2::TOP_OF_PIPE,
+            src_access_mask: vk::AccessFlags2::empty(),
+            dst_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            dst_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
             old_layout: vk::ImageLayout::UNDEFINED,
             new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
@@ -131,13 +176,12 @@ pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparat
             subresource_range: subresource,
         };
 
-        vulkan_base.device.cmd_pipeline_barrier(
-            command_buffer,
-            vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            vk::DependencyFlags::empty(), &[], &[], &[swapchain_barrier_begin_render]
-        );
+        let dependency_info = vk::DependencyInfo::builder()
+            .image_memory_barriers(std::slice::from_ref(&swapchain_barrier_begin_render));
+        vulkan_base.commands.cmd_pipeline_barrier2(command_buffer, &dependency_info);
 
-        vulkan_base.device.cmd_begin_rendering(command_buffer, &rendering_info);
+        let render_pass_label = vulkan_base.debug_utils.begin_label(command_buffer, "hello_triangle.render_pass");
+        vulkan_base.commands.cmd_begin_rendering(command_buffer, &rendering_info);
 
         let viewport = vk::Viewport {
             x: 0.0, y: 0.0,
@@ -158,33 +202,36 @@ pub fn record_command_buffer(vulkan_base: &VulkanRenderBase, prep: FramePreparat
             vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, &push_constants
         );
         vulkan_base.device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.handle], &[0]);
+        vulkan_base.debug_utils.insert_marker(command_buffer, "hello_triangle.draw");
         vulkan_base.device.cmd_draw(command_buffer, 3, 1, 0, 0);
 
-        vulkan_base.device.cmd_end_rendering(command_buffer);
+        vulkan_base.commands.cmd_end_rendering(command_buffer);
+        drop(render_pass_label);
 
-        let swapchain_barrier_begin_present = vk::ImageMemoryBarrier {
-            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+        let swapchain_barrier_begin_present = vk::ImageMemoryBarrier2 {
+            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER_2,
             p_next: null_mut(),
-            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-            dst_access_mask: vk::AccessFlags::empty(),
+            src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+            dst_access_mask: vk::AccessFlags2::empty(),
             old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            new_layout: vulkan_base.render_target.final_layout(),
             src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
             dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
             image: swapchain_image,
             subresource_range: subresource,
         };
 
-        vulkan_base.device.cmd_pipeline_barrier(
-            command_buffer,
-            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-            vk::DependencyFlags::empty(), &[], &[], &[swapchain_barrier_begin_present]
-        );
+        let dependency_info = vk::DependencyInfo::builder()
+            .image_memory_barriers(std::slice::from_ref(&swapchain_barrier_begin_present));
+        vulkan_base.commands.cmd_pipeline_barrier2(command_buffer, &dependency_info);
 
-        vulkan_base.device.end_command_buffer(command_buffer).expect("MEH");
+        vulkan_base.device.end_command_buffer(command_buffer)
+            .context("vkEndCommandBuffer", "frame command buffer")?;
     };
 
-    return FrameSubmitData { do_submit: prep.acquire_successful, image_index: prep.image_index };
+    return Ok(FrameSubmitData { do_submit: prep.acquire_successful, image_index: prep.image_index });
 }
 
 
@@ -3,11 +3,74 @@ mod render_app;
 mod vulkan_render_base;
 mod math;
 mod hello_triangle;
+mod buffer_device_address;
+mod device_info;
+mod validation_capture;
 
+use crate::vulkan_core::device_info::DeviceInfoFormat;
+use crate::vulkan_core::memory_report::MemoryReportFormat;
+
+
+const HEADLESS_FRAME_COUNT: u32 = 60;
+/// `--validate` exits with this when it can't run, tests/validation.rs skips on it.
+const VALIDATION_UNAVAILABLE_EXIT_CODE: i32 = 77;
 
 fn main() {
     //let b = include_bytes!("shaders/triangle.vert");
     //println!("{}", String::from_utf8_lossy(b));
 
-    hello_triangle::main();
+    let args: Vec<String> = std::env::args().collect();
+    let has_arg = |name: &str| args.iter().any(|arg| arg == name);
+
+    // Exits with 1 when the validation layer reports an error, for CI
+    if has_arg("--validate") {
+        let error_count = match hello_triangle::capture_validation(HEADLESS_FRAME_COUNT) {
+            Ok(messages) => validation_capture::report_validation_messages(&messages),
+            Err(error) if validation_capture::is_validation_unavailable(&error) => {
+                println!("Validation unavailable: {}", error);
+                std::process::exit(VALIDATION_UNAVAILABLE_EXIT_CODE);
+            }
+            Err(error) => {
+                println!("Fatal Vulkan error: {}", error);
+                std::process::exit(1);
+            }
+        };
+        std::process::exit(if error_count == 0 { 0 } else { 1 });
+    }
+
+    if has_arg("--buffer-address") {
+        if let Err(error) = buffer_device_address::main() {
+            println!("Fatal Vulkan error: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let headless = has_arg("--headless");
+    let json = has_arg("--json");
+    let result = match (has_arg("--device-info"), headless) {
+        (true, _) => {
+            // --device-info [--json] [--headless]
+            let format = match json {
+                true => DeviceInfoFormat::Json,
+                false => DeviceInfoFormat::Text
+            };
+            device_info::main(format, !headless)
+        }
+        (false, true) => {
+            // --headless [--memory-report [--json]]
+            let memory_report = match (has_arg("--memory-report"), json) {
+                (false, _) => None,
+                (true, false) => Some(MemoryReportFormat::Text),
+                (true, true) => Some(MemoryReportFormat::Json)
+            };
+            hello_triangle::main_headless(HEADLESS_FRAME_COUNT, memory_report)
+        }
+        (false, false) => hello_triangle::main()
+    };
+
+    if let Err(error) = result {
+        println!("Fatal Vulkan error: {}", error);
+        std::process::exit(1);
+    }
 }
\ No newline at end of file
@@ -2,10 +2,15 @@ use winit::event::Event;
 use winit::event::Event::WindowEvent;
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
-use crate::vulkan_render_base::{FramePreparation, FrameSubmitData, initialize_vulkan, VulkanRenderBase};
+use crate::vulkan_core::instance::InstanceBuilder;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use ash::vk;
+use crate::vulkan_render_base::{FramePreparation, FrameSubmitData, initialize_vulkan, initialize_vulkan_headless, VulkanRenderBase, VulkanRenderBaseConfiguration};
 
 
-type RecordCommandBufferFunc = fn(vulkan_base: &VulkanRenderBase, frame_preparation: FramePreparation) -> FrameSubmitData;
+type RecordCommandBufferFunc = fn(vulkan_base: &VulkanRenderBase, frame_preparation: FramePreparation) -> Result<FrameSubmitData, VulkanError>;
+/// Releases the app's own Vulkan objects. Called once the device is idle, before the base is torn down.
+type DestroyFunc = fn(vulkan_base: &VulkanRenderBase);
 
 pub struct RenderApp {
     pub event_loop: EventLoop<()>,
@@ -14,23 +19,35 @@ pub struct RenderApp {
 }
 
 impl RenderApp {
-    pub fn main_loop(mut self, record_cmd_function: RecordCommandBufferFunc) {
+    pub fn main_loop(self, record_cmd_function: RecordCommandBufferFunc, destroy_function: DestroyFunc) {
+        let window = self.window;
+        // Taken on LoopDestroyed, so the base is gone before the window
+        let mut vulkan_base = Some(self.vulkan_base);
+
         self.event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Poll;
 
             match event {
                 Event::MainEventsCleared => {
-                    self.window.request_redraw()
+                    window.request_redraw()
                 }
 
                 Event::RedrawRequested { .. } => {
-                    let prep = self.vulkan_base.prepare_frame();
-                    let submit = record_cmd_function(&self.vulkan_base, prep);
-                    self.vulkan_base.submit_frame(submit);
+                    let Some(vulkan_base) = vulkan_base.as_mut() else { return; };
+                    if let Err(error) = render_frame(vulkan_base, record_cmd_function) {
+                        println!("Frame failed: {}", error);
+                        *control_flow = ControlFlow::Exit;
+                    }
                 }
 
                 Event::LoopDestroyed => {
-                    unsafe { self.vulkan_base.device.device_wait_idle().expect("MEH") };
+                    if let Some(vulkan_base) = vulkan_base.take() {
+                        let wait_result = unsafe { vulkan_base.device.device_wait_idle() };
+                        if let Err(error) = wait_result.context("vkDeviceWaitIdle", "device") {
+                            println!("{}", error);
+                        }
+                        destroy_function(&vulkan_base);
+                    }
                 }
 
                 WindowEvent { event, .. } => match event {
@@ -54,7 +71,25 @@ impl RenderApp {
     }
 }
 
-pub fn create_app() -> RenderApp {
+/// Renders a fixed number of frames into the offscreen targets, then waits for the device.
+pub fn run_headless(
+    vulkan_base: &mut VulkanRenderBase, record_cmd_function: RecordCommandBufferFunc, frame_count: u32
+) -> Result<(), VulkanError> {
+    for _frame in 0..frame_count {
+        render_frame(vulkan_base, record_cmd_function)?;
+    }
+
+    unsafe { vulkan_base.device.device_wait_idle().context("vkDeviceWaitIdle", "device")? };
+    return Ok(());
+}
+
+fn render_frame(vulkan_base: &mut VulkanRenderBase, record_cmd_function: RecordCommandBufferFunc) -> Result<(), VulkanError> {
+    let prep = vulkan_base.prepare_frame()?;
+    let submit = record_cmd_function(vulkan_base, prep)?;
+    return vulkan_base.submit_frame(submit);
+}
+
+pub fn create_app() -> Result<RenderApp, VulkanError> {
     let event_loop = EventLoop::new();
     let winit_window: winit::window::Window = WindowBuilder::new()
         .with_title("Vulkan Stuff")
@@ -64,7 +99,18 @@ pub fn create_app() -> RenderApp {
         .build(&event_loop).unwrap();
 
     println!("PID: {}", std::process::id());
-    let base = initialize_vulkan(&winit_window, 3);
+    let config = VulkanRenderBaseConfiguration::default();
+    let base = initialize_vulkan(&winit_window, &config)?;
+
+    return Ok(RenderApp { event_loop, window: winit_window, vulkan_base: base });
+}
+
+pub fn create_headless_base() -> Result<VulkanRenderBase, VulkanError> {
+    let extent = vk::Extent2D { width: 1024, height: 600 };
+    let config = VulkanRenderBaseConfiguration {
+        instance_builder: InstanceBuilder::new().surface_support(false),
+        ..Default::default()
+    };
 
-    return RenderApp { event_loop, window: winit_window, vulkan_base: base };
+    return initialize_vulkan_headless(extent, &config);
 }
\ No newline at end of file
@@ -0,0 +1,60 @@
+use ash::vk;
+use crate::vulkan_core::debug::{CollectingSink, DebugMessage, DebugMessengerConfiguration};
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::instance::InstanceBuilder;
+use crate::vulkan_render_base::{initialize_vulkan_headless, VulkanRenderBase, VulkanRenderBaseConfiguration};
+
+
+/// Runs `scenario` on a headless base with the Khronos validation layer enabled and returns every
+/// message the layer reported while the scenario ran. The layer and lavapipe are enough, no GPU needed.
+///
+/// Messages of every ID are kept, the repeat limit of the regular messenger would hide regressions.
+/// The base is torn down before returning, so objects the scenario leaked show up as well.
+pub fn capture_validation_messages<F>(extent: vk::Extent2D, scenario: F) -> Result<Vec<DebugMessage>, VulkanError>
+    where F: FnOnce(&mut VulkanRenderBase) -> Result<(), VulkanError>
+{
+    let collector = CollectingSink::new();
+    let config = VulkanRenderBaseConfiguration {
+        instance_builder: InstanceBuilder::new()
+            .application_name("Validation Capture")
+            .surface_support(false)
+            .validation(true),
+        debug_messenger: DebugMessengerConfiguration {
+            repeat_limit: None,
+            sink: collector.clone(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let mut vulkan_base = initialize_vulkan_headless(extent, &config)?;
+    let scenario_result = scenario(&mut vulkan_base);
+    // Some messages only arrive once the submitted work completes
+    let wait_result = unsafe { vulkan_base.device.device_wait_idle().context("vkDeviceWaitIdle", "device") };
+    drop(vulkan_base);
+    scenario_result?;
+    wait_result?;
+
+    return Ok(collector.take());
+}
+
+/// The loader, a driver or the validation layer is missing, so there is nothing to validate with.
+/// A device that exists but gets rejected is a failure, not a reason to skip.
+pub fn is_validation_unavailable(error: &VulkanError) -> bool {
+    return match error {
+        VulkanError::Loading(_) | VulkanError::MissingInstanceLayer(_) | VulkanError::NoPhysicalDevice => true,
+        _ => error.result() == Some(vk::Result::ERROR_INCOMPATIBLE_DRIVER)
+    };
+}
+
+/// Prints the messages and returns how many of them are errors.
+pub fn report_validation_messages(messages: &[DebugMessage]) -> usize {
+    for message in messages {
+        let id = message.message_id_name.as_deref().unwrap_or("<no id>");
+        println!("[{:?}] {}: {}", message.severity, id, message.message);
+    }
+
+    let error_count = messages.iter().filter(|m| m.is_error()).count();
+    println!("Validation: {} messages, {} errors", messages.len(), error_count);
+    return error_count;
+}
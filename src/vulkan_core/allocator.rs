@@ -0,0 +1,759 @@
+use std::collections::BTreeMap;
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
+use ash::vk;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::memory_usage::{rank_memory_types, MemoryUsage};
+use crate::vulkan_core::tools::align_up;
+
+
+pub struct MemoryAllocatorConfiguration {
+    /// Size of the device memory blocks that get sub-allocated. Small heaps get smaller blocks.
+    pub block_size: vk::DeviceSize,
+    /// Resources larger than this get their own vkAllocateMemory.
+    pub dedicated_threshold: vk::DeviceSize
+}
+
+impl Default for MemoryAllocatorConfiguration {
+    fn default() -> Self {
+        return MemoryAllocatorConfiguration {
+            block_size: 64 * 1024 * 1024,
+            dedicated_threshold: 32 * 1024 * 1024
+        };
+    }
+}
+
+
+/// Buffers and linear images may not share a `bufferImageGranularity` page with optimal images.
+/// Both kinds get their own blocks, so neighbours never have to be checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceTiling {
+    Linear,
+    Optimal
+}
+
+/// What an allocation backs, for the memory report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResourceCategory {
+    Buffer,
+    Image,
+    /// Buffers in `MemoryUsage::CpuOnly` memory, which only exist to be copied from or to.
+    Staging
+}
+
+/// One live allocation, as listed by `MemoryAllocator::resources`.
+#[derive(Clone, Debug)]
+pub struct ResourceRecord {
+    pub name: String,
+    pub category: ResourceCategory,
+    pub size: vk::DeviceSize,
+    pub memory_type_index: u32,
+    pub dedicated: bool
+}
+
+#[derive(Clone, Copy)]
+enum DedicatedTarget {
+    Buffer(vk::Buffer),
+    Image(vk::Image)
+}
+
+/// What `allocate_for_buffer` and `allocate_for_image` found out about the resource.
+#[derive(Clone, Copy)]
+struct ResourceRequirements {
+    memory: vk::MemoryRequirements,
+    tiling: ResourceTiling,
+    prefers_dedicated: bool,
+    dedicated_target: DedicatedTarget
+}
+
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemoryTypeStats {
+    pub block_count: u32,
+    pub dedicated_allocation_count: u32,
+    /// Live allocations, dedicated ones included.
+    pub allocation_count: u32,
+    /// Everything taken from the device, blocks and dedicated allocations.
+    pub allocated_bytes: vk::DeviceSize,
+    pub used_bytes: vk::DeviceSize,
+    /// Unused space inside blocks.
+    pub free_bytes: vk::DeviceSize,
+    pub largest_free_range: vk::DeviceSize
+}
+
+impl MemoryTypeStats {
+    /// 0 when all free space is one range, close to 1 when it is scattered in small pieces.
+    pub fn fragmentation(&self) -> f32 {
+        if self.free_bytes == 0 { return 0.0; }
+        return 1.0 - self.largest_free_range as f32 / self.free_bytes as f32;
+    }
+
+    fn add(&mut self, other: &MemoryTypeStats) {
+        self.block_count += other.block_count;
+        self.dedicated_allocation_count += other.dedicated_allocation_count;
+        self.allocation_count += other.allocation_count;
+        self.allocated_bytes += other.allocated_bytes;
+        self.used_bytes += other.used_bytes;
+        self.free_bytes += other.free_bytes;
+        self.largest_free_range = self.largest_free_range.max(other.largest_free_range);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AllocatorStats {
+    /// Indexed by memory type.
+    pub memory_types: Vec<MemoryTypeStats>,
+    pub total: MemoryTypeStats,
+    /// Live vkAllocateMemory allocations, to compare against `maxMemoryAllocationCount`.
+    pub device_allocation_count: u32
+}
+
+
+/// Host address of a mapped allocation. Only ever handed out, the allocator doesn't touch the memory.
+#[derive(Clone, Copy)]
+struct MappedPointer(*mut c_void);
+
+unsafe impl Send for MappedPointer {}
+unsafe impl Sync for MappedPointer {}
+
+impl MappedPointer {
+    fn offset(&self, offset: vk::DeviceSize) -> MappedPointer {
+        if self.0.is_null() { return *self; }
+        return MappedPointer(unsafe { self.0.cast::<u8>().add(offset as usize).cast() });
+    }
+}
+
+
+#[derive(Clone, Copy)]
+struct FreeRange {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize
+}
+
+struct MemoryBlock {
+    id: u64,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    mapped: MappedPointer,
+    /// Sorted by offset, adjacent ranges are always merged.
+    free_ranges: Vec<FreeRange>,
+    allocation_count: u32,
+    used_bytes: vk::DeviceSize
+}
+
+impl MemoryBlock {
+    /// Best fit: the smallest free range that still fits after aligning the offset.
+    fn find_range(&self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<(usize, vk::DeviceSize)> {
+        return self.free_ranges.iter().enumerate()
+            .filter_map(|(i, range)| {
+                let offset = align_up(range.offset, alignment);
+                let fits = offset + size <= range.offset + range.size;
+                return fits.then_some((i, offset, range.size));
+            })
+            .min_by_key(|(_, _, range_size)| *range_size)
+            .map(|(i, offset, _)| (i, offset));
+    }
+
+    fn take(&mut self, range_index: usize, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let range = self.free_ranges[range_index];
+        let mut remainder = Vec::with_capacity(2);
+        if offset > range.offset {
+            remainder.push(FreeRange { offset: range.offset, size: offset - range.offset });
+        }
+        let end = offset + size;
+        let range_end = range.offset + range.size;
+        if end < range_end {
+            remainder.push(FreeRange { offset: end, size: range_end - end });
+        }
+        self.free_ranges.splice(range_index..range_index + 1, remainder);
+        self.allocation_count += 1;
+        self.used_bytes += size;
+    }
+
+    fn release(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let index = self.free_ranges.partition_point(|range| range.offset < offset);
+        self.free_ranges.insert(index, FreeRange { offset, size });
+
+        if index + 1 < self.free_ranges.len() && offset + size == self.free_ranges[index + 1].offset {
+            self.free_ranges[index].size += self.free_ranges[index + 1].size;
+            self.free_ranges.remove(index + 1);
+        }
+        if index > 0 {
+            let previous = self.free_ranges[index - 1];
+            if previous.offset + previous.size == offset {
+                self.free_ranges[index - 1].size += self.free_ranges[index].size;
+                self.free_ranges.remove(index);
+            }
+        }
+
+        self.allocation_count -= 1;
+        self.used_bytes -= size;
+    }
+
+    fn largest_free_range(&self) -> vk::DeviceSize {
+        return self.free_ranges.iter().map(|range| range.size).max().unwrap_or(0);
+    }
+}
+
+
+/// Takes the range from the first block that fits it and returns that block and the offset.
+/// None means every block is too full and a new one has to be allocated.
+fn take_from_pool(
+    pool: &mut [MemoryBlock], size: vk::DeviceSize, alignment: vk::DeviceSize
+) -> Option<(&MemoryBlock, vk::DeviceSize)> {
+    let (block, range_index, offset) = pool.iter_mut()
+        .find_map(|block| block.find_range(size, alignment).map(|(range_index, offset)| (block, range_index, offset)))?;
+    block.take(range_index, offset, size);
+    return Some((block, offset));
+}
+
+/// Returns the block once it is empty and the pool has another empty one, the caller frees its memory.
+/// One empty block stays around so a resource that gets recreated every frame doesn't hit the driver.
+fn release_to_pool(
+    pool: &mut Vec<MemoryBlock>, block_id: u64, offset: vk::DeviceSize, size: vk::DeviceSize
+) -> Option<MemoryBlock> {
+    let block_index = pool.iter().position(|block| block.id == block_id)?;
+    pool[block_index].release(offset, size);
+
+    let empty_blocks = pool.iter().filter(|block| block.allocation_count == 0).count();
+    if pool[block_index].allocation_count == 0 && empty_blocks > 1 {
+        return Some(pool.remove(block_index));
+    }
+    return None;
+}
+
+
+#[derive(Default)]
+struct DedicatedStats {
+    count: u32,
+    bytes: vk::DeviceSize
+}
+
+struct AllocatorState {
+    /// One list per memory type and tiling, see `pool_index`.
+    pools: Vec<Vec<MemoryBlock>>,
+    dedicated: Vec<DedicatedStats>,
+    /// Keyed by allocation id, in allocation order.
+    resources: BTreeMap<u64, ResourceRecord>,
+    next_block_id: u64,
+    next_allocation_id: u64
+}
+
+
+/// Hands out ranges of large device memory blocks instead of one vkAllocateMemory per resource.
+pub struct MemoryAllocator {
+    device: SharedDevice,
+    debug_utils: DebugUtilsDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    block_sizes: Vec<vk::DeviceSize>,
+    dedicated_threshold: vk::DeviceSize,
+    non_coherent_atom_size: vk::DeviceSize,
+    /// Every allocation gets `MemoryAllocateFlags::DEVICE_ADDRESS`, so any buffer in a shared block can have an address.
+    buffer_device_address: bool,
+    state: Mutex<AllocatorState>
+}
+
+pub type SharedAllocator = Arc<MemoryAllocator>;
+
+enum AllocationKind {
+    Block { pool_index: usize, block_id: u64 },
+    Dedicated
+}
+
+/// A range of device memory. Returns itself to the allocator when dropped,
+/// so it has to outlive the resource bound to it.
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub memory_type_index: u32,
+    id: u64,
+    mapped: MappedPointer,
+    /// Size of the whole `memory` object, flushes must not go past it.
+    memory_size: vk::DeviceSize,
+    kind: AllocationKind,
+    allocator: SharedAllocator
+}
+
+impl Allocation {
+    /// Host-visible memory stays mapped for the lifetime of its block, this points at `offset`.
+    pub fn mapped_ptr(&self) -> Option<*mut c_void> {
+        if self.mapped.0.is_null() { return None; }
+        return Some(self.mapped.0);
+    }
+
+    pub fn is_dedicated(&self) -> bool {
+        return matches!(self.kind, AllocationKind::Dedicated);
+    }
+
+    pub fn memory_property_flags(&self) -> vk::MemoryPropertyFlags {
+        return self.allocator.memory_properties.memory_types[self.memory_type_index as usize].property_flags;
+    }
+
+    pub fn is_coherent(&self) -> bool {
+        return self.memory_property_flags().contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+    }
+
+    fn atom_aligned_range(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> vk::MappedMemoryRange {
+        let atom_size = self.allocator.non_coherent_atom_size;
+        let start = (self.offset + offset) / atom_size * atom_size;
+        let end = align_up(self.offset + offset + size, atom_size);
+        let range_size = match end >= self.memory_size {
+            true => vk::WHOLE_SIZE,
+            false => end - start
+        };
+
+        return vk::MappedMemoryRange::builder()
+            .memory(self.memory)
+            .offset(start)
+            .size(range_size)
+            .build();
+    }
+
+    /// Makes host writes to `offset..offset + size` of this allocation visible to the device.
+    /// Does nothing for coherent memory, otherwise the range grows to whole `nonCoherentAtomSize` atoms.
+    pub fn flush(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<(), VulkanError> {
+        if self.is_coherent() || size == 0 { return Ok(()); }
+
+        let range = self.atom_aligned_range(offset, size);
+        return unsafe {
+            self.allocator.device.flush_mapped_memory_ranges(std::slice::from_ref(&range))
+                .context("vkFlushMappedMemoryRanges", "allocation")
+        };
+    }
+
+    /// Makes device writes to `offset..offset + size` visible to the host, the counterpart of `flush`.
+    pub fn invalidate(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<(), VulkanError> {
+        if self.is_coherent() || size == 0 { return Ok(()); }
+
+        let range = self.atom_aligned_range(offset, size);
+        return unsafe {
+            self.allocator.device.invalidate_mapped_memory_ranges(std::slice::from_ref(&range))
+                .context("vkInvalidateMappedMemoryRanges", "allocation")
+        };
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.allocator.free(self);
+    }
+}
+
+
+pub fn create_allocator(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: vk::DeviceSize,
+    buffer_device_address: bool,
+    config: &MemoryAllocatorConfiguration
+) -> SharedAllocator {
+    let memory_types = &memory_properties.memory_types[..memory_properties.memory_type_count as usize];
+    // A few blocks must not eat a small heap, like the 256 MiB host-visible device-local one
+    let block_sizes = memory_types.iter()
+        .map(|memory_type| {
+            let heap_size = memory_properties.memory_heaps[memory_type.heap_index as usize].size;
+            return config.block_size.min(heap_size / 8);
+        })
+        .collect();
+
+    let state = AllocatorState {
+        pools: (0..memory_types.len() * 2).map(|_| Vec::new()).collect(),
+        dedicated: (0..memory_types.len()).map(|_| DedicatedStats::default()).collect(),
+        resources: BTreeMap::new(),
+        next_block_id: 0,
+        next_allocation_id: 0
+    };
+
+    return Arc::new(MemoryAllocator {
+        device: device.clone(),
+        debug_utils: debug_utils.clone(),
+        memory_properties,
+        block_sizes,
+        dedicated_threshold: config.dedicated_threshold,
+        non_coherent_atom_size: non_coherent_atom_size.max(1),
+        buffer_device_address,
+        state: Mutex::new(state)
+    });
+}
+
+
+fn pool_index(memory_type_index: u32, tiling: ResourceTiling) -> usize {
+    return memory_type_index as usize * 2 + (tiling == ResourceTiling::Optimal) as usize;
+}
+
+
+impl MemoryAllocator {
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        return &self.memory_properties;
+    }
+
+    /// Whether the bufferDeviceAddress feature is enabled, required for SHADER_DEVICE_ADDRESS buffers.
+    pub fn buffer_device_address(&self) -> bool {
+        return self.buffer_device_address;
+    }
+
+    /// The caller binds the buffer to `memory` at `offset`.
+    pub fn allocate_for_buffer(
+        self: &Arc<Self>, buffer: vk::Buffer, usage: MemoryUsage, name: &str
+    ) -> Result<Allocation, VulkanError> {
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements2 = vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        let requirements_info = vk::BufferMemoryRequirementsInfo2::builder().buffer(buffer);
+        unsafe { self.device.get_buffer_memory_requirements2(&requirements_info, &mut requirements2) };
+        let requirements = ResourceRequirements {
+            memory: requirements2.memory_requirements,
+            tiling: ResourceTiling::Linear,
+            prefers_dedicated: dedicated_requirements.prefers_dedicated_allocation == vk::TRUE ||
+                dedicated_requirements.requires_dedicated_allocation == vk::TRUE,
+            dedicated_target: DedicatedTarget::Buffer(buffer)
+        };
+        let category = match usage {
+            MemoryUsage::CpuOnly => ResourceCategory::Staging,
+            _ => ResourceCategory::Buffer
+        };
+
+        return self.allocate(&requirements, usage, category, name);
+    }
+
+    /// The caller binds the image to `memory` at `offset`.
+    pub fn allocate_for_image(
+        self: &Arc<Self>, image: vk::Image, tiling: ResourceTiling, usage: MemoryUsage, name: &str
+    ) -> Result<Allocation, VulkanError> {
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements2 = vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        let requirements_info = vk::ImageMemoryRequirementsInfo2::builder().image(image);
+        unsafe { self.device.get_image_memory_requirements2(&requirements_info, &mut requirements2) };
+        let requirements = ResourceRequirements {
+            memory: requirements2.memory_requirements,
+            tiling,
+            prefers_dedicated: dedicated_requirements.prefers_dedicated_allocation == vk::TRUE ||
+                dedicated_requirements.requires_dedicated_allocation == vk::TRUE,
+            dedicated_target: DedicatedTarget::Image(image)
+        };
+
+        return self.allocate(&requirements, usage, ResourceCategory::Image, name);
+    }
+
+    /// Falls back to the next best memory type when one runs out of memory.
+    fn allocate(
+        self: &Arc<Self>, requirements: &ResourceRequirements, usage: MemoryUsage, category: ResourceCategory, name: &str
+    ) -> Result<Allocation, VulkanError> {
+        let type_bits = requirements.memory.memory_type_bits;
+        let memory_types = rank_memory_types(&self.memory_properties, type_bits, usage, requirements.memory.size);
+        let mut last_error = VulkanError::NoSuitableMemoryType { type_bits, usage };
+        for memory_type_index in memory_types {
+            match self.allocate_from_type(requirements, memory_type_index, name) {
+                Ok(allocation) => return Ok(self.register(allocation, category, name)),
+                Err(error) if error.is_out_of_memory() => last_error = error,
+                Err(error) => return Err(error)
+            }
+        }
+
+        return Err(last_error);
+    }
+
+    fn register(&self, mut allocation: Allocation, category: ResourceCategory, name: &str) -> Allocation {
+        let mut state = self.state.lock().unwrap();
+        allocation.id = state.next_allocation_id;
+        state.next_allocation_id += 1;
+        state.resources.insert(allocation.id, ResourceRecord {
+            name: name.to_string(),
+            category,
+            size: allocation.size,
+            memory_type_index: allocation.memory_type_index,
+            dedicated: allocation.is_dedicated()
+        });
+        return allocation;
+    }
+
+    fn allocate_from_type(
+        self: &Arc<Self>, requirements: &ResourceRequirements, memory_type_index: u32, name: &str
+    ) -> Result<Allocation, VulkanError> {
+        let block_size = self.block_sizes[memory_type_index as usize];
+        let size = requirements.memory.size;
+
+        if requirements.prefers_dedicated || size > self.dedicated_threshold || size > block_size {
+            return self.allocate_dedicated(size, memory_type_index, requirements.dedicated_target, name);
+        }
+
+        let pool_index = pool_index(memory_type_index, requirements.tiling);
+        let mut state = self.state.lock().unwrap();
+
+        if let Some((block, offset)) = take_from_pool(&mut state.pools[pool_index], size, requirements.memory.alignment) {
+            return Ok(Allocation {
+                memory: block.memory,
+                offset,
+                size,
+                memory_type_index,
+                id: 0,
+                mapped: block.mapped.offset(offset),
+                memory_size: block.size,
+                kind: AllocationKind::Block { pool_index, block_id: block.id },
+                allocator: self.clone()
+            });
+        }
+
+        let block_id = state.next_block_id;
+        state.next_block_id += 1;
+        let block_name = format!("allocator.type[{}].block[{}]", memory_type_index, block_id);
+        let (memory, mapped) = self.allocate_device_memory(block_size, memory_type_index, None, &block_name)?;
+        let mut block = MemoryBlock {
+            id: block_id,
+            memory,
+            size: block_size,
+            mapped,
+            free_ranges: vec![FreeRange { offset: 0, size: block_size }],
+            allocation_count: 0,
+            used_bytes: 0
+        };
+        block.take(0, 0, size);
+        state.pools[pool_index].push(block);
+
+        return Ok(Allocation {
+            memory,
+            offset: 0,
+            size,
+            memory_type_index,
+            id: 0,
+            mapped,
+            memory_size: block_size,
+            kind: AllocationKind::Block { pool_index, block_id },
+            allocator: self.clone()
+        });
+    }
+
+    fn allocate_dedicated(
+        self: &Arc<Self>, size: vk::DeviceSize, memory_type_index: u32, dedicated_target: DedicatedTarget, name: &str
+    ) -> Result<Allocation, VulkanError> {
+        let (memory, mapped) = self.allocate_device_memory(
+            size, memory_type_index, Some(dedicated_target), &format!("{}.memory", name)
+        )?;
+
+        let mut state = self.state.lock().unwrap();
+        let dedicated = &mut state.dedicated[memory_type_index as usize];
+        dedicated.count += 1;
+        dedicated.bytes += size;
+
+        return Ok(Allocation {
+            memory,
+            offset: 0,
+            size,
+            memory_type_index,
+            id: 0,
+            mapped,
+            memory_size: size,
+            kind: AllocationKind::Dedicated,
+            allocator: self.clone()
+        });
+    }
+
+    /// Host-visible memory gets mapped right away and stays mapped until it is freed.
+    fn allocate_device_memory(
+        &self, size: vk::DeviceSize, memory_type_index: u32, dedicated_target: Option<DedicatedTarget>, name: &str
+    ) -> Result<(vk::DeviceMemory, MappedPointer), VulkanError> { unsafe {
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder();
+        match dedicated_target {
+            Some(DedicatedTarget::Buffer(buffer)) => dedicated_info = dedicated_info.buffer(buffer),
+            Some(DedicatedTarget::Image(image)) => dedicated_info = dedicated_info.image(image),
+            None => {}
+        }
+
+        let mut flags_info = vk::MemoryAllocateFlagsInfo::builder()
+            .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+
+        let mut alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+        if dedicated_target.is_some() {
+            alloc_info = alloc_info.push_next(&mut dedicated_info);
+        }
+        if self.buffer_device_address {
+            alloc_info = alloc_info.push_next(&mut flags_info);
+        }
+
+        let memory = self.device.allocate_memory(&alloc_info, None)
+            .context("vkAllocateMemory", name)?;
+        self.debug_utils.set_name(memory, name);
+
+        let property_flags = self.memory_properties.memory_types[memory_type_index as usize].property_flags;
+        let mut mapped = MappedPointer(std::ptr::null_mut());
+        if property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            let map_result = self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .context("vkMapMemory", name);
+            match map_result {
+                Ok(pointer) => mapped = MappedPointer(pointer),
+                Err(error) => {
+                    self.device.free_memory(memory, None);
+                    return Err(error);
+                }
+            }
+        }
+
+        return Ok((memory, mapped));
+    } }
+
+    fn free(&self, allocation: &Allocation) {
+        let mut state = self.state.lock().unwrap();
+        state.resources.remove(&allocation.id);
+
+        let (pool_index, block_id) = match allocation.kind {
+            AllocationKind::Dedicated => {
+                let dedicated = &mut state.dedicated[allocation.memory_type_index as usize];
+                dedicated.count -= 1;
+                dedicated.bytes -= allocation.size;
+                unsafe { self.device.free_memory(allocation.memory, None) };
+                return;
+            }
+            AllocationKind::Block { pool_index, block_id } => (pool_index, block_id)
+        };
+
+        if let Some(block) = release_to_pool(&mut state.pools[pool_index], block_id, allocation.offset, allocation.size) {
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+
+    /// Every live allocation, oldest first.
+    pub fn resources(&self) -> Vec<ResourceRecord> {
+        return self.state.lock().unwrap().resources.values().cloned().collect();
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let state = self.state.lock().unwrap();
+        let mut memory_types = vec![MemoryTypeStats::default(); self.memory_properties.memory_type_count as usize];
+
+        for (pool_index, pool) in state.pools.iter().enumerate() {
+            let stats = &mut memory_types[pool_index / 2];
+            for block in pool {
+                stats.block_count += 1;
+                stats.allocation_count += block.allocation_count;
+                stats.allocated_bytes += block.size;
+                stats.used_bytes += block.used_bytes;
+                stats.free_bytes += block.size - block.used_bytes;
+                stats.largest_free_range = stats.largest_free_range.max(block.largest_free_range());
+            }
+        }
+        for (memory_type_index, dedicated) in state.dedicated.iter().enumerate() {
+            let stats = &mut memory_types[memory_type_index];
+            stats.dedicated_allocation_count += dedicated.count;
+            stats.allocation_count += dedicated.count;
+            stats.allocated_bytes += dedicated.bytes;
+            stats.used_bytes += dedicated.bytes;
+        }
+
+        let mut total = MemoryTypeStats::default();
+        for stats in &memory_types {
+            total.add(stats);
+        }
+        let device_allocation_count = total.block_count + total.dedicated_allocation_count;
+
+        return AllocatorStats { memory_types, total, device_allocation_count };
+    }
+}
+
+impl Drop for MemoryAllocator {
+    /// Every allocation holds the allocator, so only empty blocks are left here.
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        for block in state.pools.iter().flatten() {
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_block(id: u64, size: vk::DeviceSize) -> MemoryBlock {
+        return MemoryBlock {
+            id,
+            memory: vk::DeviceMemory::null(),
+            size,
+            mapped: MappedPointer(std::ptr::null_mut()),
+            free_ranges: vec![FreeRange { offset: 0, size }],
+            allocation_count: 0,
+            used_bytes: 0
+        };
+    }
+
+    fn free_ranges(block: &MemoryBlock) -> Vec<(vk::DeviceSize, vk::DeviceSize)> {
+        return block.free_ranges.iter().map(|range| (range.offset, range.size)).collect();
+    }
+
+    #[test]
+    fn aligned_allocation_leaves_a_front_gap() {
+        let mut pool = vec![empty_block(0, 1024)];
+        let (_, first) = take_from_pool(&mut pool, 10, 1).unwrap();
+        let (_, second) = take_from_pool(&mut pool, 64, 256).unwrap();
+
+        assert_eq!((first, second), (0, 256));
+        assert_eq!(free_ranges(&pool[0]), vec![(10, 246), (320, 704)]);
+        assert_eq!((pool[0].allocation_count, pool[0].used_bytes), (2, 74));
+    }
+
+    #[test]
+    fn best_fit_picks_the_smallest_range() {
+        let mut block = empty_block(0, 1024);
+        block.free_ranges = vec![
+            FreeRange { offset: 4, size: 200 },
+            FreeRange { offset: 300, size: 100 },
+            FreeRange { offset: 500, size: 524 }
+        ];
+
+        assert_eq!(block.find_range(100, 4), Some((1, 300)));
+        // Aligning to 256 pushes the offset past the end of the two smaller ranges
+        assert_eq!(block.find_range(100, 256), Some((2, 512)));
+        assert_eq!(block.find_range(600, 1), None);
+    }
+
+    #[test]
+    fn freeing_in_any_order_merges_into_one_range() {
+        let orders = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+        for order in orders {
+            let mut pool = vec![empty_block(0, 300)];
+            let offsets: Vec<vk::DeviceSize> = (0..3).map(|_| take_from_pool(&mut pool, 100, 1).unwrap().1).collect();
+            assert_eq!(offsets, vec![0, 100, 200]);
+            assert!(pool[0].free_ranges.is_empty());
+
+            for index in order {
+                assert!(release_to_pool(&mut pool, 0, offsets[index], 100).is_none());
+            }
+            assert_eq!(free_ranges(&pool[0]), vec![(0, 300)], "free order {:?}", order);
+            assert_eq!((pool[0].allocation_count, pool[0].used_bytes), (0, 0));
+        }
+    }
+
+    #[test]
+    fn full_block_spills_into_a_new_block() {
+        let mut pool = vec![empty_block(0, 256)];
+        take_from_pool(&mut pool, 200, 1).unwrap();
+        assert!(take_from_pool(&mut pool, 64, 1).is_none());
+
+        pool.push(empty_block(1, 256));
+        let (block, offset) = take_from_pool(&mut pool, 64, 1).unwrap();
+        assert_eq!((block.id, offset), (1, 0));
+        // The gap left in the first block is still used for what fits
+        assert_eq!(take_from_pool(&mut pool, 56, 1).map(|(block, offset)| (block.id, offset)), Some((0, 200)));
+    }
+
+    #[test]
+    fn one_empty_block_is_kept() {
+        let mut pool = vec![empty_block(0, 256), empty_block(1, 256)];
+        pool[0].take(0, 0, 128);
+        pool[1].take(0, 0, 128);
+
+        assert!(release_to_pool(&mut pool, 0, 0, 128).is_none());
+        let freed = release_to_pool(&mut pool, 1, 0, 128).unwrap();
+        assert_eq!(freed.id, 1);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(free_ranges(&pool[0]), vec![(0, 256)]);
+
+        // Unknown blocks are ignored
+        assert!(release_to_pool(&mut pool, 7, 0, 128).is_none());
+    }
+}
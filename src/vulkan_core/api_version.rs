@@ -0,0 +1,103 @@
+use ash::vk;
+use ash::prelude::VkResult;
+use ash::extensions::khr::{DynamicRendering, Synchronization2};
+
+
+/// The version the instance asks for when the loader supports it.
+pub const TARGET_API_VERSION: u32 = vk::API_VERSION_1_3;
+/// Anything below has neither the core nor the extension path for dynamic rendering and synchronization2.
+pub const MIN_API_VERSION: u32 = vk::API_VERSION_1_2;
+
+const KHR_FALLBACK_DEVICE_EXTENSIONS: [&str; 3] = [
+    "VK_KHR_dynamic_rendering",
+    "VK_KHR_synchronization2",
+    "VK_KHR_depth_stencil_resolve"
+];
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiPath {
+    /// Dynamic rendering and synchronization2 are core.
+    Vulkan13,
+    /// Vulkan 1.2 with the KHR extensions that were promoted in 1.3.
+    Vulkan12Khr
+}
+
+impl ApiPath {
+    /// `api_version` is the lower of the instance and the device version.
+    pub fn for_version(api_version: u32) -> Option<ApiPath> {
+        if api_version >= vk::API_VERSION_1_3 { return Some(ApiPath::Vulkan13); }
+        if api_version >= MIN_API_VERSION { return Some(ApiPath::Vulkan12Khr); }
+        return None;
+    }
+
+    pub fn required_extensions(&self) -> Vec<&'static str> {
+        return match self {
+            ApiPath::Vulkan13 => Vec::new(),
+            ApiPath::Vulkan12Khr => KHR_FALLBACK_DEVICE_EXTENSIONS.to_vec()
+        };
+    }
+}
+
+
+/// A device can't use more than the instance asked for, even if it supports a newer version.
+pub fn effective_api_version(instance_api_version: u32, device_api_version: u32) -> u32 {
+    let major_minor = |v: u32| vk::make_api_version(0, vk::api_version_major(v), vk::api_version_minor(v), 0);
+    return major_minor(instance_api_version).min(major_minor(device_api_version));
+}
+
+pub fn negotiate_instance_version(loader_version: u32) -> u32 {
+    let loader_version = vk::make_api_version(0, vk::api_version_major(loader_version), vk::api_version_minor(loader_version), 0);
+    return loader_version.min(TARGET_API_VERSION);
+}
+
+
+/// Routes the commands that moved into core in 1.3 to either the core or the KHR entry points.
+pub struct DeviceCommands {
+    pub path: ApiPath,
+    device: ash::Device,
+    dynamic_rendering: Option<DynamicRendering>,
+    synchronization2: Option<Synchronization2>
+}
+
+impl DeviceCommands {
+    pub fn new(instance: &ash::Instance, device: &ash::Device, path: ApiPath) -> Self {
+        let (dynamic_rendering, synchronization2) = match path {
+            ApiPath::Vulkan13 => (None, None),
+            ApiPath::Vulkan12Khr => (
+                Some(DynamicRendering::new(instance, device)),
+                Some(Synchronization2::new(instance, device))
+            )
+        };
+
+        return DeviceCommands { path, device: device.clone(), dynamic_rendering, synchronization2 };
+    }
+
+    pub unsafe fn cmd_begin_rendering(&self, command_buffer: vk::CommandBuffer, rendering_info: &vk::RenderingInfo) {
+        match &self.dynamic_rendering {
+            Some(loader) => loader.cmd_begin_rendering(command_buffer, rendering_info),
+            None => self.device.cmd_begin_rendering(command_buffer, rendering_info)
+        }
+    }
+
+    pub unsafe fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        match &self.dynamic_rendering {
+            Some(loader) => loader.cmd_end_rendering(command_buffer),
+            None => self.device.cmd_end_rendering(command_buffer)
+        }
+    }
+
+    pub unsafe fn cmd_pipeline_barrier2(&self, command_buffer: vk::CommandBuffer, dependency_info: &vk::DependencyInfo) {
+        match &self.synchronization2 {
+            Some(loader) => loader.cmd_pipeline_barrier2(command_buffer, dependency_info),
+            None => self.device.cmd_pipeline_barrier2(command_buffer, dependency_info)
+        }
+    }
+
+    pub unsafe fn queue_submit2(&self, queue: vk::Queue, submits: &[vk::SubmitInfo2], fence: vk::Fence) -> VkResult<()> {
+        return match &self.synchronization2 {
+            Some(loader) => loader.queue_submit2(queue, submits, fence),
+            None => self.device.queue_submit2(queue, submits, fence)
+        };
+    }
+}
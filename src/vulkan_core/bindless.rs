@@ -0,0 +1,402 @@
+use std::collections::VecDeque;
+use ash::vk;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::descriptor::{create_descriptor_allocator, DescriptorAllocator, DescriptorAllocatorConfiguration, DescriptorLayoutCache, DescriptorSetLayoutBuilder, DescriptorWriter};
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::VulkanError;
+use crate::vulkan_core::features::{DeviceFeature, EnabledFeatures};
+
+
+/// The arrays of the bindless set, the binding numbers match shaders/include/bindless.glsl.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindlessResourceType {
+    SampledImage,
+    StorageImage,
+    Sampler,
+    StorageBuffer
+}
+
+impl BindlessResourceType {
+    pub const ALL: [BindlessResourceType; 4] = [
+        BindlessResourceType::SampledImage,
+        BindlessResourceType::StorageImage,
+        BindlessResourceType::Sampler,
+        BindlessResourceType::StorageBuffer
+    ];
+
+    pub fn binding(&self) -> u32 {
+        return *self as u32;
+    }
+
+    pub fn descriptor_type(&self) -> vk::DescriptorType {
+        return match self {
+            BindlessResourceType::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+            BindlessResourceType::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            BindlessResourceType::Sampler => vk::DescriptorType::SAMPLER,
+            BindlessResourceType::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER
+        };
+    }
+}
+
+
+/// Requested array sizes, lowered to the device's update-after-bind limits.
+pub struct BindlessTableConfiguration {
+    pub sampled_images: u32,
+    pub storage_images: u32,
+    pub samplers: u32,
+    pub storage_buffers: u32,
+    /// Kept free of `maxPerStageUpdateAfterBindResources` for the other sets and attachments of pipelines
+    /// that use the table.
+    pub reserved_resources: u32
+}
+
+impl Default for BindlessTableConfiguration {
+    fn default() -> Self {
+        return BindlessTableConfiguration {
+            sampled_images: 16384,
+            storage_images: 1024,
+            samplers: 256,
+            storage_buffers: 4096,
+            reserved_resources: 1024
+        };
+    }
+}
+
+
+/// Hands out indices of one array. Released indices come back once every frame that could use them has finished.
+struct IndexAllocator {
+    capacity: u32,
+    /// Indices below this have been handed out at least once.
+    high_water_mark: u32,
+    /// Whether each index is handed out and not yet released.
+    registered: Vec<bool>,
+    free: Vec<u32>,
+    /// Index and the frame number it was released in.
+    pending_release: VecDeque<(u64, u32)>
+}
+
+impl IndexAllocator {
+    fn new(capacity: u32) -> Self {
+        return IndexAllocator {
+            capacity,
+            high_water_mark: 0,
+            registered: vec![false; capacity as usize],
+            free: Vec::new(),
+            pending_release: VecDeque::new()
+        };
+    }
+
+    fn allocate(&mut self, resource_type: BindlessResourceType) -> Result<u32, VulkanError> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None if self.high_water_mark == self.capacity => {
+                return Err(VulkanError::BindlessTableFull { resource_type, capacity: self.capacity });
+            }
+            None => {
+                self.high_water_mark += 1;
+                self.high_water_mark - 1
+            }
+        };
+        self.registered[index as usize] = true;
+        return Ok(index);
+    }
+
+    /// Returns false for indices that aren't handed out, including ones already released.
+    fn release(&mut self, index: u32, frame_number: u64) -> bool {
+        match self.registered.get_mut(index as usize) {
+            Some(registered) if *registered => *registered = false,
+            _ => return false
+        }
+        self.pending_release.push_back((frame_number, index));
+        return true;
+    }
+
+    fn collect(&mut self, completed_frame_count: u64) {
+        // Frame numbers only grow, so everything collectable is at the front
+        while let Some((frame_number, index)) = self.pending_release.front() {
+            if *frame_number >= completed_frame_count { break; }
+            self.free.push(*index);
+            self.pending_release.pop_front();
+        }
+    }
+
+    fn in_use(&self) -> u32 {
+        return self.high_water_mark - self.free.len() as u32 - self.pending_release.len() as u32;
+    }
+}
+
+
+/// One global descriptor set with an UPDATE_AFTER_BIND and PARTIALLY_BOUND array per `BindlessResourceType`.
+/// Registering a resource writes it into a free array element and returns that element's index, which stays
+/// valid until the resource is released. Bind the set once per command buffer and pass indices to shaders.
+pub struct BindlessTable {
+    pub set_layout: vk::DescriptorSetLayout,
+    pub set: vk::DescriptorSet,
+    /// Indexed by `BindlessResourceType`.
+    indices: [IndexAllocator; 4],
+    storage_image_write_without_format: bool,
+    /// Owns the pool of `set`.
+    descriptor_allocator: DescriptorAllocator,
+    device: SharedDevice
+}
+
+
+/// Array sizes in `BindlessResourceType` order, lowered to the device's update-after-bind limits.
+fn fit_capacities(
+    config: &BindlessTableConfiguration, limits: &vk::PhysicalDeviceDescriptorIndexingProperties
+) -> [u32; 4] {
+    // Every stage sees every array, so the per-stage limits apply to the whole set
+    let mut capacities = [
+        config.sampled_images
+            .min(limits.max_per_stage_descriptor_update_after_bind_sampled_images)
+            .min(limits.max_descriptor_set_update_after_bind_sampled_images),
+        config.storage_images
+            .min(limits.max_per_stage_descriptor_update_after_bind_storage_images)
+            .min(limits.max_descriptor_set_update_after_bind_storage_images),
+        config.samplers
+            .min(limits.max_per_stage_descriptor_update_after_bind_samplers)
+            .min(limits.max_descriptor_set_update_after_bind_samplers),
+        config.storage_buffers
+            .min(limits.max_per_stage_descriptor_update_after_bind_storage_buffers)
+            .min(limits.max_descriptor_set_update_after_bind_storage_buffers)
+    ];
+    // All four arrays count against one per-stage total as well, shrink them evenly to fit
+    let resource_budget = limits.max_per_stage_update_after_bind_resources.saturating_sub(config.reserved_resources);
+    let resource_count: u64 = capacities.iter().map(|capacity| *capacity as u64).sum();
+    if resource_count > resource_budget as u64 {
+        for capacity in &mut capacities {
+            *capacity = (*capacity as u64 * resource_budget as u64 / resource_count) as u32;
+        }
+    }
+    return capacities;
+}
+
+
+pub fn create_bindless_table(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    physical_device: vk::PhysicalDevice,
+    enabled_features: &EnabledFeatures,
+    layout_cache: &mut DescriptorLayoutCache,
+    config: &BindlessTableConfiguration
+) -> Result<BindlessTable, VulkanError> {
+    let mut indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2::builder().push_next(&mut indexing_properties);
+    unsafe { device.instance().get_physical_device_properties2(physical_device, &mut properties2) };
+    let capacities = fit_capacities(config, &indexing_properties);
+
+    let binding_flags = vk::DescriptorBindingFlags::UPDATE_AFTER_BIND | vk::DescriptorBindingFlags::PARTIALLY_BOUND |
+        vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+    let mut layout_builder = DescriptorSetLayoutBuilder::new()
+        .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL);
+    for resource_type in BindlessResourceType::ALL {
+        layout_builder = layout_builder.binding_with_flags(
+            resource_type.binding(), resource_type.descriptor_type(), capacities[resource_type as usize],
+            vk::ShaderStageFlags::ALL, binding_flags
+        );
+    }
+    let set_layout = layout_builder.build(layout_cache)?;
+
+    // A single pool sized for exactly the one set
+    let allocator_config = DescriptorAllocatorConfiguration {
+        initial_sets_per_pool: 1,
+        max_sets_per_pool: 1,
+        descriptors_per_set: BindlessResourceType::ALL.iter()
+            .map(|resource_type| (resource_type.descriptor_type(), capacities[*resource_type as usize] as f32))
+            .collect(),
+        pool_flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
+    };
+    let mut descriptor_allocator = create_descriptor_allocator(device, debug_utils, allocator_config, "bindless");
+    let set = descriptor_allocator.allocate(set_layout, "bindless.set")?;
+
+    return Ok(BindlessTable {
+        set_layout,
+        set,
+        indices: capacities.map(IndexAllocator::new),
+        storage_image_write_without_format: enabled_features.storage_image_write_without_format,
+        descriptor_allocator,
+        device: device.clone()
+    });
+}
+
+
+impl BindlessTable {
+    /// `layout` is the one the image is in whenever shaders sample it, usually SHADER_READ_ONLY_OPTIMAL.
+    pub fn register_sampled_image(&mut self, image_view: vk::ImageView, layout: vk::ImageLayout) -> Result<u32, VulkanError> {
+        let info = vk::DescriptorImageInfo { sampler: vk::Sampler::null(), image_view, image_layout: layout };
+        return self.register_image(BindlessResourceType::SampledImage, info);
+    }
+
+    /// Storage images are accessed in the GENERAL layout. The shader side declares them without a format,
+    /// so they need the StorageImageWriteWithoutFormat feature.
+    pub fn register_storage_image(&mut self, image_view: vk::ImageView) -> Result<u32, VulkanError> {
+        if !self.storage_image_write_without_format {
+            return Err(VulkanError::MissingDeviceFeatures(vec![DeviceFeature::StorageImageWriteWithoutFormat]));
+        }
+        let info = vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(), image_view, image_layout: vk::ImageLayout::GENERAL
+        };
+        return self.register_image(BindlessResourceType::StorageImage, info);
+    }
+
+    /// Samplers from the sampler cache can be registered once and kept for the table's lifetime.
+    pub fn register_sampler(&mut self, sampler: vk::Sampler) -> Result<u32, VulkanError> {
+        let info = vk::DescriptorImageInfo {
+            sampler, image_view: vk::ImageView::null(), image_layout: vk::ImageLayout::UNDEFINED
+        };
+        return self.register_image(BindlessResourceType::Sampler, info);
+    }
+
+    /// `range` can be WHOLE_SIZE.
+    pub fn register_storage_buffer(
+        &mut self, buffer: vk::Buffer, offset: vk::DeviceSize, range: vk::DeviceSize
+    ) -> Result<u32, VulkanError> {
+        let index = self.allocate_index(BindlessResourceType::StorageBuffer)?;
+        DescriptorWriter::new()
+            .buffers(
+                BindlessResourceType::StorageBuffer.binding(), index, vk::DescriptorType::STORAGE_BUFFER,
+                &[vk::DescriptorBufferInfo { buffer, offset, range }]
+            )
+            .update(&self.device, self.set);
+        return Ok(index);
+    }
+
+    /// Frees `index` once the frame `frame_number`, and with it every earlier frame, has finished.
+    /// The resource itself has to stay alive as long, `VulkanRenderBase::destroy_deferred` takes care of that.
+    /// Indices that aren't registered, for example ones released twice, are ignored and return false.
+    pub fn release(&mut self, resource_type: BindlessResourceType, index: u32, frame_number: u64) -> bool {
+        return self.indices[resource_type as usize].release(index, frame_number);
+    }
+
+    /// Recycles the indices released in frames below `completed_frame_count`, the render base does so in `prepare_frame`.
+    pub fn collect(&mut self, completed_frame_count: u64) {
+        for indices in &mut self.indices {
+            indices.collect(completed_frame_count);
+        }
+    }
+
+    /// Array size after clamping to the device limits.
+    pub fn capacity(&self, resource_type: BindlessResourceType) -> u32 {
+        return self.indices[resource_type as usize].capacity;
+    }
+
+    /// Registered and not yet released.
+    pub fn registered_count(&self, resource_type: BindlessResourceType) -> u32 {
+        return self.indices[resource_type as usize].in_use();
+    }
+
+    /// Binds the table at `set_index` of `pipeline_layout`, which has to use `set_layout` there.
+    pub fn bind(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        set_index: u32
+    ) {
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(command_buffer, bind_point, pipeline_layout, set_index, &[self.set], &[]);
+        }
+    }
+
+    fn register_image(&mut self, resource_type: BindlessResourceType, info: vk::DescriptorImageInfo) -> Result<u32, VulkanError> {
+        let index = self.allocate_index(resource_type)?;
+        DescriptorWriter::new()
+            .images(resource_type.binding(), index, resource_type.descriptor_type(), &[info])
+            .update(&self.device, self.set);
+        return Ok(index);
+    }
+
+    fn allocate_index(&mut self, resource_type: BindlessResourceType) -> Result<u32, VulkanError> {
+        return self.indices[resource_type as usize].allocate(resource_type);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLED_IMAGE: BindlessResourceType = BindlessResourceType::SampledImage;
+
+    #[test]
+    fn released_indices_come_back_after_their_frame() {
+        let mut indices = IndexAllocator::new(8);
+        let allocated: Vec<u32> = (0..4).map(|_| indices.allocate(SAMPLED_IMAGE).unwrap()).collect();
+        assert_eq!(allocated, [0, 1, 2, 3]);
+
+        assert!(indices.release(1, 10));
+        assert!(indices.release(3, 11));
+        assert_eq!(indices.in_use(), 2);
+
+        // Frame 10 is still in flight
+        indices.collect(10);
+        assert_eq!(indices.allocate(SAMPLED_IMAGE).unwrap(), 4);
+        indices.collect(11);
+        assert_eq!(indices.allocate(SAMPLED_IMAGE).unwrap(), 1);
+        indices.collect(12);
+        assert_eq!(indices.allocate(SAMPLED_IMAGE).unwrap(), 3);
+        assert_eq!(indices.allocate(SAMPLED_IMAGE).unwrap(), 5);
+        assert_eq!(indices.in_use(), 6);
+    }
+
+    #[test]
+    fn unregistered_indices_are_not_released() {
+        let mut indices = IndexAllocator::new(4);
+        let index = indices.allocate(SAMPLED_IMAGE).unwrap();
+
+        assert!(indices.release(index, 0));
+        assert!(!indices.release(index, 0));
+        // Handed out never, and out of range
+        assert!(!indices.release(2, 0));
+        assert!(!indices.release(4, 0));
+
+        indices.collect(1);
+        assert_eq!(indices.allocate(SAMPLED_IMAGE).unwrap(), index);
+        // A duplicate would have come back a second time
+        assert_eq!(indices.allocate(SAMPLED_IMAGE).unwrap(), 1);
+        assert_eq!(indices.in_use(), 2);
+    }
+
+    #[test]
+    fn exhausted_array_is_full() {
+        let mut indices = IndexAllocator::new(2);
+        indices.allocate(SAMPLED_IMAGE).unwrap();
+        indices.allocate(SAMPLED_IMAGE).unwrap();
+        indices.release(0, 5);
+
+        // Released but still pending
+        let result = indices.allocate(SAMPLED_IMAGE);
+        assert!(matches!(result, Err(VulkanError::BindlessTableFull { resource_type: SAMPLED_IMAGE, capacity: 2 })));
+        indices.collect(6);
+        assert_eq!(indices.allocate(SAMPLED_IMAGE).unwrap(), 0);
+    }
+
+    fn limits(per_type: u32, per_stage_resources: u32) -> vk::PhysicalDeviceDescriptorIndexingProperties {
+        return vk::PhysicalDeviceDescriptorIndexingProperties {
+            max_per_stage_descriptor_update_after_bind_sampled_images: per_type,
+            max_per_stage_descriptor_update_after_bind_storage_images: per_type,
+            max_per_stage_descriptor_update_after_bind_samplers: per_type,
+            max_per_stage_descriptor_update_after_bind_storage_buffers: per_type,
+            max_descriptor_set_update_after_bind_sampled_images: per_type,
+            max_descriptor_set_update_after_bind_storage_images: per_type,
+            max_descriptor_set_update_after_bind_samplers: per_type,
+            max_descriptor_set_update_after_bind_storage_buffers: per_type,
+            max_per_stage_update_after_bind_resources: per_stage_resources,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn capacities_fit_the_device_limits() {
+        let config = BindlessTableConfiguration::default();
+
+        // Room for everything
+        assert_eq!(fit_capacities(&config, &limits(1 << 20, 1 << 20)), [16384, 1024, 256, 4096]);
+        // Per type limits only
+        assert_eq!(fit_capacities(&config, &limits(2048, 1 << 20)), [2048, 1024, 256, 2048]);
+        // 21760 requested, 11904 minus the 1024 reserved allows half of that
+        assert_eq!(fit_capacities(&config, &limits(1 << 20, 11904)), [8192, 512, 128, 2048]);
+        // A reserve above the total leaves nothing
+        assert_eq!(fit_capacities(&config, &limits(1 << 20, 512)), [0, 0, 0, 0]);
+    }
+}
@@ -1,45 +1,85 @@
 use ash::vk;
-use crate::vulkan_core::tools::find_memory_type_index;
+use crate::vulkan_core::allocator::{Allocation, SharedAllocator};
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::features::DeviceFeature;
+use crate::vulkan_core::memory_usage::MemoryUsage;
 
 
 pub struct VulkanBufferConfiguration {
     pub size: vk::DeviceSize,
-    pub memory_property_flags: vk::MemoryPropertyFlags,
+    pub memory_usage: MemoryUsage,
+    /// With SHADER_DEVICE_ADDRESS the buffer gets a device address, which needs the BufferDeviceAddress feature.
     pub buffer_usage: vk::BufferUsageFlags
 }
 
 pub struct VulkanBuffer {
     pub handle: vk::Buffer,
-    pub memory: vk::DeviceMemory
+    pub size: vk::DeviceSize,
+    /// Dropped after the buffer is destroyed, which returns the range to the allocator.
+    pub allocation: Allocation,
+    device_address: Option<vk::DeviceAddress>,
+    device: SharedDevice
+}
+
+impl VulkanBuffer {
+    /// For `buffer_reference` in shaders. Only buffers created with SHADER_DEVICE_ADDRESS usage have one.
+    pub fn device_address(&self) -> Option<vk::DeviceAddress> {
+        return self.device_address;
+    }
+}
+
+impl Drop for VulkanBuffer {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_buffer(self.handle, None) };
+    }
 }
 
 pub fn create_buffer(
-    device: &ash::Device,
-    memory_properties: &vk::PhysicalDeviceMemoryProperties,
-    config: &VulkanBufferConfiguration
-) -> VulkanBuffer { unsafe {
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    allocator: &SharedAllocator,
+    config: &VulkanBufferConfiguration,
+    name: &str
+) -> Result<VulkanBuffer, VulkanError> { unsafe {
+    let device_address_usage = config.buffer_usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS);
+    if device_address_usage && !allocator.buffer_device_address() {
+        return Err(VulkanError::MissingDeviceFeatures(vec![DeviceFeature::BufferDeviceAddress]));
+    }
+
     let buffer_create_info = vk::BufferCreateInfo::builder()
         .size(config.size)
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
         .usage(config.buffer_usage);
 
-    let buffer_handle = device.create_buffer(&buffer_create_info, None).expect("MEH");
+    let buffer_handle = device.create_buffer(&buffer_create_info, None)
+        .context("vkCreateBuffer", name)?;
+    debug_utils.set_name(buffer_handle, name);
 
-    let memory_requirements = device.get_buffer_memory_requirements(buffer_handle);
-    let memory_type_index = find_memory_type_index(
-        memory_requirements, memory_properties, config.memory_property_flags
-    );
-
-    let alloc_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(config.size)
-        .memory_type_index(memory_type_index);
-
-    let buffer_memory_handle = device.allocate_memory(&alloc_info, None).expect("MEH");
-
-    device.bind_buffer_memory(buffer_handle, buffer_memory_handle, 0).expect("MEH");
+    let allocation = match allocator.allocate_for_buffer(buffer_handle, config.memory_usage, name) {
+        Ok(allocation) => allocation,
+        Err(error) => {
+            device.destroy_buffer(buffer_handle, None);
+            return Err(error);
+        }
+    };
 
-    return VulkanBuffer {
+    let mut buffer = VulkanBuffer {
         handle: buffer_handle,
-        memory: buffer_memory_handle
+        size: config.size,
+        allocation,
+        device_address: None,
+        device: device.clone()
     };
+    device.bind_buffer_memory(buffer.handle, buffer.allocation.memory, buffer.allocation.offset)
+        .context("vkBindBufferMemory", name)?;
+
+    // Only valid once the memory is bound
+    if device_address_usage {
+        let address_info = vk::BufferDeviceAddressInfo::builder().buffer(buffer.handle);
+        buffer.device_address = Some(device.get_buffer_device_address(&address_info));
+    }
+
+    return Ok(buffer);
 } }
\ No newline at end of file
@@ -1,21 +1,104 @@
 use ash::vk;
 use crate::vulkan_core::{QueueFamily};
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::sync::create_fence;
 
 
-pub fn create_command_pool(device: &ash::Device, queue_family: &QueueFamily) -> vk::CommandPool {
+pub fn create_command_pool(
+    device: &ash::Device, debug_utils: &DebugUtilsDevice, queue_family: &QueueFamily, name: &str
+) -> Result<vk::CommandPool, VulkanError> {
     let create_info = vk::CommandPoolCreateInfo::builder()
         .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
         .queue_family_index(queue_family.index);
     
-    return unsafe { device.create_command_pool(&create_info, None).expect("MEH") } ;
+    let command_pool = unsafe { device.create_command_pool(&create_info, None).context("vkCreateCommandPool", name)? };
+    debug_utils.set_name(command_pool, name);
+    return Ok(command_pool);
 }
 
 
-pub fn create_command_buffer(device: &ash::Device, command_pool: vk::CommandPool, level: vk::CommandBufferLevel) -> vk::CommandBuffer {
+pub fn create_command_buffer(
+    device: &ash::Device,
+    debug_utils: &DebugUtilsDevice,
+    command_pool: vk::CommandPool,
+    level: vk::CommandBufferLevel,
+    name: &str
+) -> Result<vk::CommandBuffer, VulkanError> {
     let alloc_info = vk::CommandBufferAllocateInfo::builder()
         .command_pool(command_pool)
         .level(level)
         .command_buffer_count(1);
 
-    return unsafe { device.allocate_command_buffers(&alloc_info).expect("MEH")[0] };
-}
\ No newline at end of file
+    let command_buffers = unsafe {
+        device.allocate_command_buffers(&alloc_info).context("vkAllocateCommandBuffers", name)?
+    };
+
+    debug_utils.set_name(command_buffers[0], name);
+    return Ok(command_buffers[0]);
+}
+
+/// Records a command buffer with `record`, submits it to `queue` and waits for it to finish.
+/// Whatever `record` returns is handed back afterwards, so resources the commands use can live until then.
+/// If the wait fails the commands may still be executing, so the command buffer, the fence and whatever `record`
+/// returned are leaked instead of destroyed.
+pub fn submit_one_time_commands<T, F: FnOnce(vk::CommandBuffer) -> Result<T, VulkanError>>(
+    device: &ash::Device,
+    debug_utils: &DebugUtilsDevice,
+    command_pool: vk::CommandPool,
+    queue: vk::Queue,
+    name: &str,
+    record: F
+) -> Result<T, VulkanError> {
+    let command_buffer = create_command_buffer(device, debug_utils, command_pool, vk::CommandBufferLevel::PRIMARY, name)?;
+    let fence = match create_fence(device, debug_utils, false, &format!("{}.fence", name)) {
+        Ok(fence) => fence,
+        Err(error) => {
+            unsafe { device.free_command_buffers(command_pool, &[command_buffer]) };
+            return Err(error);
+        }
+    };
+
+    let recorded = match record_and_submit(device, command_buffer, queue, fence, name, record) {
+        Ok(recorded) => recorded,
+        Err(error) => {
+            destroy_one_time_commands(device, command_pool, command_buffer, fence);
+            return Err(error);
+        }
+    };
+    if let Err(error) = unsafe { device.wait_for_fences(&[fence], true, u64::MAX) }.context("vkWaitForFences", name) {
+        std::mem::forget(recorded);
+        return Err(error);
+    }
+
+    destroy_one_time_commands(device, command_pool, command_buffer, fence);
+    return Ok(recorded);
+}
+
+fn destroy_one_time_commands(
+    device: &ash::Device, command_pool: vk::CommandPool, command_buffer: vk::CommandBuffer, fence: vk::Fence
+) {
+    unsafe {
+        device.destroy_fence(fence, None);
+        device.free_command_buffers(command_pool, &[command_buffer]);
+    }
+}
+
+/// Nothing has been submitted if this fails.
+fn record_and_submit<T, F: FnOnce(vk::CommandBuffer) -> Result<T, VulkanError>>(
+    device: &ash::Device, command_buffer: vk::CommandBuffer, queue: vk::Queue, fence: vk::Fence, name: &str, record: F
+) -> Result<T, VulkanError> { unsafe {
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &begin_info)
+        .context("vkBeginCommandBuffer", name)?;
+    let recorded = record(command_buffer)?;
+    device.end_command_buffer(command_buffer)
+        .context("vkEndCommandBuffer", name)?;
+
+    let submit_info = vk::SubmitInfo::builder()
+        .command_buffers(std::slice::from_ref(&command_buffer));
+    device.queue_submit(queue, std::slice::from_ref(&submit_info), fence)
+        .context("vkQueueSubmit", name)?;
+    return Ok(recorded);
+} }
@@ -1,26 +1,394 @@
 #![allow(unused_variables)]
 
-use std::ffi::{c_void, CStr};
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr, CString};
+use std::sync::{Arc, Mutex};
 use colored::Colorize;
 use ash::*;
+use ash::extensions::ext::DebugUtils;
 use ash::vk::{Bool32, DebugUtilsMessengerCallbackDataEXT};
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::instance::SharedInstance;
 
 
+#[derive(Clone, Debug)]
+pub struct DebugObject {
+    pub object_type: vk::ObjectType,
+    pub handle: u64,
+    pub name: Option<String>
+}
+
+/// An owned copy of everything the debug utils callback reports.
+#[derive(Clone, Debug)]
+pub struct DebugMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// The VUID for validation messages, e.g. "VUID-vkCmdDraw-None-02859".
+    pub message_id_name: Option<String>,
+    pub message_id_number: i32,
+    pub message: String,
+    pub objects: Vec<DebugObject>,
+    pub queue_labels: Vec<String>,
+    pub command_buffer_labels: Vec<String>
+}
+
+impl DebugMessage {
+    pub fn is_error(&self) -> bool {
+        return self.severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR);
+    }
+
+    pub fn is_validation(&self) -> bool {
+        return self.message_types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION);
+    }
+
+    unsafe fn from_callback_data(
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+        data: &DebugUtilsMessengerCallbackDataEXT
+    ) -> DebugMessage {
+        let to_string = |ptr: *const std::os::raw::c_char| match ptr.is_null() {
+            true => None,
+            false => Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        };
+        let objects = raw_slice(data.p_objects, data.object_count).iter()
+            .map(|o| DebugObject {
+                object_type: o.object_type,
+                handle: o.object_handle,
+                name: to_string(o.p_object_name)
+            })
+            .collect();
+        let queue_labels = raw_slice(data.p_queue_labels, data.queue_label_count).iter()
+            .filter_map(|l| to_string(l.p_label_name))
+            .collect();
+        let command_buffer_labels = raw_slice(data.p_cmd_buf_labels, data.cmd_buf_label_count).iter()
+            .filter_map(|l| to_string(l.p_label_name))
+            .collect();
+
+        return DebugMessage {
+            severity,
+            message_types,
+            message_id_name: to_string(data.p_message_id_name),
+            message_id_number: data.message_id_number,
+            message: to_string(data.p_message).unwrap_or_default(),
+            objects,
+            queue_labels,
+            command_buffer_labels
+        };
+    }
+}
+
+
+/// Receives every message that passed the filters of a `DebugMessenger`.
+/// Called from whichever thread made the Vulkan call, so implementations have to be thread-safe.
+pub trait DebugSink: Send + Sync {
+    fn handle_message(&self, message: &DebugMessage);
+}
+
+/// Prints colored messages to stdout.
+pub struct PrintSink;
+
+impl DebugSink for PrintSink {
+    fn handle_message(&self, message: &DebugMessage) {
+        print_message(message);
+    }
+}
+
+/// Keeps every message for later inspection.
+#[derive(Default)]
+pub struct CollectingSink {
+    messages: Mutex<Vec<DebugMessage>>
+}
+
+impl CollectingSink {
+    pub fn new() -> Arc<CollectingSink> {
+        return Arc::new(CollectingSink::default());
+    }
+
+    pub fn messages(&self) -> Vec<DebugMessage> {
+        return self.messages.lock().unwrap().clone();
+    }
+
+    pub fn errors(&self) -> Vec<DebugMessage> {
+        return self.messages.lock().unwrap().iter().filter(|m| m.is_error()).cloned().collect();
+    }
+
+    /// Returns the collected messages and starts over with an empty list.
+    pub fn take(&self) -> Vec<DebugMessage> {
+        return std::mem::take(&mut *self.messages.lock().unwrap());
+    }
+}
+
+impl DebugSink for CollectingSink {
+    fn handle_message(&self, message: &DebugMessage) {
+        self.messages.lock().unwrap().push(message.clone());
+    }
+}
+
+
+pub struct DebugMessengerConfiguration {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Messages with these VUIDs never reach the sink.
+    pub suppressed_message_ids: Vec<String>,
+    /// How often the same message ID, or the same text for messages without one, is forwarded
+    /// before further repeats are dropped.
+    pub repeat_limit: Option<u32>,
+    /// Keeps the first validation error for `DebugMessenger::check`. Panicking in the callback itself
+    /// would abort the process, as unwinding can't cross the driver's frames.
+    pub fail_on_error: bool,
+    pub sink: Arc<dyn DebugSink>
+}
+
+impl Default for DebugMessengerConfiguration {
+    fn default() -> Self {
+        return DebugMessengerConfiguration {
+            severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_types: vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION |
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL |
+                vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            suppressed_message_ids: Vec::new(),
+            repeat_limit: Some(10),
+            fail_on_error: false,
+            sink: Arc::new(PrintSink)
+        };
+    }
+}
+
+
+/// The callback's p_user_data points here, so it lives in a Box to keep its address stable.
+struct MessengerState {
+    suppressed_message_ids: Vec<String>,
+    repeat_limit: Option<u32>,
+    fail_on_error: bool,
+    sink: Arc<dyn DebugSink>,
+    repeat_counts: Mutex<HashMap<(i32, String), u32>>,
+    dropped_count: Mutex<u64>,
+    first_error: Mutex<Option<DebugMessage>>
+}
+
+impl MessengerState {
+    fn dispatch(&self, message: DebugMessage) {
+        if let Some(id_name) = &message.message_id_name {
+            if self.suppressed_message_ids.iter().any(|suppressed| suppressed == id_name) { return; }
+        }
+
+        if let Some(repeat_limit) = self.repeat_limit {
+            let mut repeat_counts = self.repeat_counts.lock().unwrap();
+            let count = repeat_counts.entry(repeat_key(&message)).or_insert(0);
+            *count += 1;
+            if *count > repeat_limit {
+                *self.dropped_count.lock().unwrap() += 1;
+                return;
+            }
+        }
+
+        self.sink.handle_message(&message);
+
+        if self.fail_on_error && message.is_error() && message.is_validation() {
+            self.first_error.lock().unwrap().get_or_insert(message);
+        }
+    }
+}
+
+
+/// Loader and many performance messages have no ID, their text tells them apart instead.
+fn repeat_key(message: &DebugMessage) -> (i32, String) {
+    return match message.message_id_number {
+        0 => (0, message.message.clone()),
+        id_number => (id_number, message.message_id_name.clone().unwrap_or_default())
+    };
+}
+
+
+pub struct DebugMessenger {
+    pub handle: vk::DebugUtilsMessengerEXT,
+    pub loader: DebugUtils,
+    state: Box<MessengerState>,
+    instance: SharedInstance
+}
+
+impl DebugMessenger {
+    /// Messages dropped because their ID exceeded the repeat limit.
+    pub fn dropped_message_count(&self) -> u64 {
+        return *self.state.dropped_count.lock().unwrap();
+    }
+
+    pub fn sink(&self) -> &Arc<dyn DebugSink> {
+        return &self.state.sink;
+    }
+
+    /// The first validation error since the last call, only recorded with `fail_on_error`.
+    pub fn take_error(&self) -> Option<DebugMessage> {
+        return self.state.first_error.lock().unwrap().take();
+    }
+
+    /// Turns the first validation error since the last call into an error on the caller's stack.
+    pub fn check(&self) -> Result<(), VulkanError> {
+        return match self.take_error() {
+            Some(message) => Err(VulkanError::Validation {
+                message_id: message.message_id_name.unwrap_or_default(),
+                message: message.message
+            }),
+            None => Ok(())
+        };
+    }
+}
+
+impl Drop for DebugMessenger {
+    fn drop(&mut self) {
+        unsafe { self.loader.destroy_debug_utils_messenger(self.handle, None) };
+    }
+}
+
+
+/// The instance needs VK_EXT_debug_utils enabled.
+pub fn create_debug_messenger(
+    instance: &SharedInstance, config: &DebugMessengerConfiguration
+) -> Result<DebugMessenger, VulkanError> {
+    let loader = DebugUtils::new(&instance.entry, instance);
+    let state = Box::new(MessengerState {
+        suppressed_message_ids: config.suppressed_message_ids.clone(),
+        repeat_limit: config.repeat_limit,
+        fail_on_error: config.fail_on_error,
+        sink: config.sink.clone(),
+        repeat_counts: Mutex::new(HashMap::new()),
+        dropped_count: Mutex::new(0),
+        first_error: Mutex::new(None)
+    });
+
+    let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(config.severity)
+        .message_type(config.message_types)
+        .pfn_user_callback(Some(debug_callback))
+        .user_data(&*state as *const MessengerState as *mut c_void);
+
+    let handle = unsafe {
+        loader.create_debug_utils_messenger(&create_info, None)
+            .context("vkCreateDebugUtilsMessengerEXT", "debug messenger")?
+    };
+
+    return Ok(DebugMessenger { handle, loader, state, instance: instance.clone() });
+}
+
+
+/// Names objects and labels command buffers. Every call is a no-op when VK_EXT_debug_utils isn't enabled,
+/// so factories can name unconditionally.
+#[derive(Clone)]
+pub struct DebugUtilsDevice {
+    loader: Option<DebugUtils>,
+    device: vk::Device
+}
+
+impl DebugUtilsDevice {
+    pub fn new(entry: &ash::Entry, instance: &ash::Instance, device: &ash::Device, enabled: bool) -> Self {
+        let loader = match enabled {
+            true => Some(DebugUtils::new(entry, instance)),
+            false => None
+        };
+        return DebugUtilsDevice { loader, device: device.handle() };
+    }
+
+    pub fn disabled() -> Self {
+        return DebugUtilsDevice { loader: None, device: vk::Device::null() };
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        return self.loader.is_some();
+    }
+
+    /// Names show up in validation messages and captures, e.g. "hello_triangle.vertex_buffer".
+    pub fn set_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        let Some(loader) = &self.loader else { return; };
+        let name = label_text(name);
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(H::TYPE)
+            .object_handle(handle.as_raw())
+            .object_name(&name);
+
+        let result = unsafe { loader.set_debug_utils_object_name(self.device, &name_info) };
+        if let Err(error) = result.context("vkSetDebugUtilsObjectNameEXT", &name.to_string_lossy()) {
+            println!("{}", error);
+        }
+    }
+
+    /// The region ends when the returned scope is dropped.
+    pub fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str) -> CommandBufferLabel<'_> {
+        if let Some(loader) = &self.loader {
+            let name = label_text(name);
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+            unsafe { loader.cmd_begin_debug_utils_label(command_buffer, &label) };
+        }
+        return CommandBufferLabel { debug_utils: self, command_buffer };
+    }
+
+    pub fn insert_marker(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        let Some(loader) = &self.loader else { return; };
+        let name = label_text(name);
+        let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+        unsafe { loader.cmd_insert_debug_utils_label(command_buffer, &label) };
+    }
+}
+
+
+/// Names come from callers and file names, interior NULs are dropped instead of failing the call.
+fn label_text(name: &str) -> CString {
+    return CString::new(name.replace('\0', "")).unwrap_or_default();
+}
+
+
+/// A labelled region of a command buffer, from `DebugUtilsDevice::begin_label` until drop.
+pub struct CommandBufferLabel<'a> {
+    debug_utils: &'a DebugUtilsDevice,
+    command_buffer: vk::CommandBuffer
+}
+
+impl Drop for CommandBufferLabel<'_> {
+    fn drop(&mut self) {
+        if let Some(loader) = &self.debug_utils.loader {
+            unsafe { loader.cmd_end_debug_utils_label(self.command_buffer) };
+        }
+    }
+}
+
+
+/// Without user data, e.g. during instance creation, messages are printed.
 pub unsafe extern "system" fn debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_types: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const DebugUtilsMessengerCallbackDataEXT,
     p_user_data: *mut c_void,
 ) -> Bool32 {
-    let message = CStr::from_ptr((*p_callback_data).p_message);
+    let message = DebugMessage::from_callback_data(message_severity, message_types, &*p_callback_data);
 
-    let severity = match message_severity {
+    match (p_user_data as *const MessengerState).as_ref() {
+        Some(state) => state.dispatch(message),
+        None => print_message(&message)
+    }
+    return vk::FALSE;
+}
+
+unsafe fn raw_slice<'a, T>(ptr: *const T, count: u32) -> &'a [T] {
+    if ptr.is_null() || count == 0 { return &[]; }
+    return std::slice::from_raw_parts(ptr, count as usize);
+}
+
+fn print_message(message: &DebugMessage) {
+    let severity = match message.severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "VERBOSE".white(),
         vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "INFO".white(),
         vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "WARNING".yellow(),
         vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "ERROR".red(),
         _ => "".white()
     };
 
-    println!("{}: {}", severity, message.to_str().unwrap());
-    return vk::FALSE;
-}
\ No newline at end of file
+    println!("{}: {}", severity, message.message);
+    for object in &message.objects {
+        match &object.name {
+            Some(name) => println!("    {:?} {:#x} \"{}\"", object.object_type, object.handle, name),
+            None => println!("    {:?} {:#x}", object.object_type, object.handle)
+        }
+    }
+    if !message.command_buffer_labels.is_empty() {
+        println!("    command buffer labels: {}", message.command_buffer_labels.join(" > "));
+    }
+}
@@ -0,0 +1,44 @@
+use std::any::Any;
+use std::collections::VecDeque;
+
+
+/// Keeps released resources alive until the GPU is done with every frame that could still use them.
+/// Entries are tagged with the number of the frame they were released in, and dropping an entry
+/// destroys its Vulkan objects through their own `Drop`.
+#[derive(Default)]
+pub struct DeletionQueue {
+    pending: VecDeque<(u64, Box<dyn Any>)>
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        return DeletionQueue { pending: VecDeque::new() };
+    }
+
+    /// `frame_number` is the frame that has to complete before the resource may be destroyed.
+    pub fn push<T: 'static>(&mut self, frame_number: u64, resource: T) {
+        self.pending.push_back((frame_number, Box::new(resource)));
+    }
+
+    /// Destroys everything whose frame is below `completed_frame_count`.
+    pub fn collect(&mut self, completed_frame_count: u64) {
+        // Frame numbers only grow, so everything collectable is at the front
+        while let Some((frame_number, _)) = self.pending.front() {
+            if *frame_number >= completed_frame_count { break; }
+            self.pending.pop_front();
+        }
+    }
+
+    /// Destroys everything. Only valid once the device is idle.
+    pub fn flush(&mut self) {
+        self.pending.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.pending.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.pending.is_empty();
+    }
+}
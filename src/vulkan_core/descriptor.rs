@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use ash::vk;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DescriptorBinding {
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// Array size, the upper bound for a VARIABLE_DESCRIPTOR_COUNT binding.
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+    pub flags: vk::DescriptorBindingFlags
+}
+
+
+/// Everything that makes two set layouts different. Identical descriptions share one `vk::DescriptorSetLayout`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DescriptorSetLayoutDescription {
+    pub flags: vk::DescriptorSetLayoutCreateFlags,
+    /// Sorted by binding number.
+    pub bindings: Vec<DescriptorBinding>
+}
+
+
+pub struct DescriptorSetLayoutBuilder {
+    description: DescriptorSetLayoutDescription
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn new() -> Self {
+        return DescriptorSetLayoutBuilder { description: DescriptorSetLayoutDescription::default() };
+    }
+
+    /// Replaces an earlier binding with the same number.
+    pub fn binding(self, binding: u32, descriptor_type: vk::DescriptorType, count: u32, stages: vk::ShaderStageFlags) -> Self {
+        return self.binding_with_flags(binding, descriptor_type, count, stages, vk::DescriptorBindingFlags::empty());
+    }
+
+    /// Binding flags like PARTIALLY_BOUND or UPDATE_AFTER_BIND need the DescriptorIndexing feature.
+    pub fn binding_with_flags(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+        flags: vk::DescriptorBindingFlags
+    ) -> Self {
+        let new_binding = DescriptorBinding { binding, descriptor_type, count, stages, flags };
+        let bindings = &mut self.description.bindings;
+        match bindings.binary_search_by_key(&binding, |existing| existing.binding) {
+            Ok(index) => bindings[index] = new_binding,
+            Err(index) => bindings.insert(index, new_binding)
+        }
+        return self;
+    }
+
+    /// UPDATE_AFTER_BIND_POOL is required as soon as a binding has UPDATE_AFTER_BIND.
+    pub fn flags(mut self, flags: vk::DescriptorSetLayoutCreateFlags) -> Self {
+        self.description.flags = flags;
+        return self;
+    }
+
+    pub fn description(&self) -> &DescriptorSetLayoutDescription {
+        return &self.description;
+    }
+
+    /// The cache owns the layout, it stays valid until the cache is dropped.
+    pub fn build(&self, cache: &mut DescriptorLayoutCache) -> Result<vk::DescriptorSetLayout, VulkanError> {
+        return cache.get_or_create(&self.description);
+    }
+}
+
+
+/// Creates every distinct set layout once, so pipelines built from the same description agree on their layouts.
+pub struct DescriptorLayoutCache {
+    layouts: HashMap<DescriptorSetLayoutDescription, vk::DescriptorSetLayout>,
+    debug_utils: DebugUtilsDevice,
+    device: SharedDevice
+}
+
+impl Drop for DescriptorLayoutCache {
+    fn drop(&mut self) {
+        for layout in self.layouts.values() {
+            unsafe { self.device.destroy_descriptor_set_layout(*layout, None) };
+        }
+    }
+}
+
+
+pub fn create_descriptor_layout_cache(device: &SharedDevice, debug_utils: &DebugUtilsDevice) -> DescriptorLayoutCache {
+    return DescriptorLayoutCache {
+        layouts: HashMap::new(),
+        debug_utils: debug_utils.clone(),
+        device: device.clone()
+    };
+}
+
+
+impl DescriptorLayoutCache {
+    pub fn len(&self) -> usize {
+        return self.layouts.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.layouts.is_empty();
+    }
+
+    pub fn get_or_create(&mut self, description: &DescriptorSetLayoutDescription) -> Result<vk::DescriptorSetLayout, VulkanError> {
+        if let Some(layout) = self.layouts.get(description) {
+            return Ok(*layout);
+        }
+
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = description.bindings.iter()
+            .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding.binding)
+                .descriptor_type(binding.descriptor_type)
+                .descriptor_count(binding.count)
+                .stage_flags(binding.stages)
+                .build())
+            .collect();
+        let binding_flags: Vec<vk::DescriptorBindingFlags> = description.bindings.iter()
+            .map(|binding| binding.flags)
+            .collect();
+        let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder()
+            .binding_flags(&binding_flags);
+
+        let mut layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(description.flags)
+            .bindings(&bindings);
+        // Without any flags the struct is left out, so plain layouts don't depend on descriptor indexing
+        if binding_flags.iter().any(|flags| !flags.is_empty()) {
+            layout_info = layout_info.push_next(&mut binding_flags_info);
+        }
+
+        let name = format!("descriptor_layout_cache.layout[{}]", self.layouts.len());
+        let layout = unsafe {
+            self.device.create_descriptor_set_layout(&layout_info, None).context("vkCreateDescriptorSetLayout", &name)?
+        };
+        self.debug_utils.set_name(layout, &name);
+
+        self.layouts.insert(description.clone(), layout);
+        return Ok(layout);
+    }
+}
+
+
+#[derive(Clone)]
+pub struct DescriptorAllocatorConfiguration {
+    /// Sets of the first pool, every further pool doubles it up to `max_sets_per_pool`.
+    pub initial_sets_per_pool: u32,
+    pub max_sets_per_pool: u32,
+    /// Average descriptors of each type per set. A pool for N sets holds N times as many.
+    pub descriptors_per_set: Vec<(vk::DescriptorType, f32)>,
+    /// UPDATE_AFTER_BIND for sets of UPDATE_AFTER_BIND_POOL layouts. FREE_DESCRIPTOR_SET isn't needed,
+    /// sets are only released all at once by `reset`.
+    pub pool_flags: vk::DescriptorPoolCreateFlags
+}
+
+impl Default for DescriptorAllocatorConfiguration {
+    fn default() -> Self {
+        return DescriptorAllocatorConfiguration {
+            initial_sets_per_pool: 64,
+            max_sets_per_pool: 4096,
+            descriptors_per_set: vec![
+                (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+                (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+                (vk::DescriptorType::STORAGE_BUFFER, 2.0),
+                (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 1.0),
+                (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+                (vk::DescriptorType::SAMPLED_IMAGE, 4.0),
+                (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+                (vk::DescriptorType::SAMPLER, 1.0)
+            ],
+            pool_flags: vk::DescriptorPoolCreateFlags::empty()
+        };
+    }
+}
+
+
+/// Allocates sets from a list of pools and creates a bigger pool whenever the current one runs out.
+/// Sets are never freed one by one, `reset` returns all of them at once.
+pub struct DescriptorAllocator {
+    config: DescriptorAllocatorConfiguration,
+    /// Pools with space left, the last one is allocated from.
+    ready_pools: Vec<vk::DescriptorPool>,
+    full_pools: Vec<vk::DescriptorPool>,
+    next_sets_per_pool: u32,
+    created_pool_count: u32,
+    name: String,
+    debug_utils: DebugUtilsDevice,
+    device: SharedDevice
+}
+
+impl Drop for DescriptorAllocator {
+    fn drop(&mut self) {
+        for pool in self.ready_pools.drain(..).chain(self.full_pools.drain(..)) {
+            unsafe { self.device.destroy_descriptor_pool(pool, None) };
+        }
+    }
+}
+
+
+pub fn create_descriptor_allocator(
+    device: &SharedDevice, debug_utils: &DebugUtilsDevice, config: DescriptorAllocatorConfiguration, name: &str
+) -> DescriptorAllocator {
+    return DescriptorAllocator {
+        next_sets_per_pool: config.initial_sets_per_pool.max(1),
+        config,
+        ready_pools: Vec::new(),
+        full_pools: Vec::new(),
+        created_pool_count: 0,
+        name: name.to_string(),
+        debug_utils: debug_utils.clone(),
+        device: device.clone()
+    };
+}
+
+
+impl DescriptorAllocator {
+    pub fn allocate(&mut self, layout: vk::DescriptorSetLayout, name: &str) -> Result<vk::DescriptorSet, VulkanError> {
+        return self.allocate_with_count(layout, None, name);
+    }
+
+    /// For layouts whose last binding has VARIABLE_DESCRIPTOR_COUNT, `count` is that binding's actual size.
+    pub fn allocate_variable(
+        &mut self, layout: vk::DescriptorSetLayout, count: u32, name: &str
+    ) -> Result<vk::DescriptorSet, VulkanError> {
+        return self.allocate_with_count(layout, Some(count), name);
+    }
+
+    /// Makes every set allocated so far invalid. Sets of a frame in flight can only be reset once its fence
+    /// has signalled, the render base does so for `frame_descriptor_allocator` in `prepare_frame`.
+    pub fn reset(&mut self) -> Result<(), VulkanError> {
+        let pools: Vec<vk::DescriptorPool> = self.full_pools.drain(..).collect();
+        self.ready_pools.extend(pools);
+        for pool in &self.ready_pools {
+            unsafe {
+                self.device.reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty())
+                    .context("vkResetDescriptorPool", &self.name)?;
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn pool_count(&self) -> usize {
+        return self.ready_pools.len() + self.full_pools.len();
+    }
+
+    fn allocate_with_count(
+        &mut self, layout: vk::DescriptorSetLayout, variable_count: Option<u32>, name: &str
+    ) -> Result<vk::DescriptorSet, VulkanError> {
+        let mut pool = self.ready_pool()?;
+        let set = match self.try_allocate(pool, layout, variable_count) {
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.full_pools.push(self.ready_pools.pop().unwrap());
+                pool = self.ready_pool()?;
+                // A fresh pool failing as well means the set doesn't fit the configured ratios at all
+                self.try_allocate(pool, layout, variable_count)
+            },
+            result => result
+        }.context("vkAllocateDescriptorSets", name)?;
+
+        self.debug_utils.set_name(set, name);
+        return Ok(set);
+    }
+
+    fn try_allocate(
+        &self, pool: vk::DescriptorPool, layout: vk::DescriptorSetLayout, variable_count: Option<u32>
+    ) -> Result<vk::DescriptorSet, vk::Result> {
+        let set_layouts = [layout];
+        let counts = [variable_count.unwrap_or(0)];
+        let mut variable_count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+            .descriptor_counts(&counts);
+        let mut allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        if variable_count.is_some() {
+            allocate_info = allocate_info.push_next(&mut variable_count_info);
+        }
+
+        let sets = unsafe { self.device.allocate_descriptor_sets(&allocate_info)? };
+        return Ok(sets[0]);
+    }
+
+    /// The pool to allocate from, a new one if none has space left.
+    fn ready_pool(&mut self) -> Result<vk::DescriptorPool, VulkanError> {
+        if let Some(pool) = self.ready_pools.last() {
+            return Ok(*pool);
+        }
+
+        let set_count = self.next_sets_per_pool;
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = self.config.descriptors_per_set.iter()
+            .map(|(descriptor_type, per_set)| vk::DescriptorPoolSize {
+                ty: *descriptor_type,
+                descriptor_count: ((per_set * set_count as f32).ceil() as u32).max(1)
+            })
+            .collect();
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(self.config.pool_flags)
+            .max_sets(set_count)
+            .pool_sizes(&pool_sizes);
+
+        let name = format!("{}.pool[{}]", self.name, self.created_pool_count);
+        let pool = unsafe {
+            self.device.create_descriptor_pool(&pool_info, None).context("vkCreateDescriptorPool", &name)?
+        };
+        self.debug_utils.set_name(pool, &name);
+
+        self.created_pool_count += 1;
+        self.next_sets_per_pool = (set_count * 2).min(self.config.max_sets_per_pool.max(set_count));
+        self.ready_pools.push(pool);
+        return Ok(pool);
+    }
+}
+
+
+enum PendingInfo {
+    Buffers(std::ops::Range<usize>),
+    Images(std::ops::Range<usize>)
+}
+
+struct PendingWrite {
+    binding: u32,
+    array_element: u32,
+    descriptor_type: vk::DescriptorType,
+    info: PendingInfo
+}
+
+
+/// Collects descriptor writes and applies them with a single vkUpdateDescriptorSets.
+pub struct DescriptorWriter {
+    buffer_infos: Vec<vk::DescriptorBufferInfo>,
+    image_infos: Vec<vk::DescriptorImageInfo>,
+    writes: Vec<PendingWrite>
+}
+
+impl DescriptorWriter {
+    pub fn new() -> Self {
+        return DescriptorWriter { buffer_infos: Vec::new(), image_infos: Vec::new(), writes: Vec::new() };
+    }
+
+    /// Uniform and storage buffers, dynamic ones included. `range` can be WHOLE_SIZE.
+    pub fn buffer(
+        self, binding: u32, descriptor_type: vk::DescriptorType, buffer: vk::Buffer, offset: vk::DeviceSize, range: vk::DeviceSize
+    ) -> Self {
+        return self.buffers(binding, 0, descriptor_type, &[vk::DescriptorBufferInfo { buffer, offset, range }]);
+    }
+
+    /// Consecutive array elements starting at `array_element`.
+    pub fn buffers(
+        mut self, binding: u32, array_element: u32, descriptor_type: vk::DescriptorType, infos: &[vk::DescriptorBufferInfo]
+    ) -> Self {
+        let start = self.buffer_infos.len();
+        self.buffer_infos.extend_from_slice(infos);
+        self.writes.push(PendingWrite {
+            binding, array_element, descriptor_type, info: PendingInfo::Buffers(start..self.buffer_infos.len())
+        });
+        return self;
+    }
+
+    /// Sampled, storage and input attachment images. The sampler is only read for COMBINED_IMAGE_SAMPLER.
+    pub fn image(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        image_view: vk::ImageView,
+        image_layout: vk::ImageLayout,
+        sampler: vk::Sampler
+    ) -> Self {
+        return self.images(binding, 0, descriptor_type, &[vk::DescriptorImageInfo { sampler, image_view, image_layout }]);
+    }
+
+    pub fn sampler(self, binding: u32, sampler: vk::Sampler) -> Self {
+        return self.image(binding, vk::DescriptorType::SAMPLER, vk::ImageView::null(), vk::ImageLayout::UNDEFINED, sampler);
+    }
+
+    /// Consecutive array elements starting at `array_element`.
+    pub fn images(
+        mut self, binding: u32, array_element: u32, descriptor_type: vk::DescriptorType, infos: &[vk::DescriptorImageInfo]
+    ) -> Self {
+        let start = self.image_infos.len();
+        self.image_infos.extend_from_slice(infos);
+        self.writes.push(PendingWrite {
+            binding, array_element, descriptor_type, info: PendingInfo::Images(start..self.image_infos.len())
+        });
+        return self;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.writes.is_empty();
+    }
+
+    /// Writes everything collected into `set`. The writer can be reused for further sets of the same layout.
+    pub fn update(&self, device: &ash::Device, set: vk::DescriptorSet) {
+        if self.writes.is_empty() {
+            return;
+        }
+
+        let writes: Vec<vk::WriteDescriptorSet> = self.writes.iter()
+            .map(|write| {
+                let builder = vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(write.binding)
+                    .dst_array_element(write.array_element)
+                    .descriptor_type(write.descriptor_type);
+                return match &write.info {
+                    PendingInfo::Buffers(range) => builder.buffer_info(&self.buffer_infos[range.clone()]).build(),
+                    PendingInfo::Images(range) => builder.image_info(&self.image_infos[range.clone()]).build()
+                };
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer_infos.clear();
+        self.image_infos.clear();
+        self.writes.clear();
+    }
+}
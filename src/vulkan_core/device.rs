@@ -0,0 +1,46 @@
+use std::ops::Deref;
+use std::sync::Arc;
+use crate::vulkan_core::instance::SharedInstance;
+
+
+/// Every object created from the device holds one of these, so the device is destroyed last.
+pub struct OwnedDevice {
+    pub handle: ash::Device,
+    pub enabled_extensions: Vec<String>,
+    // Keeps the instance alive until the device is gone
+    instance: SharedInstance
+}
+
+impl OwnedDevice {
+    pub fn new(instance: &SharedInstance, handle: ash::Device, enabled_extensions: Vec<String>) -> SharedDevice {
+        return Arc::new(OwnedDevice { handle, enabled_extensions, instance: instance.clone() });
+    }
+
+    pub fn is_extension_enabled(&self, name: &str) -> bool {
+        return self.enabled_extensions.iter().any(|extension| extension == name);
+    }
+
+    pub fn instance(&self) -> &SharedInstance {
+        return &self.instance;
+    }
+}
+
+impl Deref for OwnedDevice {
+    type Target = ash::Device;
+
+    fn deref(&self) -> &ash::Device {
+        return &self.handle;
+    }
+}
+
+impl Drop for OwnedDevice {
+    fn drop(&mut self) {
+        unsafe {
+            // Nothing can be in use anymore, but a failed wait shouldn't prevent the destruction
+            let _ = self.handle.device_wait_idle();
+            self.handle.destroy_device(None);
+        }
+    }
+}
+
+pub type SharedDevice = Arc<OwnedDevice>;
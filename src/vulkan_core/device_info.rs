@@ -0,0 +1,370 @@
+use std::ffi::CStr;
+use std::fmt::Write;
+use ash::vk;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::vulkan_core::{get_unique_queue_families, SurfaceInfo};
+use crate::vulkan_core::api_version::effective_api_version;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::features::{query_supported_features, DeviceFeature};
+
+
+const REPORTED_DEVICE_FEATURES: [DeviceFeature; 13] = [
+    DeviceFeature::SamplerAnisotropy,
+    DeviceFeature::SampleRateShading,
+    DeviceFeature::MultiDrawIndirect,
+    DeviceFeature::ShaderDrawParameters,
+    DeviceFeature::DescriptorIndexing,
+    DeviceFeature::TimelineSemaphore,
+    DeviceFeature::DynamicRendering,
+    DeviceFeature::Synchronization2,
+    DeviceFeature::BufferDeviceAddress,
+    DeviceFeature::TextureCompressionBc,
+    DeviceFeature::TextureCompressionAstcLdr,
+    DeviceFeature::StorageImageWriteWithoutFormat,
+    DeviceFeature::StorageArrayNonUniformIndexing
+];
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceInfoFormat {
+    Text,
+    Json
+}
+
+
+/// Everything the instance can tell about every physical device, meant to be attached to bug reports.
+#[derive(Serialize)]
+pub struct DeviceInfoReport {
+    pub instance_api_version: String,
+    pub devices: Vec<PhysicalDeviceInfo>
+}
+
+#[derive(Serialize)]
+pub struct PhysicalDeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub device_type: String,
+    pub api_version: String,
+    pub driver_version: u32,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub limits: Map<String, Value>,
+    /// Core 1.0 features plus the features this crate knows how to enable.
+    pub features: Map<String, Value>,
+    pub memory_heaps: Vec<MemoryHeapInfo>,
+    pub memory_types: Vec<MemoryTypeInfo>,
+    pub queue_families: Vec<QueueFamilyInfo>,
+    pub extensions: Vec<ExtensionInfo>,
+    /// Only present when the report was created with a surface.
+    pub surface: Option<SurfaceSupportInfo>
+}
+
+#[derive(Serialize)]
+pub struct MemoryHeapInfo {
+    pub index: u32,
+    pub size: vk::DeviceSize,
+    pub flags: String
+}
+
+#[derive(Serialize)]
+pub struct MemoryTypeInfo {
+    pub index: u32,
+    pub heap_index: u32,
+    pub flags: String
+}
+
+#[derive(Serialize)]
+pub struct QueueFamilyInfo {
+    pub index: u32,
+    pub queue_count: u32,
+    pub flags: String,
+    pub timestamp_valid_bits: u32,
+    pub present_supported: Option<bool>
+}
+
+#[derive(Serialize)]
+pub struct ExtensionInfo {
+    pub name: String,
+    pub spec_version: u32
+}
+
+#[derive(Serialize)]
+pub struct SurfaceSupportInfo {
+    pub min_image_count: u32,
+    pub max_image_count: u32,
+    pub current_extent: [u32; 2],
+    pub formats: Vec<SurfaceFormatInfo>,
+    pub present_modes: Vec<String>
+}
+
+#[derive(Serialize)]
+pub struct SurfaceFormatInfo {
+    pub format: String,
+    pub color_space: String
+}
+
+
+impl DeviceInfoReport {
+    pub fn format(&self, format: DeviceInfoFormat) -> String {
+        return match format {
+            DeviceInfoFormat::Text => self.to_text(),
+            DeviceInfoFormat::Json => self.to_json()
+        };
+    }
+
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).unwrap();
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        writeln!(text, "Instance API version: {}", self.instance_api_version).unwrap();
+        for device in &self.devices {
+            writeln!(text).unwrap();
+            device.write_text(&mut text);
+        }
+        return text;
+    }
+}
+
+impl PhysicalDeviceInfo {
+    fn write_text(&self, text: &mut String) {
+        writeln!(text, "[{}] {}", self.index, self.name).unwrap();
+        writeln!(text, "  Type: {}", self.device_type).unwrap();
+        writeln!(text, "  API version: {}", self.api_version).unwrap();
+        writeln!(text, "  Driver version: {:#x}", self.driver_version).unwrap();
+        writeln!(text, "  Vendor/Device ID: {:#06x}/{:#06x}", self.vendor_id, self.device_id).unwrap();
+
+        writeln!(text, "  Limits:").unwrap();
+        for (name, value) in &self.limits {
+            writeln!(text, "    {}: {}", name, value).unwrap();
+        }
+
+        writeln!(text, "  Features:").unwrap();
+        for (name, value) in &self.features {
+            writeln!(text, "    {}: {}", name, value).unwrap();
+        }
+
+        writeln!(text, "  Memory heaps:").unwrap();
+        for heap in &self.memory_heaps {
+            writeln!(text, "    [{}] {} MiB {}", heap.index, heap.size >> 20, heap.flags).unwrap();
+        }
+
+        writeln!(text, "  Memory types:").unwrap();
+        for memory_type in &self.memory_types {
+            writeln!(text, "    [{}] heap {} {}", memory_type.index, memory_type.heap_index, memory_type.flags).unwrap();
+        }
+
+        writeln!(text, "  Queue families:").unwrap();
+        for family in &self.queue_families {
+            let present = match family.present_supported {
+                Some(true) => ", present",
+                _ => ""
+            };
+            writeln!(
+                text, "    [{}] {} queues, {}{}, {} timestamp bits",
+                family.index, family.queue_count, family.flags, present, family.timestamp_valid_bits
+            ).unwrap();
+        }
+
+        writeln!(text, "  Extensions ({}):", self.extensions.len()).unwrap();
+        for extension in &self.extensions {
+            writeln!(text, "    {} (rev {})", extension.name, extension.spec_version).unwrap();
+        }
+
+        if let Some(surface) = &self.surface {
+            writeln!(text, "  Surface:").unwrap();
+            writeln!(text, "    Image count: {} - {}", surface.min_image_count, surface.max_image_count).unwrap();
+            writeln!(text, "    Current extent: {}x{}", surface.current_extent[0], surface.current_extent[1]).unwrap();
+            writeln!(text, "    Formats:").unwrap();
+            for format in &surface.formats {
+                writeln!(text, "      {} {}", format.format, format.color_space).unwrap();
+            }
+            writeln!(text, "    Present modes: {}", surface.present_modes.join(", ")).unwrap();
+        }
+    }
+}
+
+
+/// Collects named struct fields into a JSON map. Maps are sorted by name.
+macro_rules! named_values {
+    ($source:expr, |$value:ident| $convert:expr, $($field:ident),+ $(,)?) => {{
+        let mut map = Map::new();
+        $(map.insert(stringify!($field).to_string(), { let $value = &$source.$field; $convert });)+
+        map
+    }};
+}
+
+
+pub fn query_device_info(
+    instance: &ash::Instance, instance_api_version: u32, surface: Option<&SurfaceInfo>
+) -> Result<DeviceInfoReport, VulkanError> {
+    let physical_devices = unsafe {
+        instance.enumerate_physical_devices().context("vkEnumeratePhysicalDevices", "instance")?
+    };
+
+    let mut devices = Vec::new();
+    for (index, physical_device) in physical_devices.into_iter().enumerate() {
+        devices.push(query_physical_device_info(instance, instance_api_version, surface, physical_device, index)?);
+    }
+
+    return Ok(DeviceInfoReport { instance_api_version: version_string(instance_api_version), devices });
+}
+
+
+fn query_physical_device_info(
+    instance: &ash::Instance,
+    instance_api_version: u32,
+    surface: Option<&SurfaceInfo>,
+    physical_device: vk::PhysicalDevice,
+    index: usize
+) -> Result<PhysicalDeviceInfo, VulkanError> {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let queue_family_properties = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let extension_properties = unsafe {
+        instance.enumerate_device_extension_properties(physical_device)
+            .context("vkEnumerateDeviceExtensionProperties", "physical device")?
+    };
+
+    let limits = &properties.limits;
+    let mut limits = named_values!(limits, |value| serde_json::json!(value),
+        max_image_dimension1_d, max_image_dimension2_d, max_image_dimension3_d, max_image_dimension_cube,
+        max_image_array_layers, max_texel_buffer_elements, max_uniform_buffer_range, max_storage_buffer_range,
+        max_push_constants_size, max_memory_allocation_count, max_sampler_allocation_count,
+        buffer_image_granularity, max_bound_descriptor_sets,
+        max_per_stage_descriptor_samplers, max_per_stage_descriptor_uniform_buffers,
+        max_per_stage_descriptor_storage_buffers, max_per_stage_descriptor_sampled_images,
+        max_per_stage_descriptor_storage_images, max_per_stage_resources,
+        max_descriptor_set_samplers, max_descriptor_set_uniform_buffers, max_descriptor_set_storage_buffers,
+        max_descriptor_set_sampled_images, max_descriptor_set_storage_images,
+        max_vertex_input_attributes, max_vertex_input_bindings,
+        max_fragment_output_attachments, max_compute_shared_memory_size,
+        max_compute_work_group_count, max_compute_work_group_invocations, max_compute_work_group_size,
+        max_draw_indexed_index_value, max_draw_indirect_count,
+        max_sampler_lod_bias, max_sampler_anisotropy, max_viewports, max_viewport_dimensions,
+        min_memory_map_alignment, min_texel_buffer_offset_alignment,
+        min_uniform_buffer_offset_alignment, min_storage_buffer_offset_alignment,
+        max_framebuffer_width, max_framebuffer_height, max_framebuffer_layers,
+        max_color_attachments, timestamp_compute_and_graphics, timestamp_period,
+        optimal_buffer_copy_offset_alignment, optimal_buffer_copy_row_pitch_alignment, non_coherent_atom_size
+    );
+    // Sample count flags are more useful by name than as raw bits
+    limits.insert(
+        "framebuffer_color_sample_counts".to_string(),
+        Value::String(format!("{:?}", properties.limits.framebuffer_color_sample_counts))
+    );
+    limits.insert(
+        "framebuffer_depth_sample_counts".to_string(),
+        Value::String(format!("{:?}", properties.limits.framebuffer_depth_sample_counts))
+    );
+
+    let api_version = effective_api_version(instance_api_version, properties.api_version);
+    let supported_features = query_supported_features(instance, physical_device, api_version)?;
+    let base = &supported_features.base;
+    let mut features = named_values!(base, |value| Value::Bool(*value == vk::TRUE),
+        robust_buffer_access, full_draw_index_uint32, image_cube_array, independent_blend,
+        geometry_shader, tessellation_shader, sample_rate_shading, dual_src_blend, logic_op,
+        multi_draw_indirect, draw_indirect_first_instance, depth_clamp, depth_bias_clamp, fill_mode_non_solid,
+        depth_bounds, wide_lines, large_points, alpha_to_one, multi_viewport, sampler_anisotropy,
+        texture_compression_etc2, texture_compression_astc_ldr, texture_compression_bc,
+        occlusion_query_precise, pipeline_statistics_query,
+        vertex_pipeline_stores_and_atomics, fragment_stores_and_atomics,
+        shader_tessellation_and_geometry_point_size, shader_image_gather_extended,
+        shader_storage_image_extended_formats, shader_storage_image_multisample,
+        shader_storage_image_read_without_format, shader_storage_image_write_without_format,
+        shader_uniform_buffer_array_dynamic_indexing, shader_sampled_image_array_dynamic_indexing,
+        shader_storage_buffer_array_dynamic_indexing, shader_storage_image_array_dynamic_indexing,
+        shader_clip_distance, shader_cull_distance, shader_float64, shader_int64, shader_int16,
+        shader_resource_residency, shader_resource_min_lod, sparse_binding,
+        sparse_residency_buffer, sparse_residency_image2_d, sparse_residency_image3_d,
+        sparse_residency2_samples, sparse_residency4_samples, sparse_residency8_samples,
+        sparse_residency16_samples, sparse_residency_aliased, variable_multisample_rate, inherited_queries
+    );
+    for feature in REPORTED_DEVICE_FEATURES {
+        features.insert(format!("{:?}", feature), Value::Bool(supported_features.supports(feature)));
+    }
+
+    let memory_heaps = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].iter()
+        .enumerate()
+        .map(|(i, heap)| MemoryHeapInfo { index: i as u32, size: heap.size, flags: format!("{:?}", heap.flags) })
+        .collect();
+    let memory_types = memory_properties.memory_types[..memory_properties.memory_type_count as usize].iter()
+        .enumerate()
+        .map(|(i, memory_type)| MemoryTypeInfo {
+            index: i as u32,
+            heap_index: memory_type.heap_index,
+            flags: format!("{:?}", memory_type.property_flags)
+        })
+        .collect();
+
+    let unique_queue_families = get_unique_queue_families(instance, surface, physical_device)?;
+    let queue_families = unique_queue_families.iter()
+        .map(|family| QueueFamilyInfo {
+            index: family.index,
+            queue_count: family.queue_count,
+            // The full flags, QueueFamily only keeps the graphics, compute and transfer bits
+            flags: format!("{:?}", queue_family_properties[family.index as usize].queue_flags),
+            timestamp_valid_bits: queue_family_properties[family.index as usize].timestamp_valid_bits,
+            present_supported: surface.map(|_| family.present_supported)
+        })
+        .collect();
+
+    let extensions = extension_properties.iter()
+        .map(|e| ExtensionInfo {
+            name: unsafe { CStr::from_ptr(e.extension_name.as_ptr()) }.to_string_lossy().into_owned(),
+            spec_version: e.spec_version
+        })
+        .collect();
+
+    let surface = match surface {
+        Some(surface) => Some(query_surface_support(surface, physical_device)?),
+        None => None
+    };
+
+    return Ok(PhysicalDeviceInfo {
+        index,
+        name: unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned(),
+        device_type: format!("{:?}", properties.device_type),
+        api_version: version_string(properties.api_version),
+        driver_version: properties.driver_version,
+        vendor_id: properties.vendor_id,
+        device_id: properties.device_id,
+        limits,
+        features,
+        memory_heaps,
+        memory_types,
+        queue_families,
+        extensions,
+        surface
+    });
+}
+
+fn query_surface_support(
+    surface: &SurfaceInfo, physical_device: vk::PhysicalDevice
+) -> Result<SurfaceSupportInfo, VulkanError> { unsafe {
+    let capabilities = surface.loader.get_physical_device_surface_capabilities(physical_device, surface.handle)
+        .context("vkGetPhysicalDeviceSurfaceCapabilitiesKHR", "surface")?;
+    let formats = surface.loader.get_physical_device_surface_formats(physical_device, surface.handle)
+        .context("vkGetPhysicalDeviceSurfaceFormatsKHR", "surface")?;
+    let present_modes = surface.loader.get_physical_device_surface_present_modes(physical_device, surface.handle)
+        .context("vkGetPhysicalDeviceSurfacePresentModesKHR", "surface")?;
+
+    return Ok(SurfaceSupportInfo {
+        min_image_count: capabilities.min_image_count,
+        max_image_count: capabilities.max_image_count,
+        current_extent: [capabilities.current_extent.width, capabilities.current_extent.height],
+        formats: formats.iter()
+            .map(|f| SurfaceFormatInfo { format: format!("{:?}", f.format), color_space: format!("{:?}", f.color_space) })
+            .collect(),
+        present_modes: present_modes.iter().map(|mode| format!("{:?}", mode)).collect()
+    });
+} }
+
+fn version_string(version: u32) -> String {
+    return format!(
+        "{}.{}.{}",
+        vk::api_version_major(version), vk::api_version_minor(version), vk::api_version_patch(version)
+    );
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+use std::path::PathBuf;
+use ash::vk;
+use crate::vulkan_core::bindless::BindlessResourceType;
+use crate::vulkan_core::features::DeviceFeature;
+use crate::vulkan_core::memory_usage::MemoryUsage;
+
+
+#[derive(Debug)]
+pub enum VulkanError {
+    /// A Vulkan call returned an error code.
+    Call { function: &'static str, object: String, result: vk::Result },
+    /// The Vulkan loader library could not be found or loaded.
+    Loading(String),
+    /// The loader only offers this instance version, below `MIN_API_VERSION`.
+    UnsupportedApiVersion(u32),
+    MissingInstanceLayer(String),
+    MissingInstanceExtension(String),
+    MissingDeviceExtension(String),
+    MissingDeviceFeatures(Vec<DeviceFeature>),
+    NoPhysicalDevice,
+    /// Every physical device was rejected, one reason per device.
+    NoSuitablePhysicalDevice(Vec<String>),
+    DeviceOverrideNotFound(String),
+    NoSuitableQueueFamily(&'static str),
+    NoSuitableMemoryType { type_bits: u32, usage: MemoryUsage },
+    /// A buffer that has to be mapped ended up in memory the host can't see.
+    NotHostVisible(String),
+    /// An index or range past the end of a resource.
+    OutOfBounds { name: String, reason: String },
+    /// The mapped memory of a resource isn't aligned to `alignment` bytes of its element type.
+    Misaligned { name: String, alignment: usize },
+    /// The current frame's region of the frame ring buffer has less than `requested` bytes left.
+    FrameRingBufferFull { requested: vk::DeviceSize, remaining: vk::DeviceSize },
+    NoSuitableSurfaceFormat,
+    /// Work read back from the GPU doesn't match what the CPU expects.
+    WrongResult(String),
+    /// A validation error recorded by a `DebugMessenger` with `fail_on_error`.
+    Validation { message_id: String, message: String },
+    ShaderFile { path: PathBuf, source: std::io::Error },
+    TextureFile { path: PathBuf, source: std::io::Error },
+    /// The texture file is truncated or its contents contradict its header.
+    CorruptTexture { name: String, reason: String },
+    /// A valid texture file using a feature the loader doesn't handle, like a supercompressed KTX2.
+    UnsupportedTexture { name: String, reason: String },
+    /// The device can't use `format` for `required` in optimal tiling.
+    UnsupportedFormat { format: vk::Format, required: vk::FormatFeatureFlags },
+    /// A depth view was requested for an image of a color format.
+    NoDepthAspect { name: String, format: vk::Format },
+    /// Every index of this bindless array is registered or waiting for release.
+    BindlessTableFull { resource_type: BindlessResourceType, capacity: u32 },
+}
+
+impl VulkanError {
+    pub fn result(&self) -> Option<vk::Result> {
+        return match self {
+            VulkanError::Call { result, .. } => Some(*result),
+            _ => None
+        };
+    }
+
+    pub fn is_out_of_date(&self) -> bool {
+        return self.result() == Some(vk::Result::ERROR_OUT_OF_DATE_KHR);
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        return self.result() == Some(vk::Result::ERROR_DEVICE_LOST);
+    }
+
+    pub fn is_out_of_memory(&self) -> bool {
+        return matches!(
+            self.result(), Some(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY) | Some(vk::Result::ERROR_OUT_OF_HOST_MEMORY)
+        );
+    }
+}
+
+impl fmt::Display for VulkanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VulkanError::Call { function, object, result } =>
+                write!(f, "{} failed for {}: {}", function, object, result),
+            VulkanError::Loading(reason) => write!(f, "failed to load Vulkan: {}", reason),
+            VulkanError::UnsupportedApiVersion(version) => write!(
+                f, "Vulkan {}.{} is not supported, at least 1.2 is required",
+                vk::api_version_major(*version), vk::api_version_minor(*version)
+            ),
+            VulkanError::MissingInstanceLayer(name) => write!(f, "missing instance layer {}", name),
+            VulkanError::MissingInstanceExtension(name) => write!(f, "missing instance extension {}", name),
+            VulkanError::MissingDeviceExtension(name) => write!(f, "missing device extension {}", name),
+            VulkanError::MissingDeviceFeatures(features) => write!(f, "missing required device features {:?}", features),
+            VulkanError::NoPhysicalDevice => write!(f, "no Vulkan capable physical device found"),
+            VulkanError::NoSuitablePhysicalDevice(reasons) =>
+                write!(f, "no suitable physical device: {}", reasons.join("; ")),
+            VulkanError::DeviceOverrideNotFound(device_override) =>
+                write!(f, "no physical device matches override {}", device_override),
+            VulkanError::NoSuitableQueueFamily(purpose) => write!(f, "no queue family suitable for {}", purpose),
+            VulkanError::NoSuitableMemoryType { type_bits, usage } =>
+                write!(f, "no memory type in bits {:#b} suitable for {:?}", type_bits, usage),
+            VulkanError::NotHostVisible(name) => write!(f, "{} is not in host-visible memory", name),
+            VulkanError::OutOfBounds { name, reason } => write!(f, "out of bounds access to {}: {}", name, reason),
+            VulkanError::Misaligned { name, alignment } =>
+                write!(f, "mapped memory of {} is not aligned to the {} bytes of its element type", name, alignment),
+            VulkanError::FrameRingBufferFull { requested, remaining } =>
+                write!(f, "frame ring buffer is full: {} bytes requested, {} remaining this frame", requested, remaining),
+            VulkanError::NoSuitableSurfaceFormat => write!(f, "surface supports none of the preferred formats"),
+            VulkanError::WrongResult(reason) => write!(f, "wrong result: {}", reason),
+            VulkanError::Validation { message_id, message } => write!(f, "validation error {}: {}", message_id, message),
+            VulkanError::ShaderFile { path, source } =>
+                write!(f, "failed to read shader {}: {}", path.display(), source),
+            VulkanError::TextureFile { path, source } =>
+                write!(f, "failed to read texture {}: {}", path.display(), source),
+            VulkanError::CorruptTexture { name, reason } => write!(f, "corrupt texture {}: {}", name, reason),
+            VulkanError::UnsupportedTexture { name, reason } => write!(f, "unsupported texture {}: {}", name, reason),
+            VulkanError::UnsupportedFormat { format, required } =>
+                write!(f, "format {:?} doesn't support {:?} with optimal tiling", format, required),
+            VulkanError::NoDepthAspect { name, format } => write!(f, "{} has no depth aspect, its format is {:?}", name, format),
+            VulkanError::BindlessTableFull { resource_type, capacity } =>
+                write!(f, "bindless table is full: all {} {:?} indices are in use", capacity, resource_type),
+        }
+    }
+}
+
+impl std::error::Error for VulkanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            VulkanError::ShaderFile { source, .. } => Some(source),
+            VulkanError::TextureFile { source, .. } => Some(source),
+            _ => None
+        };
+    }
+}
+
+
+/// Attaches the failing function and the object it operated on to a raw `vk::Result`.
+pub trait VkResultExt<T> {
+    fn context(self, function: &'static str, object: &str) -> Result<T, VulkanError>;
+}
+
+impl<T> VkResultExt<T> for Result<T, vk::Result> {
+    fn context(self, function: &'static str, object: &str) -> Result<T, VulkanError> {
+        return self.map_err(|result| VulkanError::Call { function, object: object.to_string(), result });
+    }
+}
@@ -0,0 +1,321 @@
+use std::ffi::CStr;
+use ash::vk;
+use crate::vulkan_core::api_version::ApiPath;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceFeature {
+    SamplerAnisotropy,
+    SampleRateShading,
+    MultiDrawIndirect,
+    ShaderDrawParameters,
+    /// The subset of descriptor indexing needed for bindless resource arrays.
+    DescriptorIndexing,
+    TimelineSemaphore,
+    DynamicRendering,
+    Synchronization2,
+    /// Buffers with SHADER_DEVICE_ADDRESS usage, for `buffer_reference` in shaders.
+    BufferDeviceAddress,
+    /// BC1 to BC7 block-compressed textures.
+    TextureCompressionBc,
+    /// ASTC LDR block-compressed textures, mostly found on mobile and integrated GPUs.
+    TextureCompressionAstcLdr,
+    /// Storage image writes without a format qualifier, used by the compute mipmap fallback
+    /// and the bindless storage image array.
+    StorageImageWriteWithoutFormat,
+    /// `nonuniformEXT` indices into arrays of storage images and storage buffers, like the bindless ones.
+    StorageArrayNonUniformIndexing
+}
+
+
+pub struct DeviceFeatureRequirements {
+    pub required: Vec<DeviceFeature>,
+    pub optional: Vec<DeviceFeature>
+}
+
+impl Default for DeviceFeatureRequirements {
+    fn default() -> Self {
+        return DeviceFeatureRequirements {
+            required: vec![
+                DeviceFeature::DynamicRendering,
+                DeviceFeature::Synchronization2,
+                DeviceFeature::DescriptorIndexing,
+                DeviceFeature::MultiDrawIndirect
+            ],
+            optional: vec![
+                DeviceFeature::SamplerAnisotropy,
+                DeviceFeature::SampleRateShading,
+                DeviceFeature::ShaderDrawParameters,
+                DeviceFeature::TimelineSemaphore,
+                DeviceFeature::BufferDeviceAddress,
+                DeviceFeature::TextureCompressionBc,
+                DeviceFeature::TextureCompressionAstcLdr,
+                DeviceFeature::StorageImageWriteWithoutFormat,
+                DeviceFeature::StorageArrayNonUniformIndexing
+            ]
+        };
+    }
+}
+
+
+/// The features that were both requested and supported, and therefore enabled on the device.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnabledFeatures {
+    pub sampler_anisotropy: bool,
+    pub sample_rate_shading: bool,
+    pub multi_draw_indirect: bool,
+    pub shader_draw_parameters: bool,
+    pub descriptor_indexing: bool,
+    pub timeline_semaphore: bool,
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+    pub buffer_device_address: bool,
+    pub texture_compression_bc: bool,
+    pub texture_compression_astc_ldr: bool,
+    pub storage_image_write_without_format: bool,
+    pub storage_array_non_uniform_indexing: bool
+}
+
+impl EnabledFeatures {
+    pub fn is_enabled(&self, feature: DeviceFeature) -> bool {
+        return match feature {
+            DeviceFeature::SamplerAnisotropy => self.sampler_anisotropy,
+            DeviceFeature::SampleRateShading => self.sample_rate_shading,
+            DeviceFeature::MultiDrawIndirect => self.multi_draw_indirect,
+            DeviceFeature::ShaderDrawParameters => self.shader_draw_parameters,
+            DeviceFeature::DescriptorIndexing => self.descriptor_indexing,
+            DeviceFeature::TimelineSemaphore => self.timeline_semaphore,
+            DeviceFeature::DynamicRendering => self.dynamic_rendering,
+            DeviceFeature::Synchronization2 => self.synchronization2,
+            DeviceFeature::BufferDeviceAddress => self.buffer_device_address,
+            DeviceFeature::TextureCompressionBc => self.texture_compression_bc,
+            DeviceFeature::TextureCompressionAstcLdr => self.texture_compression_astc_ldr,
+            DeviceFeature::StorageImageWriteWithoutFormat => self.storage_image_write_without_format,
+            DeviceFeature::StorageArrayNonUniformIndexing => self.storage_array_non_uniform_indexing
+        };
+    }
+
+    fn enable(&mut self, feature: DeviceFeature) {
+        match feature {
+            DeviceFeature::SamplerAnisotropy => self.sampler_anisotropy = true,
+            DeviceFeature::SampleRateShading => self.sample_rate_shading = true,
+            DeviceFeature::MultiDrawIndirect => self.multi_draw_indirect = true,
+            DeviceFeature::ShaderDrawParameters => self.shader_draw_parameters = true,
+            DeviceFeature::DescriptorIndexing => self.descriptor_indexing = true,
+            DeviceFeature::TimelineSemaphore => self.timeline_semaphore = true,
+            DeviceFeature::DynamicRendering => self.dynamic_rendering = true,
+            DeviceFeature::Synchronization2 => self.synchronization2 = true,
+            DeviceFeature::BufferDeviceAddress => self.buffer_device_address = true,
+            DeviceFeature::TextureCompressionBc => self.texture_compression_bc = true,
+            DeviceFeature::TextureCompressionAstcLdr => self.texture_compression_astc_ldr = true,
+            DeviceFeature::StorageImageWriteWithoutFormat => self.storage_image_write_without_format = true,
+            DeviceFeature::StorageArrayNonUniformIndexing => self.storage_array_non_uniform_indexing = true
+        }
+    }
+}
+
+
+/// Raw feature structs as reported by vkGetPhysicalDeviceFeatures2.
+/// On the 1.2 path the KHR dynamic rendering and synchronization2 features are folded into `vk13`.
+pub struct SupportedFeatures {
+    pub api_version: u32,
+    pub base: vk::PhysicalDeviceFeatures,
+    pub vk11: vk::PhysicalDeviceVulkan11Features,
+    pub vk12: vk::PhysicalDeviceVulkan12Features,
+    pub vk13: vk::PhysicalDeviceVulkan13Features
+}
+
+impl SupportedFeatures {
+    pub fn supports(&self, feature: DeviceFeature) -> bool {
+        return match feature {
+            DeviceFeature::SamplerAnisotropy => self.base.sampler_anisotropy == vk::TRUE,
+            DeviceFeature::SampleRateShading => self.base.sample_rate_shading == vk::TRUE,
+            DeviceFeature::MultiDrawIndirect => self.base.multi_draw_indirect == vk::TRUE,
+            DeviceFeature::ShaderDrawParameters => self.vk11.shader_draw_parameters == vk::TRUE,
+            DeviceFeature::DescriptorIndexing =>
+                self.vk12.runtime_descriptor_array == vk::TRUE &&
+                self.vk12.descriptor_binding_partially_bound == vk::TRUE &&
+                self.vk12.descriptor_binding_update_unused_while_pending == vk::TRUE &&
+                self.vk12.shader_sampled_image_array_non_uniform_indexing == vk::TRUE &&
+                self.vk12.descriptor_binding_sampled_image_update_after_bind == vk::TRUE &&
+                self.vk12.descriptor_binding_storage_image_update_after_bind == vk::TRUE &&
+                self.vk12.descriptor_binding_storage_buffer_update_after_bind == vk::TRUE,
+            DeviceFeature::TimelineSemaphore => self.vk12.timeline_semaphore == vk::TRUE,
+            DeviceFeature::DynamicRendering => self.vk13.dynamic_rendering == vk::TRUE,
+            DeviceFeature::Synchronization2 => self.vk13.synchronization2 == vk::TRUE,
+            DeviceFeature::BufferDeviceAddress => self.vk12.buffer_device_address == vk::TRUE,
+            DeviceFeature::TextureCompressionBc => self.base.texture_compression_bc == vk::TRUE,
+            DeviceFeature::TextureCompressionAstcLdr => self.base.texture_compression_astc_ldr == vk::TRUE,
+            DeviceFeature::StorageImageWriteWithoutFormat => self.base.shader_storage_image_write_without_format == vk::TRUE,
+            DeviceFeature::StorageArrayNonUniformIndexing =>
+                self.vk12.shader_storage_image_array_non_uniform_indexing == vk::TRUE &&
+                self.vk12.shader_storage_buffer_array_non_uniform_indexing == vk::TRUE
+        };
+    }
+}
+
+
+/// `api_version` is the effective version, the lower of the instance and the device version.
+pub fn query_supported_features(
+    instance: &ash::Instance, physical_device: vk::PhysicalDevice, api_version: u32
+) -> Result<SupportedFeatures, VulkanError> {
+    let available_extensions = unsafe {
+        instance.enumerate_device_extension_properties(physical_device)
+            .context("vkEnumerateDeviceExtensionProperties", "physical device")?
+    };
+    let extension_supported = |name: &str| available_extensions.iter()
+        .any(|e| unsafe { CStr::from_ptr(e.extension_name.as_ptr()) }.to_bytes() == name.as_bytes());
+    let khr_path = ApiPath::for_version(api_version) == Some(ApiPath::Vulkan12Khr);
+
+    let mut vk11 = vk::PhysicalDeviceVulkan11Features::default();
+    let mut vk12 = vk::PhysicalDeviceVulkan12Features::default();
+    let mut vk13 = vk::PhysicalDeviceVulkan13Features::default();
+    let mut dynamic_rendering_khr = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    let mut synchronization2_khr = vk::PhysicalDeviceSynchronization2Features::default();
+
+    // The per-version structs may only be chained when the device supports that version
+    let mut features2 = vk::PhysicalDeviceFeatures2::builder();
+    if api_version >= vk::API_VERSION_1_2 {
+        features2 = features2.push_next(&mut vk11).push_next(&mut vk12);
+    }
+    if api_version >= vk::API_VERSION_1_3 {
+        features2 = features2.push_next(&mut vk13);
+    }
+    if khr_path && extension_supported("VK_KHR_dynamic_rendering") {
+        features2 = features2.push_next(&mut dynamic_rendering_khr);
+    }
+    if khr_path && extension_supported("VK_KHR_synchronization2") {
+        features2 = features2.push_next(&mut synchronization2_khr);
+    }
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+    let base = features2.features;
+
+    if khr_path {
+        vk13.dynamic_rendering = dynamic_rendering_khr.dynamic_rendering;
+        vk13.synchronization2 = synchronization2_khr.synchronization2;
+    }
+
+    // Don't keep the p_next pointers into this stack frame around
+    vk11.p_next = std::ptr::null_mut();
+    vk12.p_next = std::ptr::null_mut();
+    vk13.p_next = std::ptr::null_mut();
+
+    return Ok(SupportedFeatures { api_version, base, vk11, vk12, vk13 });
+}
+
+pub fn missing_features(supported: &SupportedFeatures, features: &[DeviceFeature]) -> Vec<DeviceFeature> {
+    return features.iter()
+        .filter(|feature| !supported.supports(**feature))
+        .copied()
+        .collect();
+}
+
+/// Enables every requested feature the device supports and fails if a required one is missing.
+pub fn negotiate_features(
+    supported: &SupportedFeatures, requirements: &DeviceFeatureRequirements
+) -> Result<EnabledFeatures, VulkanError> {
+    let missing_required = missing_features(supported, &requirements.required);
+    if !missing_required.is_empty() {
+        return Err(VulkanError::MissingDeviceFeatures(missing_required));
+    }
+
+    let mut enabled = EnabledFeatures::default();
+    for feature in &requirements.required {
+        enabled.enable(*feature);
+    }
+    for feature in &requirements.optional {
+        if supported.supports(*feature) {
+            enabled.enable(*feature);
+        } else {
+            println!("Optional device feature not supported: {:?}", feature);
+        }
+    }
+
+    return Ok(enabled);
+}
+
+
+/// Owns the feature structs handed to vkCreateDevice.
+pub struct DeviceFeatureChain {
+    pub path: ApiPath,
+    pub base: vk::PhysicalDeviceFeatures,
+    pub vk11: vk::PhysicalDeviceVulkan11Features,
+    pub vk12: vk::PhysicalDeviceVulkan12Features,
+    pub vk13: vk::PhysicalDeviceVulkan13Features,
+    pub dynamic_rendering_khr: vk::PhysicalDeviceDynamicRenderingFeatures,
+    pub synchronization2_khr: vk::PhysicalDeviceSynchronization2Features
+}
+
+impl DeviceFeatureChain {
+    pub fn new(enabled: &EnabledFeatures, path: ApiPath) -> Self {
+        let base = vk::PhysicalDeviceFeatures::builder()
+            .sampler_anisotropy(enabled.sampler_anisotropy)
+            .sample_rate_shading(enabled.sample_rate_shading)
+            .multi_draw_indirect(enabled.multi_draw_indirect)
+            .texture_compression_bc(enabled.texture_compression_bc)
+            .texture_compression_astc_ldr(enabled.texture_compression_astc_ldr)
+            .shader_storage_image_write_without_format(enabled.storage_image_write_without_format)
+            .build();
+
+        let vk11 = vk::PhysicalDeviceVulkan11Features::builder()
+            .shader_draw_parameters(enabled.shader_draw_parameters)
+            .build();
+
+        let vk12 = vk::PhysicalDeviceVulkan12Features::builder()
+            .runtime_descriptor_array(enabled.descriptor_indexing)
+            .descriptor_binding_partially_bound(enabled.descriptor_indexing)
+            .descriptor_binding_update_unused_while_pending(enabled.descriptor_indexing)
+            .shader_sampled_image_array_non_uniform_indexing(enabled.descriptor_indexing)
+            .descriptor_binding_sampled_image_update_after_bind(enabled.descriptor_indexing)
+            .descriptor_binding_storage_image_update_after_bind(enabled.descriptor_indexing)
+            .descriptor_binding_storage_buffer_update_after_bind(enabled.descriptor_indexing)
+            .shader_storage_image_array_non_uniform_indexing(enabled.storage_array_non_uniform_indexing)
+            .shader_storage_buffer_array_non_uniform_indexing(enabled.storage_array_non_uniform_indexing)
+            .timeline_semaphore(enabled.timeline_semaphore)
+            .buffer_device_address(enabled.buffer_device_address)
+            .build();
+
+        let vk13 = vk::PhysicalDeviceVulkan13Features::builder()
+            .dynamic_rendering(enabled.dynamic_rendering)
+            .synchronization2(enabled.synchronization2)
+            .build();
+
+        let dynamic_rendering_khr = vk::PhysicalDeviceDynamicRenderingFeatures::builder()
+            .dynamic_rendering(enabled.dynamic_rendering)
+            .build();
+
+        let synchronization2_khr = vk::PhysicalDeviceSynchronization2Features::builder()
+            .synchronization2(enabled.synchronization2)
+            .build();
+
+        return DeviceFeatureChain { path, base, vk11, vk12, vk13, dynamic_rendering_khr, synchronization2_khr };
+    }
+
+    /// Only structs with at least one enabled feature get chained, so devices that
+    /// don't know a struct never see it.
+    pub fn features2(&mut self, enabled: &EnabledFeatures) -> vk::PhysicalDeviceFeatures2Builder<'_> {
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder().features(self.base);
+        if enabled.shader_draw_parameters {
+            features2 = features2.push_next(&mut self.vk11);
+        }
+        if enabled.descriptor_indexing || enabled.storage_array_non_uniform_indexing ||
+            enabled.timeline_semaphore || enabled.buffer_device_address {
+            features2 = features2.push_next(&mut self.vk12);
+        }
+        match self.path {
+            ApiPath::Vulkan13 => if enabled.dynamic_rendering || enabled.synchronization2 {
+                features2 = features2.push_next(&mut self.vk13);
+            }
+            ApiPath::Vulkan12Khr => {
+                if enabled.dynamic_rendering {
+                    features2 = features2.push_next(&mut self.dynamic_rendering_khr);
+                }
+                if enabled.synchronization2 {
+                    features2 = features2.push_next(&mut self.synchronization2_khr);
+                }
+            }
+        }
+        return features2;
+    }
+}
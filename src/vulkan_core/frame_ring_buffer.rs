@@ -0,0 +1,244 @@
+use ash::vk;
+use bytemuck::Pod;
+use crate::vulkan_core::allocator::SharedAllocator;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::VulkanError;
+use crate::vulkan_core::mapped_buffer::{create_mapped_buffer, MappedBuffer};
+use crate::vulkan_core::memory_usage::MemoryUsage;
+use crate::vulkan_core::tools::align_up;
+
+
+/// Enough for index buffers of either index type and vertex data of packed floats.
+const VERTEX_ALIGNMENT: vk::DeviceSize = 16;
+
+
+pub struct FrameRingBufferConfiguration {
+    /// Bytes every frame in flight can hand out.
+    pub size_per_frame: vk::DeviceSize,
+    pub buffer_usage: vk::BufferUsageFlags
+}
+
+impl Default for FrameRingBufferConfiguration {
+    fn default() -> Self {
+        return FrameRingBufferConfiguration {
+            size_per_frame: 1024 * 1024,
+            buffer_usage: vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER |
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER
+        };
+    }
+}
+
+
+/// Decides the alignment of a sub-allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameDataUsage {
+    Uniform,
+    Storage,
+    Vertex
+}
+
+
+/// Valid until the frame it was allocated in has finished on the GPU.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameAllocation {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize
+}
+
+impl FrameAllocation {
+    /// For descriptors of type UNIFORM_BUFFER_DYNAMIC or STORAGE_BUFFER_DYNAMIC written at offset 0.
+    pub fn dynamic_offset(&self) -> u32 {
+        return self.offset as u32;
+    }
+}
+
+
+/// A persistently mapped buffer split into one region per frame in flight. Each frame allocates linearly
+/// from its region, which is reused once that frame's fence has signalled.
+pub struct FrameRingBuffer {
+    buffer: MappedBuffer<u8>,
+    regions: FrameRegions
+}
+
+/// The offsets of a `FrameRingBuffer`, kept apart from the buffer.
+struct FrameRegions {
+    frame_size: vk::DeviceSize,
+    frames_in_flight: u32,
+    frame_in_flight_index: u32,
+    /// Next free byte, relative to the start of the current frame's region.
+    cursor: vk::DeviceSize,
+    uniform_alignment: vk::DeviceSize,
+    storage_alignment: vk::DeviceSize
+}
+
+
+pub fn create_frame_ring_buffer(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    allocator: &SharedAllocator,
+    limits: &vk::PhysicalDeviceLimits,
+    frames_in_flight: u32,
+    config: &FrameRingBufferConfiguration
+) -> Result<FrameRingBuffer, VulkanError> {
+    let uniform_alignment = limits.min_uniform_buffer_offset_alignment.max(1);
+    let storage_alignment = limits.min_storage_buffer_offset_alignment.max(1);
+    // Every region has to start aligned for each usage, the alignments are powers of two
+    let region_alignment = uniform_alignment.max(storage_alignment).max(VERTEX_ALIGNMENT);
+    let frame_size = align_up(config.size_per_frame, region_alignment);
+
+    let buffer = create_mapped_buffer::<u8>(
+        device, debug_utils, allocator, (frame_size * frames_in_flight as vk::DeviceSize) as usize,
+        MemoryUsage::CpuToGpu, config.buffer_usage, "frame_ring_buffer"
+    )?;
+
+    let regions = FrameRegions {
+        frame_size,
+        frames_in_flight,
+        frame_in_flight_index: 0,
+        cursor: 0,
+        uniform_alignment,
+        storage_alignment
+    };
+    return Ok(FrameRingBuffer { buffer, regions });
+}
+
+
+impl FrameRegions {
+    fn remaining(&self) -> vk::DeviceSize {
+        return self.frame_size - self.cursor;
+    }
+
+    fn begin_frame(&mut self, frame_in_flight_index: u32) {
+        debug_assert!(frame_in_flight_index < self.frames_in_flight);
+        self.frame_in_flight_index = frame_in_flight_index;
+        self.cursor = 0;
+    }
+
+    fn alignment(&self, usage: FrameDataUsage) -> vk::DeviceSize {
+        return match usage {
+            FrameDataUsage::Uniform => self.uniform_alignment,
+            FrameDataUsage::Storage => self.storage_alignment,
+            FrameDataUsage::Vertex => VERTEX_ALIGNMENT
+        };
+    }
+
+    /// Returns the offset into the whole buffer.
+    fn allocate(&mut self, size: vk::DeviceSize, usage: FrameDataUsage) -> Result<vk::DeviceSize, VulkanError> {
+        let start = align_up(self.cursor, self.alignment(usage));
+        if start + size > self.frame_size {
+            return Err(VulkanError::FrameRingBufferFull { requested: size, remaining: self.remaining() });
+        }
+        self.cursor = start + size;
+
+        let region_start = self.frame_in_flight_index as vk::DeviceSize * self.frame_size;
+        return Ok(region_start + start);
+    }
+}
+
+
+impl FrameRingBuffer {
+    pub fn handle(&self) -> vk::Buffer {
+        return self.buffer.handle();
+    }
+
+    /// Bytes still free in the current frame's region.
+    pub fn remaining(&self) -> vk::DeviceSize {
+        return self.regions.remaining();
+    }
+
+    /// Reclaims the region of `frame_in_flight_index`. Only call once that frame's fence has signalled,
+    /// the render base does so in `prepare_frame`.
+    pub fn begin_frame(&mut self, frame_in_flight_index: u32) {
+        self.regions.begin_frame(frame_in_flight_index);
+    }
+
+    pub fn alignment(&self, usage: FrameDataUsage) -> vk::DeviceSize {
+        return self.regions.alignment(usage);
+    }
+
+    pub fn allocate(&mut self, size: vk::DeviceSize, usage: FrameDataUsage) -> Result<FrameAllocation, VulkanError> {
+        let offset = self.regions.allocate(size, usage)?;
+        return Ok(FrameAllocation { buffer: self.buffer.handle(), offset, size });
+    }
+
+    /// The mapped bytes of an allocation from the current frame. Writes need `flush` on non-coherent memory.
+    pub fn mapped_slice(&mut self, allocation: &FrameAllocation) -> &mut [u8] {
+        let range = allocation.offset as usize..(allocation.offset + allocation.size) as usize;
+        return &mut self.buffer.as_mut_slice()[range];
+    }
+
+    pub fn flush(&self, allocation: &FrameAllocation) -> Result<(), VulkanError> {
+        return self.buffer.flush_range(allocation.offset as usize..(allocation.offset + allocation.size) as usize);
+    }
+
+    /// Allocates, copies `data` in and flushes.
+    pub fn push<T: Pod>(&mut self, data: &[T], usage: FrameDataUsage) -> Result<FrameAllocation, VulkanError> {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let allocation = self.allocate(bytes.len() as vk::DeviceSize, usage)?;
+        self.mapped_slice(&allocation).copy_from_slice(bytes);
+        self.flush(&allocation)?;
+        return Ok(allocation);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regions(frame_size: vk::DeviceSize, frames_in_flight: u32) -> FrameRegions {
+        return FrameRegions {
+            frame_size,
+            frames_in_flight,
+            frame_in_flight_index: 0,
+            cursor: 0,
+            uniform_alignment: 256,
+            storage_alignment: 64
+        };
+    }
+
+    #[test]
+    fn mixed_alignments_pad_between_allocations() {
+        let mut regions = regions(1024, 2);
+        let offsets = [
+            regions.allocate(4, FrameDataUsage::Vertex).unwrap(),
+            regions.allocate(100, FrameDataUsage::Uniform).unwrap(),
+            regions.allocate(12, FrameDataUsage::Storage).unwrap(),
+            regions.allocate(8, FrameDataUsage::Vertex).unwrap(),
+            regions.allocate(1, FrameDataUsage::Uniform).unwrap()
+        ];
+
+        assert_eq!(offsets, [0, 256, 384, 400, 512]);
+        assert_eq!(regions.remaining(), 511);
+    }
+
+    #[test]
+    fn full_region_reports_what_is_left() {
+        let mut regions = regions(1024, 2);
+        regions.allocate(700, FrameDataUsage::Vertex).unwrap();
+
+        // 700 is aligned up to 768 first, so only 256 of the 324 remaining bytes are usable
+        let error = regions.allocate(300, FrameDataUsage::Uniform).unwrap_err();
+        assert!(matches!(error, VulkanError::FrameRingBufferFull { requested: 300, remaining: 324 }));
+        // A failed allocation doesn't move the cursor
+        assert_eq!(regions.allocate(256, FrameDataUsage::Uniform).unwrap(), 768);
+        assert_eq!(regions.remaining(), 0);
+        assert!(regions.allocate(1, FrameDataUsage::Vertex).is_err());
+        assert_eq!(regions.allocate(0, FrameDataUsage::Vertex).unwrap(), 1024);
+    }
+
+    #[test]
+    fn each_frame_allocates_from_its_own_region() {
+        let mut regions = regions(1024, 3);
+        for frame_in_flight_index in [0, 1, 2, 0] {
+            regions.begin_frame(frame_in_flight_index);
+            let first = regions.allocate(16, FrameDataUsage::Uniform).unwrap();
+            let second = regions.allocate(16, FrameDataUsage::Uniform).unwrap();
+
+            let region_start = frame_in_flight_index as vk::DeviceSize * 1024;
+            assert_eq!((first, second), (region_start, region_start + 256));
+        }
+    }
+}
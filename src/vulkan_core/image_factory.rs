@@ -0,0 +1,336 @@
+use ash::vk;
+use crate::vulkan_core::allocator::{Allocation, ResourceTiling, SharedAllocator};
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::memory_usage::MemoryUsage;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageKind {
+    Image1D,
+    Image2D,
+    Image3D,
+    /// Six 2D faces per cube, in the order +X, -X, +Y, -Y, +Z, -Z.
+    Cube
+}
+
+
+pub struct VulkanImageConfiguration {
+    pub kind: ImageKind,
+    /// Unused dimensions are 1, depth is only above 1 for 3D images.
+    pub extent: vk::Extent3D,
+    /// Number of cubes for cube images, anything above 1 makes the default view an array.
+    pub array_layers: u32,
+    pub format: vk::Format,
+    /// See `mip_level_count` for a full chain.
+    pub mip_levels: u32,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub tiling: vk::ImageTiling,
+    pub memory_usage: MemoryUsage
+}
+
+impl Default for VulkanImageConfiguration {
+    fn default() -> Self {
+        return VulkanImageConfiguration {
+            kind: ImageKind::Image2D,
+            extent: vk::Extent3D { width: 1, height: 1, depth: 1 },
+            array_layers: 1,
+            format: vk::Format::R8G8B8A8_UNORM,
+            mip_levels: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            tiling: vk::ImageTiling::OPTIMAL,
+            memory_usage: MemoryUsage::GpuOnly
+        };
+    }
+}
+
+
+/// Levels of a full mip chain down to 1x1x1.
+pub fn mip_level_count(extent: vk::Extent3D) -> u32 {
+    let largest = extent.width.max(extent.height).max(extent.depth).max(1);
+    return u32::BITS - largest.leading_zeros();
+}
+
+/// Size of `mip_level`, never below 1 in any dimension.
+pub fn mip_extent(extent: vk::Extent3D, mip_level: u32) -> vk::Extent3D {
+    return vk::Extent3D {
+        width: (extent.width >> mip_level).max(1),
+        height: (extent.height >> mip_level).max(1),
+        depth: (extent.depth >> mip_level).max(1)
+    };
+}
+
+pub fn is_depth_format(format: vk::Format) -> bool {
+    return matches!(
+        format,
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT |
+        vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT
+    );
+}
+
+pub fn has_stencil(format: vk::Format) -> bool {
+    return matches!(
+        format,
+        vk::Format::S8_UINT | vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT
+    );
+}
+
+/// Every aspect of `format`. Views of depth-stencil images that get sampled have to pick one, see `create_depth_view`.
+pub fn format_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    let mut aspect_mask = vk::ImageAspectFlags::empty();
+    if is_depth_format(format) {
+        aspect_mask |= vk::ImageAspectFlags::DEPTH;
+    }
+    if has_stencil(format) {
+        aspect_mask |= vk::ImageAspectFlags::STENCIL;
+    }
+    if aspect_mask.is_empty() {
+        aspect_mask = vk::ImageAspectFlags::COLOR;
+    }
+    return aspect_mask;
+}
+
+
+/// An extra view of a `VulkanImage`. Has to be dropped before the image.
+pub struct VulkanImageView {
+    pub handle: vk::ImageView,
+    pub subresource_range: vk::ImageSubresourceRange,
+    device: SharedDevice
+}
+
+impl Drop for VulkanImageView {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_image_view(self.handle, None) };
+    }
+}
+
+
+pub struct VulkanImage {
+    pub handle: vk::Image,
+    /// Covers every mip level, layer and aspect.
+    pub view: vk::ImageView,
+    pub kind: ImageKind,
+    pub extent: vk::Extent3D,
+    pub format: vk::Format,
+    pub mip_levels: u32,
+    /// All layers, six per cube for cube images.
+    pub array_layers: u32,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    /// Dropped after the image is destroyed, which returns the range to the allocator.
+    pub allocation: Allocation,
+    name: String,
+    debug_utils: DebugUtilsDevice,
+    device: SharedDevice
+}
+
+impl Drop for VulkanImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.view, None);
+            self.device.destroy_image(self.handle, None);
+        }
+    }
+}
+
+
+pub fn create_image(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    allocator: &SharedAllocator,
+    config: &VulkanImageConfiguration,
+    name: &str
+) -> Result<VulkanImage, VulkanError> { unsafe {
+    let (image_type, flags, array_layers) = match config.kind {
+        ImageKind::Image1D => (vk::ImageType::TYPE_1D, vk::ImageCreateFlags::empty(), config.array_layers),
+        ImageKind::Image2D => (vk::ImageType::TYPE_2D, vk::ImageCreateFlags::empty(), config.array_layers),
+        ImageKind::Image3D => (vk::ImageType::TYPE_3D, vk::ImageCreateFlags::empty(), 1),
+        ImageKind::Cube => (vk::ImageType::TYPE_2D, vk::ImageCreateFlags::CUBE_COMPATIBLE, config.array_layers * 6)
+    };
+
+    let image_create_info = vk::ImageCreateInfo::builder()
+        .flags(flags)
+        .image_type(image_type)
+        .format(config.format)
+        .extent(config.extent)
+        .mip_levels(config.mip_levels)
+        .array_layers(array_layers)
+        .samples(config.samples)
+        .tiling(config.tiling)
+        .usage(config.usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+
+    let image_handle = device.create_image(&image_create_info, None)
+        .context("vkCreateImage", name)?;
+    debug_utils.set_name(image_handle, name);
+
+    let tiling = match config.tiling {
+        vk::ImageTiling::LINEAR => ResourceTiling::Linear,
+        _ => ResourceTiling::Optimal
+    };
+    let allocation = match allocator.allocate_for_image(image_handle, tiling, config.memory_usage, name) {
+        Ok(allocation) => allocation,
+        Err(error) => {
+            device.destroy_image(image_handle, None);
+            return Err(error);
+        }
+    };
+
+    let bind_result = device.bind_image_memory(image_handle, allocation.memory, allocation.offset)
+        .context("vkBindImageMemory", name);
+    let view_result = bind_result.and_then(|_| create_view_handle(
+        device, debug_utils, image_handle, config.format,
+        default_view_type(config.kind, array_layers),
+        vk::ImageSubresourceRange {
+            aspect_mask: format_aspect_mask(config.format),
+            base_mip_level: 0,
+            level_count: config.mip_levels,
+            base_array_layer: 0,
+            layer_count: array_layers
+        },
+        &format!("{}.view", name)
+    ));
+    let view = match view_result {
+        Ok(view) => view,
+        Err(error) => {
+            device.destroy_image(image_handle, None);
+            return Err(error);
+        }
+    };
+
+    return Ok(VulkanImage {
+        handle: image_handle,
+        view,
+        kind: config.kind,
+        extent: config.extent,
+        format: config.format,
+        mip_levels: config.mip_levels,
+        array_layers,
+        samples: config.samples,
+        usage: config.usage,
+        allocation,
+        name: name.to_string(),
+        debug_utils: debug_utils.clone(),
+        device: device.clone()
+    });
+} }
+
+
+fn default_view_type(kind: ImageKind, array_layers: u32) -> vk::ImageViewType {
+    return match (kind, array_layers) {
+        (ImageKind::Image1D, 1) => vk::ImageViewType::TYPE_1D,
+        (ImageKind::Image1D, _) => vk::ImageViewType::TYPE_1D_ARRAY,
+        (ImageKind::Image2D, 1) => vk::ImageViewType::TYPE_2D,
+        (ImageKind::Image2D, _) => vk::ImageViewType::TYPE_2D_ARRAY,
+        (ImageKind::Image3D, _) => vk::ImageViewType::TYPE_3D,
+        (ImageKind::Cube, 6) => vk::ImageViewType::CUBE,
+        (ImageKind::Cube, _) => vk::ImageViewType::CUBE_ARRAY
+    };
+}
+
+fn create_view_handle(
+    device: &ash::Device,
+    debug_utils: &DebugUtilsDevice,
+    image: vk::Image,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    subresource_range: vk::ImageSubresourceRange,
+    name: &str
+) -> Result<vk::ImageView, VulkanError> {
+    let image_view_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .subresource_range(subresource_range);
+
+    let image_view = unsafe {
+        device.create_image_view(&image_view_info, None).context("vkCreateImageView", name)?
+    };
+    debug_utils.set_name(image_view, name);
+    return Ok(image_view);
+}
+
+
+impl VulkanImage {
+    pub fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        return format_aspect_mask(self.format);
+    }
+
+    /// Every mip level and layer, for barriers over the whole image.
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        return vk::ImageSubresourceRange {
+            aspect_mask: self.aspect_mask(),
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.array_layers
+        };
+    }
+
+    pub fn mip_extent(&self, mip_level: u32) -> vk::Extent3D {
+        return mip_extent(self.extent, mip_level);
+    }
+
+    pub fn create_view(
+        &self, view_type: vk::ImageViewType, subresource_range: vk::ImageSubresourceRange, name: &str
+    ) -> Result<VulkanImageView, VulkanError> {
+        let handle = create_view_handle(
+            &self.device, &self.debug_utils, self.handle, self.format, view_type, subresource_range,
+            &format!("{}.{}", self.name, name)
+        )?;
+        return Ok(VulkanImageView { handle, subresource_range, device: self.device.clone() });
+    }
+
+    /// A single mip level with all layers, e.g. as a storage image or render target of a downsampling pass.
+    pub fn create_mip_view(&self, mip_level: u32) -> Result<VulkanImageView, VulkanError> {
+        if mip_level >= self.mip_levels {
+            return Err(VulkanError::OutOfBounds {
+                name: self.name.clone(), reason: format!("mip level {} of {}", mip_level, self.mip_levels)
+            });
+        }
+        let subresource_range = vk::ImageSubresourceRange {
+            base_mip_level: mip_level,
+            level_count: 1,
+            ..self.subresource_range()
+        };
+        return self.create_view(
+            default_view_type(self.kind, self.array_layers), subresource_range, &format!("mip_view[{}]", mip_level)
+        );
+    }
+
+    /// A single layer with all mip levels, viewed as a plain 1D or 2D image. For cube images a layer is a face.
+    pub fn create_layer_view(&self, layer: u32) -> Result<VulkanImageView, VulkanError> {
+        if layer >= self.array_layers {
+            return Err(VulkanError::OutOfBounds {
+                name: self.name.clone(), reason: format!("layer {} of {}", layer, self.array_layers)
+            });
+        }
+        let view_type = match self.kind {
+            ImageKind::Image1D => vk::ImageViewType::TYPE_1D,
+            ImageKind::Image2D | ImageKind::Cube => vk::ImageViewType::TYPE_2D,
+            ImageKind::Image3D => vk::ImageViewType::TYPE_3D
+        };
+        let subresource_range = vk::ImageSubresourceRange {
+            base_array_layer: layer,
+            layer_count: 1,
+            ..self.subresource_range()
+        };
+        return self.create_view(view_type, subresource_range, &format!("layer_view[{}]", layer));
+    }
+
+    /// Only the depth aspect, which is what sampling a depth-stencil image needs.
+    pub fn create_depth_view(&self) -> Result<VulkanImageView, VulkanError> {
+        if !is_depth_format(self.format) {
+            return Err(VulkanError::NoDepthAspect { name: self.name.clone(), format: self.format });
+        }
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            ..self.subresource_range()
+        };
+        return self.create_view(default_view_type(self.kind, self.array_layers), subresource_range, "depth_view");
+    }
+}
@@ -0,0 +1,286 @@
+use std::ffi::{c_void, CStr, CString};
+use std::ops::Deref;
+use std::sync::Arc;
+use ash::vk;
+use crate::vulkan_core::surface;
+use crate::vulkan_core::api_version::{negotiate_instance_version, MIN_API_VERSION};
+use crate::vulkan_core::debug::debug_callback;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+
+
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+pub const DEBUG_UTILS_EXTENSION: &str = "VK_EXT_debug_utils";
+const VALIDATION_FEATURES_EXTENSION: &str = "VK_EXT_validation_features";
+
+
+/// Destroys the instance once every owner, including devices, surfaces and messengers, is gone.
+/// Keeps the entry alive, as dropping it unloads the Vulkan library.
+pub struct OwnedInstance {
+    pub entry: ash::Entry,
+    pub handle: ash::Instance
+}
+
+impl Deref for OwnedInstance {
+    type Target = ash::Instance;
+
+    fn deref(&self) -> &ash::Instance {
+        return &self.handle;
+    }
+}
+
+impl Drop for OwnedInstance {
+    fn drop(&mut self) {
+        unsafe { self.handle.destroy_instance(None) };
+    }
+}
+
+pub type SharedInstance = Arc<OwnedInstance>;
+
+
+pub struct InstanceInfo {
+    pub handle: SharedInstance,
+    pub api_version: u32,
+    pub enabled_layers: Vec<String>,
+    pub enabled_extensions: Vec<String>
+}
+
+impl InstanceInfo {
+    pub fn is_extension_enabled(&self, name: &str) -> bool {
+        return self.enabled_extensions.iter().any(|e| e == name);
+    }
+}
+
+
+pub struct InstanceBuilder {
+    application_name: String,
+    required_layers: Vec<String>,
+    optional_layers: Vec<String>,
+    required_extensions: Vec<String>,
+    optional_extensions: Vec<String>,
+    surface_support: bool,
+    validation: bool,
+    optional_validation: bool,
+    gpu_assisted_validation: bool,
+    sync_validation: bool
+}
+
+impl InstanceBuilder {
+    /// Surface support is on and debug builds enable validation when the layer is installed.
+    pub fn new() -> Self {
+        return InstanceBuilder {
+            application_name: "vulkan-rust-example".to_string(),
+            required_layers: Vec::new(),
+            optional_layers: Vec::new(),
+            required_extensions: Vec::new(),
+            optional_extensions: Vec::new(),
+            surface_support: true,
+            validation: false,
+            optional_validation: cfg!(debug_assertions),
+            gpu_assisted_validation: false,
+            sync_validation: false
+        };
+    }
+
+    pub fn application_name(mut self, name: &str) -> Self {
+        self.application_name = name.to_string();
+        return self;
+    }
+
+    pub fn require_layer(mut self, name: &str) -> Self {
+        self.required_layers.push(name.to_string());
+        return self;
+    }
+
+    pub fn optional_layer(mut self, name: &str) -> Self {
+        self.optional_layers.push(name.to_string());
+        return self;
+    }
+
+    pub fn require_extension(mut self, name: &str) -> Self {
+        self.required_extensions.push(name.to_string());
+        return self;
+    }
+
+    pub fn optional_extension(mut self, name: &str) -> Self {
+        self.optional_extensions.push(name.to_string());
+        return self;
+    }
+
+    /// Requires the platform's surface extensions so a window surface can be created.
+    pub fn surface_support(mut self, enabled: bool) -> Self {
+        self.surface_support = enabled;
+        return self;
+    }
+
+    /// Requires the Khronos validation layer and VK_EXT_debug_utils.
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.validation = enabled;
+        return self;
+    }
+
+    /// Enables the Khronos validation layer and VK_EXT_debug_utils if both are available, and carries on without them otherwise.
+    pub fn optional_validation(mut self, enabled: bool) -> Self {
+        self.optional_validation = enabled;
+        return self;
+    }
+
+    /// Only has an effect together with validation and VK_EXT_validation_features.
+    pub fn gpu_assisted_validation(mut self, enabled: bool) -> Self {
+        self.gpu_assisted_validation = enabled;
+        return self;
+    }
+
+    /// Only has an effect together with validation and VK_EXT_validation_features.
+    pub fn sync_validation(mut self, enabled: bool) -> Self {
+        self.sync_validation = enabled;
+        return self;
+    }
+
+    pub fn build(&self, entry: &ash::Entry) -> Result<InstanceInfo, VulkanError> {
+        let application_name = CString::new(self.application_name.as_str()).unwrap();
+        let engine_name = CString::new("FexEngine_Rust_Variant").unwrap();
+
+        let loader_version = match entry.try_enumerate_instance_version().context("vkEnumerateInstanceVersion", "entry")? {
+            // Vulkan 1.1+
+            Some(version) => version,
+            // Vulkan 1.0
+            None => vk::API_VERSION_1_0,
+        };
+        let api_version = negotiate_instance_version(loader_version);
+        if api_version < MIN_API_VERSION {
+            return Err(VulkanError::UnsupportedApiVersion(api_version));
+        }
+
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(&application_name)
+            .application_version(vk::make_version(0, 0, 1))
+            .engine_name(&engine_name)
+            .engine_version(vk::make_version(0, 0, 1))
+            .api_version(api_version);
+
+        // Instance Layers
+        let mut required_layers = self.required_layers.clone();
+        let mut optional_layers = self.optional_layers.clone();
+        if self.validation {
+            required_layers.push(VALIDATION_LAYER.to_string());
+        } else if self.optional_validation {
+            optional_layers.push(VALIDATION_LAYER.to_string());
+        }
+        let available_layers = entry.enumerate_instance_layer_properties()
+            .context("vkEnumerateInstanceLayerProperties", "entry")?
+            .iter()
+            .map(|l| unsafe { CStr::from_ptr(l.layer_name.as_ptr()) }.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let enabled_layers = resolve_names(
+            &required_layers, &optional_layers, &available_layers, "layer", VulkanError::MissingInstanceLayer
+        )?;
+
+        // Instance Extensions
+        let mut required_extensions = self.required_extensions.clone();
+        if self.surface_support {
+            required_extensions.extend(
+                surface::required_extension_names().iter().map(|e| e.to_string_lossy().into_owned())
+            );
+        }
+        let mut optional_extensions = self.optional_extensions.clone();
+        let validation_enabled = enabled_layers.iter().any(|l| l == VALIDATION_LAYER);
+        if self.validation {
+            required_extensions.push(DEBUG_UTILS_EXTENSION.to_string());
+        } else if validation_enabled {
+            optional_extensions.push(DEBUG_UTILS_EXTENSION.to_string());
+        }
+        if validation_enabled && (self.gpu_assisted_validation || self.sync_validation) {
+            optional_extensions.push(VALIDATION_FEATURES_EXTENSION.to_string());
+        }
+        let available_extensions = entry.enumerate_instance_extension_properties(None)
+            .context("vkEnumerateInstanceExtensionProperties", "entry")?
+            .iter()
+            .map(|e| unsafe { CStr::from_ptr(e.extension_name.as_ptr()) }.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let enabled_extensions = resolve_names(
+            &required_extensions, &optional_extensions, &available_extensions, "extension",
+            VulkanError::MissingInstanceExtension
+        )?;
+
+        let layer_c_names = to_c_strings(&enabled_layers);
+        let layer_c_ptrs = layer_c_names.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+        let extension_c_names = to_c_strings(&enabled_extensions);
+        let extension_c_ptrs = extension_c_names.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+
+        let message_type = vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION |
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL |
+                vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE;
+
+        let mut debug_create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+            .message_type(message_type)
+            .pfn_user_callback(Some(debug_callback));
+
+        let mut enabled_validation_features = Vec::new();
+        if self.gpu_assisted_validation {
+            enabled_validation_features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+        }
+        if self.sync_validation {
+            enabled_validation_features.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        let mut validation_features_info = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&enabled_validation_features);
+        let debug_utils_enabled = enabled_extensions.iter().any(|e| e == DEBUG_UTILS_EXTENSION);
+        if debug_utils_enabled {
+            validation_features_info.p_next = &mut debug_create_info as *mut _ as *mut c_void;
+        }
+
+        let mut instance_create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_layer_names(&layer_c_ptrs)
+            .enabled_extension_names(&extension_c_ptrs);
+        if enabled_extensions.iter().any(|e| e == VALIDATION_FEATURES_EXTENSION) {
+            instance_create_info = instance_create_info.push_next(&mut validation_features_info);
+        } else if debug_utils_enabled {
+            instance_create_info = instance_create_info.push_next(&mut debug_create_info);
+        }
+
+        let instance_handle = unsafe {
+            entry
+                .create_instance(&instance_create_info, None)
+                .context("vkCreateInstance", "instance")?
+        };
+
+        let handle = Arc::new(OwnedInstance { entry: entry.clone(), handle: instance_handle });
+        return Ok(InstanceInfo { handle, api_version, enabled_layers, enabled_extensions });
+    }
+}
+
+
+/// Fails on the first missing required name and reports which optional names could be enabled.
+fn resolve_names(
+    required: &[String],
+    optional: &[String],
+    available: &[String],
+    kind: &str,
+    missing_error: fn(String) -> VulkanError
+) -> Result<Vec<String>, VulkanError> {
+    let mut enabled: Vec<String> = Vec::new();
+    for name in required {
+        if !available.contains(name) {
+            return Err(missing_error(name.clone()));
+        }
+        if !enabled.contains(name) { enabled.push(name.clone()); }
+    }
+
+    for name in optional {
+        if enabled.contains(name) { continue; }
+        if available.contains(name) {
+            println!("Enabled optional instance {}: {}", kind, name);
+            enabled.push(name.clone());
+        } else {
+            println!("Optional instance {} not available: {}", kind, name);
+        }
+    }
+
+    return Ok(enabled);
+}
+
+fn to_c_strings(names: &[String]) -> Vec<CString> {
+    return names.iter().map(|n| CString::new(n.as_str()).unwrap()).collect();
+}
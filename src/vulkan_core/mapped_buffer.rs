@@ -0,0 +1,119 @@
+use std::marker::PhantomData;
+use std::ops::Range;
+use ash::vk;
+use bytemuck::Pod;
+use crate::vulkan_core::allocator::SharedAllocator;
+use crate::vulkan_core::buffer_factory::{create_buffer, VulkanBuffer, VulkanBufferConfiguration};
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::VulkanError;
+use crate::vulkan_core::memory_usage::MemoryUsage;
+
+
+/// A host-visible buffer of `len` elements that stays mapped for its whole lifetime.
+/// Writes through `write` and `write_at` are flushed, direct slice writes need `flush_range`.
+pub struct MappedBuffer<T: Pod> {
+    pub buffer: VulkanBuffer,
+    len: usize,
+    mapped: *mut T,
+    name: String,
+    _element: PhantomData<T>
+}
+
+
+pub fn create_mapped_buffer<T: Pod>(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    allocator: &SharedAllocator,
+    len: usize,
+    memory_usage: MemoryUsage,
+    buffer_usage: vk::BufferUsageFlags,
+    name: &str
+) -> Result<MappedBuffer<T>, VulkanError> {
+    let config = VulkanBufferConfiguration {
+        size: (len.max(1) * std::mem::size_of::<T>()) as vk::DeviceSize,
+        memory_usage,
+        buffer_usage
+    };
+    let buffer = create_buffer(device, debug_utils, allocator, &config, name)?;
+
+    let mapped = buffer.allocation.mapped_ptr()
+        .ok_or_else(|| VulkanError::NotHostVisible(name.to_string()))?
+        .cast::<T>();
+    // Allocations are only aligned to the buffer's memory requirements, which a driver may report below align_of::<T>()
+    let alignment = std::mem::align_of::<T>();
+    if !(mapped as usize).is_multiple_of(alignment) {
+        return Err(VulkanError::Misaligned { name: name.to_string(), alignment });
+    }
+
+    return Ok(MappedBuffer { buffer, len, mapped, name: name.to_string(), _element: PhantomData });
+}
+
+
+impl<T: Pod> MappedBuffer<T> {
+    pub fn handle(&self) -> vk::Buffer {
+        return self.buffer.handle;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    pub fn size_bytes(&self) -> vk::DeviceSize {
+        return (self.len * std::mem::size_of::<T>()) as vk::DeviceSize;
+    }
+
+    /// The GPU may be reading the memory, only touch elements it is done with.
+    pub fn as_slice(&self) -> &[T] {
+        return unsafe { std::slice::from_raw_parts(self.mapped, self.len) };
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        return unsafe { std::slice::from_raw_parts_mut(self.mapped, self.len) };
+    }
+
+    /// Writes `data` to the start of the buffer.
+    pub fn write(&mut self, data: &[T]) -> Result<(), VulkanError> {
+        self.check_range(&(0..data.len()))?;
+        self.as_mut_slice()[..data.len()].copy_from_slice(data);
+        return self.flush_range(0..data.len());
+    }
+
+    pub fn write_at(&mut self, index: usize, value: T) -> Result<(), VulkanError> {
+        self.check_range(&(index..index.saturating_add(1)))?;
+        self.as_mut_slice()[index] = value;
+        return self.flush_range(index..index + 1);
+    }
+
+    /// Makes writes to the elements in `range` visible to the device. Does nothing for coherent memory.
+    pub fn flush_range(&self, range: Range<usize>) -> Result<(), VulkanError> {
+        self.check_range(&range)?;
+        let element_size = std::mem::size_of::<T>() as vk::DeviceSize;
+        return self.buffer.allocation.flush(range.start as vk::DeviceSize * element_size, range.len() as vk::DeviceSize * element_size);
+    }
+
+    pub fn flush(&self) -> Result<(), VulkanError> {
+        return self.flush_range(0..self.len);
+    }
+
+    /// Makes device writes to the elements in `range` visible before reading them back.
+    pub fn invalidate_range(&self, range: Range<usize>) -> Result<(), VulkanError> {
+        self.check_range(&range)?;
+        let element_size = std::mem::size_of::<T>() as vk::DeviceSize;
+        return self.buffer.allocation.invalidate(range.start as vk::DeviceSize * element_size, range.len() as vk::DeviceSize * element_size);
+    }
+
+    fn check_range(&self, range: &Range<usize>) -> Result<(), VulkanError> {
+        if range.start > range.end || range.end > self.len {
+            return Err(VulkanError::OutOfBounds {
+                name: self.name.clone(),
+                reason: format!("elements {:?} of {}", range, self.len)
+            });
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,283 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use ash::vk;
+use colored::Colorize;
+use serde::Serialize;
+use crate::vulkan_core::allocator::MemoryAllocator;
+
+
+/// Without VK_EXT_memory_budget the budget is guessed as this share of the heap size.
+const FALLBACK_BUDGET_RATIO: f64 = 0.8;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryReportFormat {
+    Text,
+    Json
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HeapUsage {
+    pub index: u32,
+    pub flags: String,
+    pub size: vk::DeviceSize,
+    /// How much the process should stay below. From VK_EXT_memory_budget, otherwise a share of `size`.
+    pub budget: vk::DeviceSize,
+    /// With VK_EXT_memory_budget the driver's number for the whole process, otherwise `allocated_bytes`.
+    pub usage: vk::DeviceSize,
+    /// Taken from the device by the allocator, in blocks and dedicated allocations.
+    pub allocated_bytes: vk::DeviceSize,
+    /// The part of `allocated_bytes` backing live resources.
+    pub used_bytes: vk::DeviceSize
+}
+
+impl HeapUsage {
+    pub fn usage_ratio(&self) -> f32 {
+        if self.budget == 0 { return 0.0; }
+        return self.usage as f32 / self.budget as f32;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryUsage {
+    pub category: String,
+    pub count: u32,
+    pub bytes: vk::DeviceSize
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResourceUsage {
+    pub name: String,
+    pub category: String,
+    pub size: vk::DeviceSize,
+    pub heap_index: u32,
+    pub memory_type_index: u32,
+    pub dedicated: bool
+}
+
+#[derive(Debug, Serialize)]
+pub struct AllocatorSummary {
+    pub block_count: u32,
+    pub dedicated_allocation_count: u32,
+    pub device_allocation_count: u32,
+    pub allocated_bytes: vk::DeviceSize,
+    pub used_bytes: vk::DeviceSize,
+    pub free_bytes: vk::DeviceSize,
+    pub fragmentation: f32
+}
+
+/// Where the GPU memory of the app goes, per heap, per category and per named resource.
+#[derive(Debug, Serialize)]
+pub struct MemoryReport {
+    /// Whether budget and usage come from VK_EXT_memory_budget.
+    pub budget_extension: bool,
+    pub heaps: Vec<HeapUsage>,
+    pub categories: Vec<CategoryUsage>,
+    /// Largest first.
+    pub resources: Vec<ResourceUsage>,
+    pub allocator: AllocatorSummary
+}
+
+
+pub fn query_heap_usage(
+    instance: &ash::Instance, physical_device: vk::PhysicalDevice, allocator: &MemoryAllocator, budget_extension: bool
+) -> Vec<HeapUsage> {
+    let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut memory_properties2 = vk::PhysicalDeviceMemoryProperties2::builder();
+    if budget_extension {
+        memory_properties2 = memory_properties2.push_next(&mut budget_properties);
+    }
+    // The budget is only refreshed by this call
+    unsafe { instance.get_physical_device_memory_properties2(physical_device, &mut memory_properties2) };
+    let memory_properties = memory_properties2.memory_properties;
+
+    let stats = allocator.stats();
+    let mut heaps: Vec<HeapUsage> = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].iter()
+        .enumerate()
+        .map(|(index, heap)| HeapUsage {
+            index: index as u32,
+            flags: format!("{:?}", heap.flags),
+            size: heap.size,
+            budget: (heap.size as f64 * FALLBACK_BUDGET_RATIO) as vk::DeviceSize,
+            usage: 0,
+            allocated_bytes: 0,
+            used_bytes: 0
+        })
+        .collect();
+
+    for (memory_type_index, memory_type_stats) in stats.memory_types.iter().enumerate() {
+        let heap = &mut heaps[memory_properties.memory_types[memory_type_index].heap_index as usize];
+        heap.allocated_bytes += memory_type_stats.allocated_bytes;
+        heap.used_bytes += memory_type_stats.used_bytes;
+    }
+
+    for heap in &mut heaps {
+        match budget_extension {
+            true => {
+                heap.budget = budget_properties.heap_budget[heap.index as usize];
+                heap.usage = budget_properties.heap_usage[heap.index as usize];
+            }
+            false => heap.usage = heap.allocated_bytes
+        }
+    }
+
+    return heaps;
+}
+
+pub fn create_memory_report(
+    instance: &ash::Instance, physical_device: vk::PhysicalDevice, allocator: &MemoryAllocator, budget_extension: bool
+) -> MemoryReport {
+    let heaps = query_heap_usage(instance, physical_device, allocator, budget_extension);
+    let memory_types = &allocator.memory_properties().memory_types;
+
+    let mut resources: Vec<ResourceUsage> = allocator.resources().into_iter()
+        .map(|record| ResourceUsage {
+            name: record.name,
+            category: format!("{:?}", record.category),
+            size: record.size,
+            heap_index: memory_types[record.memory_type_index as usize].heap_index,
+            memory_type_index: record.memory_type_index,
+            dedicated: record.dedicated
+        })
+        .collect();
+    resources.sort_by_key(|resource| Reverse(resource.size));
+
+    let mut categories: BTreeMap<String, CategoryUsage> = BTreeMap::new();
+    for resource in &resources {
+        let category = categories.entry(resource.category.clone())
+            .or_insert_with(|| CategoryUsage { category: resource.category.clone(), count: 0, bytes: 0 });
+        category.count += 1;
+        category.bytes += resource.size;
+    }
+
+    let stats = allocator.stats();
+    let allocator_summary = AllocatorSummary {
+        block_count: stats.total.block_count,
+        dedicated_allocation_count: stats.total.dedicated_allocation_count,
+        device_allocation_count: stats.device_allocation_count,
+        allocated_bytes: stats.total.allocated_bytes,
+        used_bytes: stats.total.used_bytes,
+        free_bytes: stats.total.free_bytes,
+        fragmentation: stats.total.fragmentation()
+    };
+
+    return MemoryReport {
+        budget_extension,
+        heaps,
+        categories: categories.into_values().collect(),
+        resources,
+        allocator: allocator_summary
+    };
+}
+
+
+fn mib(bytes: vk::DeviceSize) -> f64 {
+    return bytes as f64 / (1024.0 * 1024.0);
+}
+
+impl MemoryReport {
+    pub fn format(&self, format: MemoryReportFormat) -> String {
+        return match format {
+            MemoryReportFormat::Text => self.to_text(),
+            MemoryReportFormat::Json => self.to_json()
+        };
+    }
+
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).unwrap();
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let budget_source = match self.budget_extension {
+            true => "VK_EXT_memory_budget",
+            false => "estimated"
+        };
+
+        writeln!(text, "Memory heaps (budget {}):", budget_source).unwrap();
+        for heap in &self.heaps {
+            writeln!(
+                text, "  [{}] {:.1} / {:.1} MiB ({:.0}%), allocator {:.1} MiB with {:.1} MiB used, heap {:.1} MiB {}",
+                heap.index, mib(heap.usage), mib(heap.budget), heap.usage_ratio() * 100.0,
+                mib(heap.allocated_bytes), mib(heap.used_bytes), mib(heap.size), heap.flags
+            ).unwrap();
+        }
+
+        writeln!(text, "Categories:").unwrap();
+        for category in &self.categories {
+            writeln!(text, "  {}: {} resources, {:.2} MiB", category.category, category.count, mib(category.bytes)).unwrap();
+        }
+
+        writeln!(text, "Resources:").unwrap();
+        for resource in &self.resources {
+            let dedicated = match resource.dedicated {
+                true => ", dedicated",
+                false => ""
+            };
+            writeln!(
+                text, "  {} ({}): {:.2} MiB, heap {}, type {}{}",
+                resource.name, resource.category, mib(resource.size),
+                resource.heap_index, resource.memory_type_index, dedicated
+            ).unwrap();
+        }
+
+        let allocator = &self.allocator;
+        writeln!(
+            text, "Allocator: {} blocks, {} dedicated, {:.1} MiB allocated, {:.1} MiB used, {:.1} MiB free, {:.0}% fragmented",
+            allocator.block_count, allocator.dedicated_allocation_count, mib(allocator.allocated_bytes),
+            mib(allocator.used_bytes), mib(allocator.free_bytes), allocator.fragmentation * 100.0
+        ).unwrap();
+        return text;
+    }
+}
+
+
+pub type MemoryWarningHook = Arc<dyn Fn(&HeapUsage) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct MemoryBudgetConfiguration {
+    /// Share of a heap's budget, above which `warning_hook` gets called.
+    pub warning_threshold: f32,
+    /// Frames between checks, querying the budget isn't free.
+    pub check_interval: u64,
+    pub warning_hook: MemoryWarningHook
+}
+
+impl Default for MemoryBudgetConfiguration {
+    fn default() -> Self {
+        return MemoryBudgetConfiguration {
+            warning_threshold: 0.9,
+            check_interval: 120,
+            warning_hook: Arc::new(|heap: &HeapUsage| println!(
+                "{} heap {} uses {:.1} of {:.1} MiB budget",
+                "Memory warning:".yellow(), heap.index, mib(heap.usage), mib(heap.budget)
+            ))
+        };
+    }
+}
+
+/// Calls the warning hook once when a heap crosses the threshold, and again only after it dropped below.
+pub struct MemoryBudgetWatcher {
+    pub config: MemoryBudgetConfiguration,
+    heaps_over_threshold: Vec<bool>
+}
+
+impl MemoryBudgetWatcher {
+    pub fn new(config: MemoryBudgetConfiguration) -> Self {
+        return MemoryBudgetWatcher { config, heaps_over_threshold: Vec::new() };
+    }
+
+    pub fn check(&mut self, heaps: &[HeapUsage]) {
+        self.heaps_over_threshold.resize(heaps.len(), false);
+        for (heap, was_over) in heaps.iter().zip(self.heaps_over_threshold.iter_mut()) {
+            let is_over = heap.usage_ratio() >= self.config.warning_threshold;
+            if is_over && !*was_over {
+                (self.config.warning_hook)(heap);
+            }
+            *was_over = is_over;
+        }
+    }
+}
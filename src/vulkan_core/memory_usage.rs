@@ -0,0 +1,192 @@
+use ash::vk;
+use crate::vulkan_core::error::VulkanError;
+
+
+/// Without resizable BAR the device-local host-visible heap is a 256 MiB window, too small to fill with uploads.
+const SMALL_BAR_HEAP_SIZE: vk::DeviceSize = 256 * 1024 * 1024;
+
+
+/// What a resource is used for. Picks the memory type instead of raw property flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryUsage {
+    /// Only the GPU touches it: render targets, textures, static meshes.
+    GpuOnly,
+    /// Written by the CPU every now and then, read by the GPU: uniforms, staging, dynamic vertices.
+    CpuToGpu,
+    /// Written by the GPU, read back by the CPU: screenshots, queries, compute results.
+    GpuToCpu,
+    /// Lives on the host, the GPU only copies from or to it.
+    CpuOnly
+}
+
+impl MemoryUsage {
+    /// Types without all of these are never used.
+    pub fn required_flags(&self) -> vk::MemoryPropertyFlags {
+        return match self {
+            MemoryUsage::GpuOnly => vk::MemoryPropertyFlags::empty(),
+            MemoryUsage::CpuToGpu | MemoryUsage::GpuToCpu | MemoryUsage::CpuOnly => vk::MemoryPropertyFlags::HOST_VISIBLE
+        };
+    }
+
+    /// `rebar` is whether a large device-local heap is host-visible, see `rebar_available`.
+    pub fn preferred_flags(&self, rebar: bool) -> vk::MemoryPropertyFlags {
+        return match self {
+            MemoryUsage::GpuOnly => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryUsage::CpuToGpu => match rebar {
+                true => vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_COHERENT,
+                false => vk::MemoryPropertyFlags::HOST_COHERENT
+            },
+            MemoryUsage::GpuToCpu => vk::MemoryPropertyFlags::HOST_CACHED | vk::MemoryPropertyFlags::HOST_COHERENT,
+            MemoryUsage::CpuOnly => vk::MemoryPropertyFlags::HOST_COHERENT
+        };
+    }
+
+    /// Flags that cost something without helping this usage. Only used to rank types.
+    pub fn unwanted_flags(&self) -> vk::MemoryPropertyFlags {
+        let always_unwanted = vk::MemoryPropertyFlags::PROTECTED |
+            vk::MemoryPropertyFlags::LAZILY_ALLOCATED |
+            vk::MemoryPropertyFlags::DEVICE_COHERENT_AMD;
+        return always_unwanted | match self {
+            // Keeps the host-visible device memory free for the usages that need it
+            MemoryUsage::GpuOnly => vk::MemoryPropertyFlags::HOST_VISIBLE,
+            MemoryUsage::CpuToGpu | MemoryUsage::GpuToCpu => vk::MemoryPropertyFlags::empty(),
+            MemoryUsage::CpuOnly => vk::MemoryPropertyFlags::DEVICE_LOCAL
+        };
+    }
+}
+
+
+/// Whether the whole device-local heap can be mapped, either through resizable BAR or because the GPU is integrated.
+pub fn rebar_available(memory_properties: &vk::PhysicalDeviceMemoryProperties) -> bool {
+    let host_visible_device_local = vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE;
+    return memory_properties.memory_types[..memory_properties.memory_type_count as usize].iter()
+        .filter(|memory_type| memory_type.property_flags.contains(host_visible_device_local))
+        .any(|memory_type| memory_properties.memory_heaps[memory_type.heap_index as usize].size > SMALL_BAR_HEAP_SIZE);
+}
+
+
+/// Every usable memory type, best first. Later entries are the fallbacks when allocating from earlier ones fails.
+/// Types whose heap is smaller than `size` are left out.
+pub fn rank_memory_types(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    memory_type_bits: u32,
+    usage: MemoryUsage,
+    size: vk::DeviceSize
+) -> Vec<u32> {
+    let required = usage.required_flags();
+    let preferred = usage.preferred_flags(rebar_available(memory_properties));
+    let unwanted = usage.unwanted_flags();
+
+    let mut candidates: Vec<(u32, (i32, vk::DeviceSize))> = (0..memory_properties.memory_type_count)
+        .filter(|i| memory_type_bits & (1 << i) != 0)
+        .filter_map(|i| {
+            let memory_type = memory_properties.memory_types[i as usize];
+            let heap_size = memory_properties.memory_heaps[memory_type.heap_index as usize].size;
+            if !memory_type.property_flags.contains(required) || heap_size < size {
+                return None;
+            }
+
+            let flags = memory_type.property_flags;
+            let score = (flags & preferred).as_raw().count_ones() as i32 - (flags & unwanted).as_raw().count_ones() as i32;
+            // Bigger heaps break ties, they are less likely to run out
+            return Some((i, (score, heap_size)));
+        })
+        .collect();
+
+    // Stable, so equally good types keep the driver's order
+    candidates.sort_by(|(_, a), (_, b)| b.cmp(a));
+    return candidates.into_iter().map(|(i, _)| i).collect();
+}
+
+pub fn find_memory_type_index(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    memory_requirements: vk::MemoryRequirements,
+    usage: MemoryUsage
+) -> Result<u32, VulkanError> {
+    return rank_memory_types(memory_properties, memory_requirements.memory_type_bits, usage, memory_requirements.size)
+        .first()
+        .copied()
+        .ok_or(VulkanError::NoSuitableMemoryType { type_bits: memory_requirements.memory_type_bits, usage });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: vk::DeviceSize = 1024 * 1024;
+    const GIB: vk::DeviceSize = 1024 * MIB;
+
+    const DEVICE_LOCAL: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+    const HOST_VISIBLE: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_VISIBLE;
+    const HOST_COHERENT: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_COHERENT;
+    const HOST_CACHED: vk::MemoryPropertyFlags = vk::MemoryPropertyFlags::HOST_CACHED;
+
+    fn memory_properties(heaps: &[vk::DeviceSize], types: &[(vk::MemoryPropertyFlags, u32)]) -> vk::PhysicalDeviceMemoryProperties {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: types.len() as u32,
+            memory_heap_count: heaps.len() as u32,
+            ..Default::default()
+        };
+        for (i, size) in heaps.iter().enumerate() {
+            properties.memory_heaps[i] = vk::MemoryHeap { size: *size, flags: vk::MemoryHeapFlags::empty() };
+        }
+        for (i, (property_flags, heap_index)) in types.iter().enumerate() {
+            properties.memory_types[i] = vk::MemoryType { property_flags: *property_flags, heap_index: *heap_index };
+        }
+        return properties;
+    }
+
+    /// Name, memory properties, type bits, usage, size and the expected ranking.
+    type RankCase<'a> = (&'a str, &'a vk::PhysicalDeviceMemoryProperties, u32, MemoryUsage, vk::DeviceSize, Vec<u32>);
+
+    /// A discrete GPU: device-local VRAM, two host types and a device-local host-visible type on `bar_heap_size`.
+    fn discrete_gpu(bar_heap_size: vk::DeviceSize) -> vk::PhysicalDeviceMemoryProperties {
+        return memory_properties(&[8 * GIB, 16 * GIB, bar_heap_size], &[
+            (DEVICE_LOCAL, 0),
+            (HOST_VISIBLE | HOST_COHERENT, 1),
+            (HOST_VISIBLE | HOST_COHERENT | HOST_CACHED, 1),
+            (DEVICE_LOCAL | HOST_VISIBLE | HOST_COHERENT, 2)
+        ]);
+    }
+
+    #[test]
+    fn ranks_memory_types() {
+        let small_bar = discrete_gpu(256 * MIB);
+        let rebar = discrete_gpu(8 * GIB);
+        let no_host_visible = memory_properties(&[8 * GIB], &[(DEVICE_LOCAL, 0), (DEVICE_LOCAL, 0)]);
+
+        let cases: [RankCase; 11] = [
+            ("gpu only", &small_bar, !0, MemoryUsage::GpuOnly, MIB, vec![0, 3, 1, 2]),
+            ("small bar uploads prefer system memory", &small_bar, !0, MemoryUsage::CpuToGpu, MIB, vec![1, 2, 3]),
+            ("rebar uploads prefer the bar", &rebar, !0, MemoryUsage::CpuToGpu, MIB, vec![3, 1, 2]),
+            ("readback prefers cached", &small_bar, !0, MemoryUsage::GpuToCpu, MIB, vec![2, 1, 3]),
+            ("staging avoids device-local", &rebar, !0, MemoryUsage::CpuOnly, MIB, vec![1, 2, 3]),
+            ("type bits", &small_bar, 0b1010, MemoryUsage::GpuOnly, MIB, vec![3, 1]),
+            ("heap smaller than the resource", &small_bar, !0, MemoryUsage::CpuToGpu, 512 * MIB, vec![1, 2]),
+            ("no heap large enough", &small_bar, !0, MemoryUsage::GpuOnly, 32 * GIB, vec![]),
+            ("no host-visible type", &no_host_visible, !0, MemoryUsage::CpuToGpu, MIB, vec![]),
+            ("no host-visible type, gpu only", &no_host_visible, !0, MemoryUsage::GpuOnly, MIB, vec![0, 1]),
+            ("no allowed type", &small_bar, 0, MemoryUsage::GpuOnly, MIB, vec![])
+        ];
+        for (name, properties, type_bits, usage, size, expected) in cases {
+            assert_eq!(rank_memory_types(properties, type_bits, usage, size), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn detects_rebar() {
+        assert!(!rebar_available(&discrete_gpu(256 * MIB)));
+        assert!(rebar_available(&discrete_gpu(8 * GIB)));
+    }
+
+    #[test]
+    fn missing_host_visible_type_is_an_error() {
+        let properties = memory_properties(&[8 * GIB], &[(DEVICE_LOCAL, 0)]);
+        let requirements = vk::MemoryRequirements { size: MIB, alignment: 256, memory_type_bits: 1 };
+
+        let result = find_memory_type_index(&properties, requirements, MemoryUsage::CpuToGpu);
+        assert!(matches!(result, Err(VulkanError::NoSuitableMemoryType { type_bits: 1, usage: MemoryUsage::CpuToGpu })));
+        assert_eq!(find_memory_type_index(&properties, requirements, MemoryUsage::GpuOnly).ok(), Some(0));
+    }
+}
@@ -0,0 +1,465 @@
+use std::path::Path;
+use ash::vk;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::features::EnabledFeatures;
+use crate::vulkan_core::image_factory::{ImageKind, VulkanImage, VulkanImageView};
+use crate::vulkan_core::pipeline::{create_compute_pipeline, ComputePipeline, ComputePipelineConfiguration, PushConstantsLayout};
+use crate::vulkan_core::tools::read_shader_code;
+
+
+/// Matches `local_size_x` and `local_size_y` in shaders/mipmap/downsample.comp.
+const DOWNSAMPLE_WORKGROUP_SIZE: u32 = 8;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipmapMethod {
+    /// A chain of linear vkCmdBlitImage calls, each level from the one above.
+    Blit,
+    /// Averages 2x2 texels in a compute shader, for formats that can't be blitted or linearly filtered.
+    Compute
+}
+
+impl MipmapMethod {
+    /// What the image has to be created with on top of its own usage.
+    pub fn required_usage(&self) -> vk::ImageUsageFlags {
+        return match self {
+            MipmapMethod::Blit => vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+            MipmapMethod::Compute => vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE
+        };
+    }
+}
+
+
+/// Which downsampling shader variant fits a format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TexelType {
+    Float,
+    Uint,
+    Sint
+}
+
+impl TexelType {
+    fn of(format: vk::Format) -> TexelType {
+        return match format {
+            vk::Format::R8_UINT | vk::Format::R8G8_UINT | vk::Format::R8G8B8A8_UINT |
+            vk::Format::R16_UINT | vk::Format::R16G16_UINT | vk::Format::R16G16B16A16_UINT |
+            vk::Format::R32_UINT | vk::Format::R32G32_UINT | vk::Format::R32G32B32A32_UINT |
+            vk::Format::A2B10G10R10_UINT_PACK32 => TexelType::Uint,
+            vk::Format::R8_SINT | vk::Format::R8G8_SINT | vk::Format::R8G8B8A8_SINT |
+            vk::Format::R16_SINT | vk::Format::R16G16_SINT | vk::Format::R16G16B16A16_SINT |
+            vk::Format::R32_SINT | vk::Format::R32G32_SINT | vk::Format::R32G32B32A32_SINT => TexelType::Sint,
+            _ => TexelType::Float
+        };
+    }
+
+    fn index(&self) -> usize {
+        return *self as usize;
+    }
+
+    fn shader_path(&self) -> &'static Path {
+        return Path::new(match self {
+            TexelType::Float => "shaders/mipmap/downsample_float.spv",
+            TexelType::Uint => "shaders/mipmap/downsample_uint.spv",
+            TexelType::Sint => "shaders/mipmap/downsample_sint.spv"
+        });
+    }
+}
+
+
+/// Matches `PushConstants` in shaders/mipmap/downsample.comp.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DownsamplePushConstants {
+    source_size: [i32; 2],
+    destination_size: [i32; 2]
+}
+
+
+/// A layout transition of some of an image's mip levels, and what it waits for.
+struct LevelTransition {
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags
+}
+
+
+/// Views and descriptors used by recorded mipmap commands. Keep it until the command buffer has finished.
+pub struct MipmapScratch {
+    views: Vec<VulkanImageView>,
+    descriptor_pool: vk::DescriptorPool,
+    device: SharedDevice
+}
+
+impl Drop for MipmapScratch {
+    fn drop(&mut self) {
+        if self.descriptor_pool != vk::DescriptorPool::null() {
+            unsafe { self.device.destroy_descriptor_pool(self.descriptor_pool, None) };
+        }
+    }
+}
+
+
+/// Fills mip levels 1 and up from level 0. The compute pipelines are only created the first time they are needed.
+pub struct MipmapGenerator {
+    physical_device: vk::PhysicalDevice,
+    storage_image_write_without_format: bool,
+    set_layout: vk::DescriptorSetLayout,
+    /// Indexed by `TexelType`.
+    pipelines: [Option<ComputePipeline>; 3],
+    debug_utils: DebugUtilsDevice,
+    device: SharedDevice
+}
+
+impl Drop for MipmapGenerator {
+    fn drop(&mut self) {
+        // The pipelines use the set layout
+        for pipeline in &mut self.pipelines {
+            pipeline.take();
+        }
+        unsafe { self.device.destroy_descriptor_set_layout(self.set_layout, None) };
+    }
+}
+
+
+pub fn create_mipmap_generator(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    physical_device: vk::PhysicalDevice,
+    enabled_features: &EnabledFeatures
+) -> Result<MipmapGenerator, VulkanError> {
+    let bindings = [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+    ];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    let set_layout = unsafe {
+        device.create_descriptor_set_layout(&layout_info, None)
+            .context("vkCreateDescriptorSetLayout", "mipmap.set_layout")?
+    };
+    debug_utils.set_name(set_layout, "mipmap.set_layout");
+
+    return Ok(MipmapGenerator {
+        physical_device,
+        storage_image_write_without_format: enabled_features.storage_image_write_without_format,
+        set_layout,
+        pipelines: [None, None, None],
+        debug_utils: debug_utils.clone(),
+        device: device.clone()
+    });
+}
+
+
+impl MipmapGenerator {
+    /// Blits when the format allows it, otherwise the compute fallback, which only handles 2D, array and cube images.
+    pub fn select_method(&self, format: vk::Format, kind: ImageKind) -> Result<MipmapMethod, VulkanError> {
+        let properties = unsafe {
+            self.device.instance().get_physical_device_format_properties(self.physical_device, format)
+        };
+        let features = properties.optimal_tiling_features;
+
+        let blit_features = vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST |
+            vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        if features.contains(blit_features) {
+            return Ok(MipmapMethod::Blit);
+        }
+
+        let compute_features = vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::STORAGE_IMAGE;
+        if features.contains(compute_features) && matches!(kind, ImageKind::Image2D | ImageKind::Cube) &&
+            self.storage_image_write_without_format {
+            return Ok(MipmapMethod::Compute);
+        }
+
+        return Err(VulkanError::UnsupportedFormat { format, required: blit_features });
+    }
+
+    /// Records the generation of every level below 0. Level 0 is in `level_0_layout`, the other levels'
+    /// contents are discarded. Afterwards the whole image is in `final_layout`.
+    /// `image` needs the usage of `method`, see `MipmapMethod::required_usage`.
+    pub fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image: &VulkanImage,
+        method: MipmapMethod,
+        level_0_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout
+    ) -> Result<MipmapScratch, VulkanError> {
+        let mut scratch = MipmapScratch { views: Vec::new(), descriptor_pool: vk::DescriptorPool::null(), device: self.device.clone() };
+        if image.mip_levels < 2 {
+            self.transition_levels(command_buffer, image, 0..1, LevelTransition {
+                old_layout: level_0_layout,
+                new_layout: final_layout,
+                src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+                dst_access_mask: vk::AccessFlags::MEMORY_READ,
+                src_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+                dst_stage: vk::PipelineStageFlags::ALL_COMMANDS
+            });
+            return Ok(scratch);
+        }
+
+        match method {
+            MipmapMethod::Blit => self.record_blits(command_buffer, image, level_0_layout, final_layout),
+            MipmapMethod::Compute => self.record_downsampling(command_buffer, image, level_0_layout, final_layout, &mut scratch)?
+        }
+        return Ok(scratch);
+    }
+
+    fn record_blits(
+        &self, command_buffer: vk::CommandBuffer, image: &VulkanImage, level_0_layout: vk::ImageLayout, final_layout: vk::ImageLayout
+    ) {
+        let levels = image.mip_levels;
+        self.transition_levels(command_buffer, image, 0..1, LevelTransition {
+            old_layout: level_0_layout,
+            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            src_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+            dst_stage: vk::PipelineStageFlags::TRANSFER
+        });
+        self.transition_levels(command_buffer, image, 1..levels, LevelTransition {
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            src_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+            dst_stage: vk::PipelineStageFlags::TRANSFER
+        });
+
+        let offset_of = |extent: vk::Extent3D| vk::Offset3D {
+            x: extent.width as i32, y: extent.height as i32, z: extent.depth as i32
+        };
+        let layers = |mip_level| vk::ImageSubresourceLayers {
+            aspect_mask: image.aspect_mask(),
+            mip_level,
+            base_array_layer: 0,
+            layer_count: image.array_layers
+        };
+
+        for level in 1..levels {
+            let blit = vk::ImageBlit {
+                src_subresource: layers(level - 1),
+                src_offsets: [vk::Offset3D::default(), offset_of(image.mip_extent(level - 1))],
+                dst_subresource: layers(level),
+                dst_offsets: [vk::Offset3D::default(), offset_of(image.mip_extent(level))]
+            };
+            unsafe {
+                self.device.cmd_blit_image(
+                    command_buffer,
+                    image.handle, vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image.handle, vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit], vk::Filter::LINEAR
+                );
+            }
+            // The next blit reads this level
+            self.transition_levels(command_buffer, image, level..level + 1, LevelTransition {
+                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                src_stage: vk::PipelineStageFlags::TRANSFER,
+                dst_stage: vk::PipelineStageFlags::TRANSFER
+            });
+        }
+
+        self.transition_levels(command_buffer, image, 0..levels, LevelTransition {
+            old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_layout: final_layout,
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::MEMORY_READ,
+            src_stage: vk::PipelineStageFlags::TRANSFER,
+            dst_stage: vk::PipelineStageFlags::ALL_COMMANDS
+        });
+    }
+
+    fn record_downsampling(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image: &VulkanImage,
+        level_0_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout,
+        scratch: &mut MipmapScratch
+    ) -> Result<(), VulkanError> {
+        let levels = image.mip_levels;
+        let texel_type = TexelType::of(image.format);
+        let (pipeline_handle, layout_handle) = {
+            let pipeline = self.pipeline(texel_type)?;
+            (pipeline.handle, pipeline.layout_handle)
+        };
+
+        // Cube images are downsampled face by face, as a plain array
+        let level_views = (0..levels)
+            .map(|level| image.create_view(
+                vk::ImageViewType::TYPE_2D_ARRAY,
+                vk::ImageSubresourceRange { base_mip_level: level, level_count: 1, ..image.subresource_range() },
+                &format!("mipmap.level_view[{}]", level)
+            ))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::SAMPLED_IMAGE, descriptor_count: levels - 1 },
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::STORAGE_IMAGE, descriptor_count: levels - 1 }
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(levels - 1)
+            .pool_sizes(&pool_sizes);
+        scratch.descriptor_pool = unsafe {
+            self.device.create_descriptor_pool(&pool_info, None).context("vkCreateDescriptorPool", "mipmap.descriptor_pool")?
+        };
+        let set_layouts = vec![self.set_layout; (levels - 1) as usize];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(scratch.descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_sets = unsafe {
+            self.device.allocate_descriptor_sets(&allocate_info).context("vkAllocateDescriptorSets", "mipmap.descriptor_sets")?
+        };
+
+        for (i, descriptor_set) in descriptor_sets.iter().enumerate() {
+            let source_info = [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: level_views[i].handle,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }];
+            let destination_info = [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: level_views[i + 1].handle,
+                image_layout: vk::ImageLayout::GENERAL
+            }];
+            let writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&source_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&destination_info)
+                    .build()
+            ];
+            unsafe { self.device.update_descriptor_sets(&writes, &[]) };
+        }
+
+        self.transition_levels(command_buffer, image, 0..1, LevelTransition {
+            old_layout: level_0_layout,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            src_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+            dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER
+        });
+        self.transition_levels(command_buffer, image, 1..levels, LevelTransition {
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::GENERAL,
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::SHADER_WRITE,
+            src_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+            dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER
+        });
+
+        unsafe { self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline_handle) };
+        for level in 1..levels {
+            let source_extent = image.mip_extent(level - 1);
+            let destination_extent = image.mip_extent(level);
+            let push_constants = DownsamplePushConstants {
+                source_size: [source_extent.width as i32, source_extent.height as i32],
+                destination_size: [destination_extent.width as i32, destination_extent.height as i32]
+            };
+
+            unsafe {
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer, vk::PipelineBindPoint::COMPUTE, layout_handle,
+                    0, &[descriptor_sets[level as usize - 1]], &[]
+                );
+                self.device.cmd_push_constants(
+                    command_buffer, layout_handle, vk::ShaderStageFlags::COMPUTE, 0, bytemuck::bytes_of(&push_constants)
+                );
+                self.device.cmd_dispatch(
+                    command_buffer,
+                    destination_extent.width.div_ceil(DOWNSAMPLE_WORKGROUP_SIZE),
+                    destination_extent.height.div_ceil(DOWNSAMPLE_WORKGROUP_SIZE),
+                    image.array_layers
+                );
+            }
+            // The next dispatch reads this level
+            self.transition_levels(command_buffer, image, level..level + 1, LevelTransition {
+                old_layout: vk::ImageLayout::GENERAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                src_access_mask: vk::AccessFlags::SHADER_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER
+            });
+        }
+
+        self.transition_levels(command_buffer, image, 0..levels, LevelTransition {
+            old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            new_layout: final_layout,
+            src_access_mask: vk::AccessFlags::SHADER_WRITE,
+            dst_access_mask: vk::AccessFlags::MEMORY_READ,
+            src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            dst_stage: vk::PipelineStageFlags::ALL_COMMANDS
+        });
+
+        scratch.views = level_views;
+        return Ok(());
+    }
+
+    fn pipeline(&mut self, texel_type: TexelType) -> Result<&ComputePipeline, VulkanError> {
+        let slot = &mut self.pipelines[texel_type.index()];
+        if slot.is_none() {
+            let config = ComputePipelineConfiguration {
+                shader_code: read_shader_code(texel_type.shader_path())?,
+                set_layouts: vec![self.set_layout],
+                push_constants_layout: PushConstantsLayout {
+                    size_bytes: std::mem::size_of::<DownsamplePushConstants>() as u32,
+                    offset: 0,
+                    shader_stages: vk::ShaderStageFlags::COMPUTE
+                },
+                spec_constants: vec![]
+            };
+            let name = format!("mipmap.downsample_{:?}", texel_type).to_lowercase();
+            *slot = Some(create_compute_pipeline(&self.device, &self.debug_utils, &config, &name)?);
+        }
+        return Ok(slot.as_ref().unwrap());
+    }
+
+    fn transition_levels(
+        &self, command_buffer: vk::CommandBuffer, image: &VulkanImage, levels: std::ops::Range<u32>, transition: LevelTransition
+    ) {
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(transition.src_access_mask)
+            .dst_access_mask(transition.dst_access_mask)
+            .old_layout(transition.old_layout)
+            .new_layout(transition.new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image.handle)
+            .subresource_range(vk::ImageSubresourceRange {
+                base_mip_level: levels.start,
+                level_count: levels.len() as u32,
+                ..image.subresource_range()
+            })
+            .build();
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer, transition.src_stage, transition.dst_stage, vk::DependencyFlags::empty(), &[], &[], &[barrier]
+            );
+        }
+    }
+}
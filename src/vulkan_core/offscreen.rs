@@ -0,0 +1,50 @@
+use ash::vk;
+use crate::vulkan_core::allocator::SharedAllocator;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::VulkanError;
+use crate::vulkan_core::image_factory::{create_image, VulkanImage, VulkanImageConfiguration};
+
+
+/// Stand-in for a swapchain when rendering without a surface.
+pub struct OffscreenTargets {
+    pub extent: vk::Extent2D,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub color_images: Vec<VulkanImage>,
+    pub depth_image: VulkanImage
+}
+
+
+pub fn create_offscreen_targets(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    allocator: &SharedAllocator,
+    extent: vk::Extent2D,
+    color_format: vk::Format,
+    depth_format: vk::Format,
+    images_total: u32
+) -> Result<OffscreenTargets, VulkanError> {
+    let extent_3d = vk::Extent3D { width: extent.width, height: extent.height, depth: 1 };
+    let color_config = VulkanImageConfiguration {
+        extent: extent_3d,
+        format: color_format,
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::SAMPLED,
+        ..Default::default()
+    };
+
+    let mut color_images = Vec::new();
+    for i in 0..images_total {
+        color_images.push(create_image(device, debug_utils, allocator, &color_config, &format!("offscreen.color[{}]", i))?);
+    }
+
+    let depth_config = VulkanImageConfiguration {
+        extent: extent_3d,
+        format: depth_format,
+        usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        ..Default::default()
+    };
+    let depth_image = create_image(device, debug_utils, allocator, &depth_config, "offscreen.depth")?;
+
+    return Ok(OffscreenTargets { extent, color_format, depth_format, color_images, depth_image });
+}
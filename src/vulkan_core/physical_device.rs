@@ -0,0 +1,269 @@
+use std::ffi::CStr;
+use ash::vk;
+use crate::vulkan_core::SurfaceInfo;
+use crate::vulkan_core::api_version::{effective_api_version, ApiPath, MIN_API_VERSION, TARGET_API_VERSION};
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::features::{missing_features, query_supported_features, DeviceFeature, DeviceFeatureRequirements};
+
+
+/// Set this to a device index or to a part of a device name to force a specific physical device.
+pub const DEVICE_OVERRIDE_ENV_VAR: &str = "VULKAN_DEVICE";
+
+
+#[derive(Clone, Debug)]
+pub enum DeviceOverride {
+    Index(usize),
+    NameSubstring(String)
+}
+
+impl DeviceOverride {
+    pub fn parse(value: &str) -> DeviceOverride {
+        return match value.trim().parse::<usize>() {
+            Ok(index) => DeviceOverride::Index(index),
+            Err(_) => DeviceOverride::NameSubstring(value.trim().to_string())
+        };
+    }
+
+    pub fn from_env() -> Option<DeviceOverride> {
+        return std::env::var(DEVICE_OVERRIDE_ENV_VAR).ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| DeviceOverride::parse(&value));
+    }
+
+    fn matches(&self, candidate: &DeviceCandidate) -> bool {
+        return match self {
+            DeviceOverride::Index(index) => candidate.index == *index,
+            DeviceOverride::NameSubstring(name) => candidate.name.to_lowercase().contains(&name.to_lowercase())
+        };
+    }
+}
+
+
+pub struct PhysicalDeviceRequirements {
+    pub min_api_version: u32,
+    /// The version the instance was created with. Devices can't use anything newer.
+    pub instance_api_version: u32,
+    pub required_extensions: Vec<&'static str>,
+    pub required_features: Vec<DeviceFeature>,
+    /// Every supported optional feature raises the score of a device.
+    pub optional_features: Vec<DeviceFeature>,
+    /// Config override, the environment variable takes precedence over it.
+    pub device_override: Option<DeviceOverride>
+}
+
+impl Default for PhysicalDeviceRequirements {
+    fn default() -> Self {
+        let feature_requirements = DeviceFeatureRequirements::default();
+        return PhysicalDeviceRequirements {
+            min_api_version: MIN_API_VERSION,
+            instance_api_version: TARGET_API_VERSION,
+            required_extensions: Vec::new(),
+            required_features: feature_requirements.required,
+            optional_features: feature_requirements.optional,
+            device_override: None
+        };
+    }
+}
+
+
+pub struct DeviceCandidate {
+    pub handle: vk::PhysicalDevice,
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: u32,
+    pub device_local_memory: vk::DeviceSize,
+    pub score: u64,
+    pub rejection: Option<String>
+}
+
+impl DeviceCandidate {
+    pub fn is_suitable(&self) -> bool {
+        return self.rejection.is_none();
+    }
+
+    pub fn describe(&self) -> String {
+        let header = format!(
+            "[{}] {} ({:?}, Vulkan {}.{})",
+            self.index, self.name, self.device_type,
+            vk::api_version_major(self.api_version), vk::api_version_minor(self.api_version)
+        );
+        return match &self.rejection {
+            Some(reason) => format!("{} rejected: {}", header, reason),
+            None => format!("{} score {}", header, self.score)
+        };
+    }
+}
+
+
+/// Rates every physical device against the requirements. The list keeps enumeration order.
+pub fn rate_physical_devices(
+    instance: &ash::Instance,
+    surface: Option<&SurfaceInfo>,
+    requirements: &PhysicalDeviceRequirements
+) -> Result<Vec<DeviceCandidate>, VulkanError> {
+    let physical_devices = unsafe {
+        instance.enumerate_physical_devices().context("vkEnumeratePhysicalDevices", "instance")?
+    };
+
+    let mut candidates = Vec::new();
+    for (index, physical_device) in physical_devices.into_iter().enumerate() {
+        candidates.push(rate_physical_device(instance, surface, requirements, physical_device, index)?);
+    }
+
+    return Ok(candidates);
+}
+
+/// Picks the best suitable device, or the one named by the override.
+pub fn select_physical_device(
+    instance: &ash::Instance,
+    surface: Option<&SurfaceInfo>,
+    requirements: &PhysicalDeviceRequirements
+) -> Result<vk::PhysicalDevice, VulkanError> {
+    let candidates = rate_physical_devices(instance, surface, requirements)?;
+    if candidates.is_empty() {
+        return Err(VulkanError::NoPhysicalDevice);
+    }
+
+    println!("Physical Devices:");
+    for candidate in &candidates {
+        println!("  {}", candidate.describe());
+    }
+
+    let device_override = DeviceOverride::from_env().or(requirements.device_override.clone());
+    let chosen = match &device_override {
+        Some(device_override) => {
+            let candidate = candidates.iter()
+                .find(|c| device_override.matches(c))
+                .ok_or_else(|| VulkanError::DeviceOverrideNotFound(format!("{:?}", device_override)))?;
+            if let Some(reason) = &candidate.rejection {
+                return Err(VulkanError::NoSuitablePhysicalDevice(vec![format!("{}: {}", candidate.name, reason)]));
+            }
+            candidate
+        }
+        // max_by_key returns the last maximum, so iterate in reverse to let the lower index win ties
+        None => candidates.iter().rev()
+            .filter(|c| c.is_suitable())
+            .max_by_key(|c| c.score)
+            .ok_or_else(|| VulkanError::NoSuitablePhysicalDevice(
+                candidates.iter().map(|c| format!("{}: {}", c.name, c.rejection.as_ref().unwrap())).collect()
+            ))?
+    };
+
+    println!("Physical Device Chosen: {}", chosen.name);
+    return Ok(chosen.handle);
+}
+
+
+fn rate_physical_device(
+    instance: &ash::Instance,
+    surface: Option<&SurfaceInfo>,
+    requirements: &PhysicalDeviceRequirements,
+    physical_device: vk::PhysicalDevice,
+    index: usize
+) -> Result<DeviceCandidate, VulkanError> {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned();
+
+    let device_local_memory: vk::DeviceSize = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum();
+
+    let mut candidate = DeviceCandidate {
+        handle: physical_device,
+        index,
+        name,
+        device_type: properties.device_type,
+        api_version: properties.api_version,
+        device_local_memory,
+        score: 0,
+        rejection: None
+    };
+
+    let api_version = effective_api_version(requirements.instance_api_version, properties.api_version);
+    candidate.rejection = find_rejection(instance, surface, requirements, physical_device, api_version)?;
+    if candidate.is_suitable() {
+        let supported_features = query_supported_features(instance, physical_device, api_version)?;
+        let optional_feature_count = requirements.optional_features.len()
+            - missing_features(&supported_features, &requirements.optional_features).len();
+        candidate.score = score_device(&properties, device_local_memory) + optional_feature_count as u64 * 500;
+    }
+
+    return Ok(candidate);
+}
+
+fn score_device(properties: &vk::PhysicalDeviceProperties, device_local_memory: vk::DeviceSize) -> u64 {
+    let type_score: u64 = match properties.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 100_000,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 50_000,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 20_000,
+        vk::PhysicalDeviceType::CPU => 10_000,
+        _ => 0
+    };
+    let api_score = vk::api_version_minor(properties.api_version) as u64 * 1_000;
+    // One point per 64 MiB of device local memory
+    let memory_score = device_local_memory >> 26;
+
+    return type_score + api_score + memory_score;
+}
+
+fn find_rejection(
+    instance: &ash::Instance,
+    surface: Option<&SurfaceInfo>,
+    requirements: &PhysicalDeviceRequirements,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32
+) -> Result<Option<String>, VulkanError> {
+    let api_path = match ApiPath::for_version(api_version) {
+        Some(api_path) if api_version >= requirements.min_api_version => api_path,
+        _ => return Ok(Some(format!(
+            "supports Vulkan {}.{} but {}.{} is required",
+            vk::api_version_major(api_version), vk::api_version_minor(api_version),
+            vk::api_version_major(requirements.min_api_version), vk::api_version_minor(requirements.min_api_version)
+        )))
+    };
+
+    let available_extensions = unsafe {
+        instance.enumerate_device_extension_properties(physical_device)
+            .context("vkEnumerateDeviceExtensionProperties", "physical device")?
+    };
+    let available_extension_names = available_extensions.iter()
+        .map(|e| unsafe { CStr::from_ptr(e.extension_name.as_ptr()) }.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    let missing_extensions = requirements.required_extensions.iter()
+        .chain(api_path.required_extensions().iter())
+        .filter(|name| !available_extension_names.iter().any(|available| available == *name))
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing_extensions.is_empty() {
+        return Ok(Some(format!("missing extensions {}", missing_extensions.join(", "))));
+    }
+
+    let supported_features = query_supported_features(instance, physical_device, api_version)?;
+    let missing_required_features = missing_features(&supported_features, &requirements.required_features);
+    if !missing_required_features.is_empty() {
+        return Ok(Some(format!("missing features {:?}", missing_required_features)));
+    }
+
+    let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    if !queue_families.iter().any(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS)) {
+        return Ok(Some("no graphics queue family".to_string()));
+    }
+    if let Some(surface) = surface {
+        let mut present_supported = false;
+        for family_index in 0..queue_families.len() as u32 {
+            present_supported |= unsafe {
+                surface.loader.get_physical_device_surface_support(physical_device, family_index, surface.handle)
+                    .context("vkGetPhysicalDeviceSurfaceSupportKHR", "surface")?
+            };
+        }
+        if !present_supported {
+            return Ok(Some("no queue family can present to the surface".to_string()));
+        }
+    }
+
+    return Ok(None);
+}
@@ -1,6 +1,8 @@
-use std::ffi::{c_void, CString};
-use std::ptr::{null};
+use std::ffi::CString;
 use ash::vk;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
 
 
 pub struct PushConstantsLayout {
@@ -49,10 +51,24 @@ pub struct GraphicsPipeline {
     pub handle: vk::Pipeline,
     pub layout_handle: vk::PipelineLayout,
     pub vertex_shader_module: vk::ShaderModule,
-    pub fragment_shader_module: vk::ShaderModule
+    pub fragment_shader_module: vk::ShaderModule,
+    device: SharedDevice
 }
 
-pub fn create_pipeline(device: &ash::Device, config: &GraphicsPipelineConfiguration) -> GraphicsPipeline {
+impl Drop for GraphicsPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.handle, None);
+            self.device.destroy_pipeline_layout(self.layout_handle, None);
+            self.device.destroy_shader_module(self.vertex_shader_module, None);
+            self.device.destroy_shader_module(self.fragment_shader_module, None);
+        }
+    }
+}
+
+pub fn create_pipeline(
+    device: &SharedDevice, debug_utils: &DebugUtilsDevice, config: &GraphicsPipelineConfiguration, name: &str
+) -> Result<GraphicsPipeline, VulkanError> {
     let push_constant_ranges = [vk::PushConstantRange {
         stage_flags: config.push_constants_layout.shader_stages,
         offset: config.push_constants_layout.offset,
@@ -64,19 +80,25 @@ pub fn create_pipeline(device: &ash::Device, config: &GraphicsPipelineConfigurat
         .push_constant_ranges(&push_constant_ranges);
 
     let pipeline_layout_handle = unsafe {
-        device.create_pipeline_layout(&pipeline_layout_create_info, None).expect("MEH")
+        device.create_pipeline_layout(&pipeline_layout_create_info, None)
+            .context("vkCreatePipelineLayout", name)?
     };
+    debug_utils.set_name(pipeline_layout_handle, &format!("{}.layout", name));
 
-    let shader_entry_point = CString::new("main").expect("MEH");
+    let shader_entry_point = CString::new("main").unwrap();
 
-    let vertex_shader_module = create_shader_module(device, &config.vertex_shader_code);
+    let vertex_shader_module = create_shader_module(
+        device, debug_utils, &config.vertex_shader_code, &format!("{}.vertex_shader", name)
+    )?;
     let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
         .module(vertex_shader_module)
         .name(&shader_entry_point)
         .build();
 
-    let fragment_shader_module = create_shader_module(device, &config.fragment_shader_code);
+    let fragment_shader_module = create_shader_module(
+        device, debug_utils, &config.fragment_shader_code, &format!("{}.fragment_shader", name)
+    )?;
     let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::FRAGMENT)
         .module(fragment_shader_module)
@@ -173,31 +195,6 @@ pub fn create_pipeline(device: &ash::Device, config: &GraphicsPipelineConfigurat
         .attachments(&color_blend_attachments)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    let stencil_op_state = vk::StencilOpState {
-        fail_op: vk::StencilOp::KEEP,
-        pass_op: vk::StencilOp::KEEP,
-        depth_fail_op: vk::StencilOp::KEEP,
-        compare_op: vk::CompareOp::ALWAYS,
-        compare_mask: 0,
-        write_mask: 0,
-        reference: 0,
-    };
-    
-    let depth_stencil_state_info = vk::PipelineDepthStencilStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
-        p_next: null(),
-        flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
-        depth_test_enable: vk::FALSE,
-        depth_write_enable: vk::FALSE,
-        depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
-        depth_bounds_test_enable: vk::FALSE,
-        stencil_test_enable: vk::FALSE,
-        front: stencil_op_state,
-        back: stencil_op_state,
-        max_depth_bounds: 1.0,
-        min_depth_bounds: 0.0,
-    };
-
     let dynamic_states_array = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
 
     let dynamic_states_info = vk::PipelineDynamicStateCreateInfo::builder()
@@ -208,61 +205,152 @@ pub fn create_pipeline(device: &ash::Device, config: &GraphicsPipelineConfigurat
     let mut dynamic_rendering_state_info = vk::PipelineRenderingCreateInfo::builder()
         .color_attachment_formats(&color_attachment_formats);
 
-    // Here I tried constructing the GraphicsPipelineCreateInfo with the builder offered by ash but it also segfaults
-    let pipeline_create_info_alternative = vk::GraphicsPipelineCreateInfo::builder()
+    // No render pass, the color format comes from the rendering info as the pipeline is used with dynamic rendering
+    let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
         .stages(&shader_stages)
         .vertex_input_state(&vertex_input_state_info)
         .input_assembly_state(&input_assembly_state_info)
         .viewport_state(&viewport_state_info)
         .rasterization_state(&rasterization_state_info)
         .multisample_state(&multisample_state_info)
-        //.depth_stencil_state(&depth_stencil_state_info)
         .color_blend_state(&color_blend_state_info)
         .dynamic_state(&dynamic_states_info)
-        //.push_next(&mut dynamic_rendering_state_info)
+        .push_next(&mut dynamic_rendering_state_info)
         .layout(pipeline_layout_handle);
 
-    /*
-    let pipeline_create_info = vk::GraphicsPipelineCreateInfo {
-        s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
-        p_next: &mut dynamic_rendering_state_info as *mut _ as *mut c_void,
-        flags: vk::PipelineCreateFlags::empty(),
-        stage_count: shader_stages.len() as u32,
-        p_stages: shader_stages.as_ptr(),
-        p_vertex_input_state: &vertex_input_state_info,
-        p_input_assembly_state: &input_assembly_state_info,
-        p_tessellation_state: null(),
-        p_viewport_state: &viewport_state_info,
-        p_rasterization_state: &rasterization_state_info,
-        p_multisample_state: &multisample_state_info,
-        p_depth_stencil_state: &depth_stencil_state_info,
-        p_color_blend_state: &color_blend_state_info,
-        p_dynamic_state: &dynamic_states_info,
-        layout: pipeline_layout_handle,
-        render_pass: vk::RenderPass::null(), // no render pass due to dynamic rendering
-        subpass: 0,
-        base_pipeline_handle: vk::Pipeline::null(),
-        base_pipeline_index: -1,
-    };
-     */
-
-    //let create_infos = [pipeline_create_info_alternative];
     let pipeline_handle = unsafe {
-        device.create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&pipeline_create_info_alternative), None).expect("MEH")
+        device.create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&pipeline_create_info), None)
+            .map_err(|(_, result)| result)
+            .context("vkCreateGraphicsPipelines", name)?
     };
-    return GraphicsPipeline {
+    debug_utils.set_name(pipeline_handle[0], name);
+    return Ok(GraphicsPipeline {
         handle: pipeline_handle[0],
         layout_handle: pipeline_layout_handle,
         vertex_shader_module,
         fragment_shader_module,
+        device: device.clone()
+    });
+}
+
+
+pub struct ComputePipelineConfiguration {
+    pub shader_code: Vec<u32>,
+    pub set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub push_constants_layout: PushConstantsLayout,
+    pub spec_constants: Vec<SpecializationConstant>
+}
+
+pub struct ComputePipeline {
+    pub handle: vk::Pipeline,
+    pub layout_handle: vk::PipelineLayout,
+    pub shader_module: vk::ShaderModule,
+    device: SharedDevice
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.handle, None);
+            self.device.destroy_pipeline_layout(self.layout_handle, None);
+            self.device.destroy_shader_module(self.shader_module, None);
+        }
+    }
+}
+
+pub fn create_compute_pipeline(
+    device: &SharedDevice, debug_utils: &DebugUtilsDevice, config: &ComputePipelineConfiguration, name: &str
+) -> Result<ComputePipeline, VulkanError> {
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: config.push_constants_layout.shader_stages,
+        offset: config.push_constants_layout.offset,
+        size: config.push_constants_layout.size_bytes,
+    }];
+    // An empty range is invalid, pipelines without push constants just leave it out
+    let push_constant_ranges = match config.push_constants_layout.size_bytes {
+        0 => &push_constant_ranges[..0],
+        _ => &push_constant_ranges[..]
+    };
+
+    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&config.set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    let pipeline_layout_handle = unsafe {
+        device.create_pipeline_layout(&pipeline_layout_create_info, None)
+            .context("vkCreatePipelineLayout", name)?
+    };
+    debug_utils.set_name(pipeline_layout_handle, &format!("{}.layout", name));
+
+    let shader_module = match create_shader_module(device, debug_utils, &config.shader_code, &format!("{}.shader", name)) {
+        Ok(shader_module) => shader_module,
+        Err(error) => {
+            unsafe { device.destroy_pipeline_layout(pipeline_layout_handle, None) };
+            return Err(error);
+        }
     };
+
+    let spec_map_entries: Vec<vk::SpecializationMapEntry> = config.spec_constants.iter()
+        .enumerate()
+        .map(|(i, constant)| vk::SpecializationMapEntry {
+            constant_id: constant.id,
+            offset: (i * std::mem::size_of::<i32>()) as u32,
+            size: std::mem::size_of::<i32>(),
+        })
+        .collect();
+    let spec_data: Vec<u8> = config.spec_constants.iter()
+        .flat_map(|constant| constant.value.to_ne_bytes())
+        .collect();
+    let spec_info = vk::SpecializationInfo::builder()
+        .map_entries(&spec_map_entries)
+        .data(&spec_data);
+
+    let shader_entry_point = CString::new("main").unwrap();
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(&shader_entry_point)
+        .specialization_info(&spec_info)
+        .build();
+
+    let pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(pipeline_layout_handle);
+
+    let pipeline_handle = unsafe {
+        device.create_compute_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&pipeline_create_info), None)
+            .map_err(|(_, result)| result)
+            .context("vkCreateComputePipelines", name)
+    };
+    let pipeline_handle = match pipeline_handle {
+        Ok(pipeline_handle) => pipeline_handle[0],
+        Err(error) => {
+            unsafe {
+                device.destroy_shader_module(shader_module, None);
+                device.destroy_pipeline_layout(pipeline_layout_handle, None);
+            }
+            return Err(error);
+        }
+    };
+    debug_utils.set_name(pipeline_handle, name);
+
+    return Ok(ComputePipeline {
+        handle: pipeline_handle,
+        layout_handle: pipeline_layout_handle,
+        shader_module,
+        device: device.clone()
+    });
 }
 
 
-fn create_shader_module(device: &ash::Device, shader_code: &Vec<u32>) -> vk::ShaderModule {
+fn create_shader_module(
+    device: &ash::Device, debug_utils: &DebugUtilsDevice, shader_code: &Vec<u32>, name: &str
+) -> Result<vk::ShaderModule, VulkanError> {
     let module_create_info = vk::ShaderModuleCreateInfo::builder().code(&shader_code);
 
-    return unsafe {
-        device.create_shader_module(&module_create_info, None).expect("MEH")
+    let shader_module = unsafe {
+        device.create_shader_module(&module_create_info, None).context("vkCreateShaderModule", name)?
     };
+    debug_utils.set_name(shader_module, name);
+    return Ok(shader_module);
 }
\ No newline at end of file
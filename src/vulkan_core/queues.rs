@@ -0,0 +1,172 @@
+use ash::vk;
+use ash::vk::QueueFlags;
+use crate::vulkan_core::QueueFamily;
+use crate::vulkan_core::cmd::create_command_pool;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::VulkanError;
+
+
+/// One priority per requested queue. Empty lists make that role share the graphics queue.
+pub struct QueueRequests {
+    pub graphics_priority: f32,
+    pub compute_priorities: Vec<f32>,
+    pub transfer_priorities: Vec<f32>
+}
+
+impl Default for QueueRequests {
+    fn default() -> Self {
+        return QueueRequests {
+            graphics_priority: 1.0,
+            compute_priorities: vec![0.5],
+            transfer_priorities: vec![0.5]
+        };
+    }
+}
+
+
+#[derive(Clone, Copy)]
+pub struct QueueSlot {
+    pub family: QueueFamily,
+    pub queue_index: u32,
+    /// The family has none of the capabilities of the graphics family besides the requested one.
+    pub dedicated: bool
+}
+
+pub struct DeviceQueue {
+    pub family: QueueFamily,
+    pub queue_index: u32,
+    pub dedicated: bool,
+    pub handle: vk::Queue,
+    pub command_pool: vk::CommandPool,
+    device: SharedDevice
+}
+
+impl Drop for DeviceQueue {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_command_pool(self.command_pool, None) };
+    }
+}
+
+
+/// Decides which family and queue index every role uses before the device is created.
+pub struct QueuePlan {
+    pub graphics: QueueSlot,
+    pub present: QueueSlot,
+    pub compute: Vec<QueueSlot>,
+    pub transfer: Vec<QueueSlot>,
+    family_priorities: Vec<(QueueFamily, Vec<f32>, u32)>
+}
+
+impl QueuePlan {
+    pub fn new(families: &[QueueFamily], requests: &QueueRequests, presentation: bool) -> Result<QueuePlan, VulkanError> {
+        // Prefer a graphics family that can also present so both share one queue
+        let graphics_family = *families.iter()
+            .find(|f| f.flags.contains(QueueFlags::GRAPHICS) && (f.present_supported || !presentation))
+            .or_else(|| families.iter().find(|f| f.flags.contains(QueueFlags::GRAPHICS)))
+            .ok_or(VulkanError::NoSuitableQueueFamily("graphics"))?;
+
+        // The graphics and present slots are placeholders until reserved below
+        let mut plan = QueuePlan {
+            graphics: QueueSlot { family: graphics_family, queue_index: 0, dedicated: false },
+            present: QueueSlot { family: graphics_family, queue_index: 0, dedicated: false },
+            compute: Vec::new(),
+            transfer: Vec::new(),
+            family_priorities: Vec::new()
+        };
+        plan.graphics = plan.reserve(graphics_family, requests.graphics_priority, false);
+
+        plan.present = match presentation && !graphics_family.present_supported {
+            true => {
+                let present_family = *families.iter()
+                    .find(|f| f.present_supported)
+                    .ok_or(VulkanError::NoSuitableQueueFamily("present"))?;
+                plan.reserve(present_family, requests.graphics_priority, false)
+            }
+            false => plan.graphics
+        };
+
+        let compute_family = families.iter()
+            .find(|f| f.flags.contains(QueueFlags::COMPUTE) && !f.flags.contains(QueueFlags::GRAPHICS))
+            .copied();
+        for priority in &requests.compute_priorities {
+            let slot = match compute_family {
+                Some(family) => plan.reserve(family, *priority, true),
+                None => plan.reserve(graphics_family, *priority, false)
+            };
+            plan.compute.push(slot);
+        }
+        if plan.compute.is_empty() {
+            plan.compute.push(plan.graphics);
+        }
+
+        let transfer_family = families.iter()
+            .find(|f| f.flags == QueueFlags::TRANSFER)
+            .or_else(|| families.iter().find(|f| f.flags.contains(QueueFlags::TRANSFER) && !f.flags.contains(QueueFlags::GRAPHICS)))
+            .copied();
+        for priority in &requests.transfer_priorities {
+            let slot = match transfer_family {
+                Some(family) => plan.reserve(family, *priority, true),
+                None => plan.reserve(graphics_family, *priority, false)
+            };
+            plan.transfer.push(slot);
+        }
+        if plan.transfer.is_empty() {
+            plan.transfer.push(plan.graphics);
+        }
+
+        return Ok(plan);
+    }
+
+    /// Hands out the next free queue of the family. Once all queues are taken, they get shared round robin.
+    fn reserve(&mut self, family: QueueFamily, priority: f32, dedicated: bool) -> QueueSlot {
+        let entry_index = match self.family_priorities.iter().position(|(f, _, _)| f.index == family.index) {
+            Some(entry_index) => entry_index,
+            None => {
+                self.family_priorities.push((family, Vec::new(), 0));
+                self.family_priorities.len() - 1
+            }
+        };
+
+        let (_, priorities, reservations) = &mut self.family_priorities[entry_index];
+        let queue_index = *reservations % family.queue_count.max(1);
+        *reservations += 1;
+        if queue_index as usize == priorities.len() {
+            priorities.push(priority);
+        } else {
+            let shared_priority = &mut priorities[queue_index as usize];
+            *shared_priority = shared_priority.max(priority);
+        }
+
+        return QueueSlot { family, queue_index, dedicated };
+    }
+
+    /// The returned infos point into the plan, so it has to outlive device creation.
+    pub fn queue_create_infos(&self) -> Vec<vk::DeviceQueueCreateInfo> {
+        return self.family_priorities.iter()
+            .map(|(family, priorities, _)| vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(family.index)
+                .queue_priorities(priorities)
+                .build())
+            .collect();
+    }
+}
+
+
+/// `name` is the role of the queue, e.g. "compute[0]".
+pub fn get_device_queue(
+    device: &SharedDevice, debug_utils: &DebugUtilsDevice, slot: &QueueSlot, name: &str
+) -> Result<DeviceQueue, VulkanError> {
+    let handle = unsafe { device.get_device_queue(slot.family.index, slot.queue_index) };
+    debug_utils.set_name(handle, name);
+    let command_pool = create_command_pool(device, debug_utils, &slot.family, &format!("{}.command_pool", name))?;
+
+    return Ok(DeviceQueue {
+        family: slot.family,
+        queue_index: slot.queue_index,
+        dedicated: slot.dedicated,
+        handle,
+        command_pool,
+        device: device.clone()
+    });
+}
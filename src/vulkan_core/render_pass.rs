@@ -1,8 +1,9 @@
 use std::ptr::null;
 use ash::vk;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
 
 
-pub fn create_render_pass(device: &ash::Device) -> vk::RenderPass {
+pub fn create_render_pass(device: &ash::Device) -> Result<vk::RenderPass, VulkanError> {
 
     let subpasses = [
         vk::SubpassDescription::builder()
@@ -13,5 +14,5 @@ pub fn create_render_pass(device: &ash::Device) -> vk::RenderPass {
     let render_pass_create_info = vk::RenderPassCreateInfo::builder()
         .subpasses(&subpasses);
 
-    return unsafe { device.create_render_pass(&render_pass_create_info, None).expect("MEH") };
+    return unsafe { device.create_render_pass(&render_pass_create_info, None).context("vkCreateRenderPass", "render pass") };
 }
\ No newline at end of file
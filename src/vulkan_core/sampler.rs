@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use ash::vk;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+
+
+/// Everything that makes two samplers different. Identical states share one `vk::Sampler`.
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerState {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    pub mip_lod_bias: f32,
+    /// Clamped to the device limit, ignored when the SamplerAnisotropy feature isn't enabled.
+    pub max_anisotropy: Option<f32>,
+    /// For shadow map lookups through `sampler2DShadow`.
+    pub compare_op: Option<vk::CompareOp>,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub border_color: vk::BorderColor,
+    pub unnormalized_coordinates: bool
+}
+
+impl Default for SamplerState {
+    fn default() -> Self {
+        return SamplerState {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            mip_lod_bias: 0.0,
+            max_anisotropy: None,
+            compare_op: None,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+            unnormalized_coordinates: false
+        };
+    }
+}
+
+impl SamplerState {
+    /// Floats compare by their bits, so the state can be a map key.
+    fn key(&self) -> impl Eq + Hash {
+        return (
+            (self.mag_filter, self.min_filter, self.mipmap_mode),
+            (self.address_mode_u, self.address_mode_v, self.address_mode_w),
+            (self.mip_lod_bias.to_bits(), self.max_anisotropy.map(f32::to_bits), self.compare_op),
+            (self.min_lod.to_bits(), self.max_lod.to_bits(), self.border_color, self.unnormalized_coordinates)
+        );
+    }
+}
+
+impl PartialEq for SamplerState {
+    fn eq(&self, other: &Self) -> bool {
+        return self.key() == other.key();
+    }
+}
+
+impl Eq for SamplerState {}
+
+impl Hash for SamplerState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+
+pub struct SamplerBuilder {
+    state: SamplerState
+}
+
+impl SamplerBuilder {
+    /// Starts from trilinear filtering with repeating addressing.
+    pub fn new() -> Self {
+        return SamplerBuilder { state: SamplerState::default() };
+    }
+
+    /// Magnification and minification filter.
+    pub fn filter(mut self, filter: vk::Filter) -> Self {
+        self.state.mag_filter = filter;
+        self.state.min_filter = filter;
+        return self;
+    }
+
+    pub fn mag_filter(mut self, filter: vk::Filter) -> Self {
+        self.state.mag_filter = filter;
+        return self;
+    }
+
+    pub fn min_filter(mut self, filter: vk::Filter) -> Self {
+        self.state.min_filter = filter;
+        return self;
+    }
+
+    pub fn mipmap_mode(mut self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+        self.state.mipmap_mode = mipmap_mode;
+        return self;
+    }
+
+    /// The same mode for all three coordinates.
+    pub fn address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.state.address_mode_u = address_mode;
+        self.state.address_mode_v = address_mode;
+        self.state.address_mode_w = address_mode;
+        return self;
+    }
+
+    pub fn address_modes(
+        mut self, u: vk::SamplerAddressMode, v: vk::SamplerAddressMode, w: vk::SamplerAddressMode
+    ) -> Self {
+        self.state.address_mode_u = u;
+        self.state.address_mode_v = v;
+        self.state.address_mode_w = w;
+        return self;
+    }
+
+    pub fn mip_lod_bias(mut self, bias: f32) -> Self {
+        self.state.mip_lod_bias = bias;
+        return self;
+    }
+
+    pub fn anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.state.max_anisotropy = Some(max_anisotropy);
+        return self;
+    }
+
+    pub fn compare(mut self, compare_op: vk::CompareOp) -> Self {
+        self.state.compare_op = Some(compare_op);
+        return self;
+    }
+
+    pub fn lod_range(mut self, min_lod: f32, max_lod: f32) -> Self {
+        self.state.min_lod = min_lod;
+        self.state.max_lod = max_lod;
+        return self;
+    }
+
+    pub fn border_color(mut self, border_color: vk::BorderColor) -> Self {
+        self.state.border_color = border_color;
+        return self;
+    }
+
+    /// Texel coordinates instead of 0..1, which rules out mipmaps, anisotropy and comparison.
+    pub fn unnormalized_coordinates(mut self, enabled: bool) -> Self {
+        self.state.unnormalized_coordinates = enabled;
+        return self;
+    }
+
+    pub fn state(&self) -> SamplerState {
+        return self.state;
+    }
+
+    /// The cache owns the sampler, it stays valid until the cache is dropped.
+    pub fn build(&self, cache: &mut SamplerCache) -> Result<vk::Sampler, VulkanError> {
+        return cache.get_or_create(&self.state);
+    }
+}
+
+
+/// Creates every distinct sampler once. Devices only allow `maxSamplerAllocationCount` samplers,
+/// as few as 4000, so per-texture samplers run out quickly.
+pub struct SamplerCache {
+    samplers: HashMap<SamplerState, vk::Sampler>,
+    /// 0 when the SamplerAnisotropy feature isn't enabled.
+    max_anisotropy: f32,
+    debug_utils: DebugUtilsDevice,
+    device: SharedDevice
+}
+
+impl Drop for SamplerCache {
+    fn drop(&mut self) {
+        for sampler in self.samplers.values() {
+            unsafe { self.device.destroy_sampler(*sampler, None) };
+        }
+    }
+}
+
+
+pub fn create_sampler_cache(
+    device: &SharedDevice, debug_utils: &DebugUtilsDevice, limits: &vk::PhysicalDeviceLimits, anisotropy_enabled: bool
+) -> SamplerCache {
+    let max_anisotropy = match anisotropy_enabled {
+        true => limits.max_sampler_anisotropy,
+        false => 0.0
+    };
+    return SamplerCache {
+        samplers: HashMap::new(),
+        max_anisotropy,
+        debug_utils: debug_utils.clone(),
+        device: device.clone()
+    };
+}
+
+
+impl SamplerCache {
+    pub fn len(&self) -> usize {
+        return self.samplers.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.samplers.is_empty();
+    }
+
+    pub fn get_or_create(&mut self, state: &SamplerState) -> Result<vk::Sampler, VulkanError> {
+        if let Some(sampler) = self.samplers.get(state) {
+            return Ok(*sampler);
+        }
+
+        let max_anisotropy = state.max_anisotropy
+            .map(|max_anisotropy| max_anisotropy.min(self.max_anisotropy))
+            .filter(|max_anisotropy| *max_anisotropy >= 1.0);
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(state.mag_filter)
+            .min_filter(state.min_filter)
+            .mipmap_mode(state.mipmap_mode)
+            .address_mode_u(state.address_mode_u)
+            .address_mode_v(state.address_mode_v)
+            .address_mode_w(state.address_mode_w)
+            .mip_lod_bias(state.mip_lod_bias)
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .compare_enable(state.compare_op.is_some())
+            .compare_op(state.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .min_lod(state.min_lod)
+            .max_lod(state.max_lod)
+            .border_color(state.border_color)
+            .unnormalized_coordinates(state.unnormalized_coordinates);
+
+        let name = format!("sampler_cache.sampler[{}]", self.samplers.len());
+        let sampler = unsafe {
+            self.device.create_sampler(&sampler_info, None).context("vkCreateSampler", &name)?
+        };
+        self.debug_utils.set_name(sampler, &name);
+
+        self.samplers.insert(*state, sampler);
+        return Ok(sampler);
+    }
+}
@@ -1,4 +1,5 @@
 
+use std::ffi::CStr;
 use ash::vk;
 
 #[cfg(target_os = "windows")]
@@ -8,7 +9,6 @@ use ash::extensions::khr::XlibSurface;
 #[cfg(target_os = "macos")]
 use ash::extensions::mvk::MacOSSurface;
 
-use ash::extensions::ext::DebugUtils;
 use ash::extensions::khr::Surface;
 
 #[cfg(target_os = "macos")]
@@ -22,29 +22,26 @@ use objc::runtime::YES;
 
 // required extension ------------------------------------------------------
 #[cfg(target_os = "macos")]
-pub fn required_extension_names() -> Vec<*const i8> {
+pub fn required_extension_names() -> Vec<&'static CStr> {
     vec![
-        Surface::name().as_ptr(),
-        MacOSSurface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
+        Surface::name(),
+        MacOSSurface::name(),
     ]
 }
 
 #[cfg(all(windows))]
-pub fn required_extension_names() -> Vec<*const i8> {
+pub fn required_extension_names() -> Vec<&'static CStr> {
     vec![
-        Surface::name().as_ptr(),
-        Win32Surface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
+        Surface::name(),
+        Win32Surface::name(),
     ]
 }
 
 #[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
-pub fn required_extension_names() -> Vec<*const i8> {
+pub fn required_extension_names() -> Vec<&'static CStr> {
     vec![
-        Surface::name().as_ptr(),
-        XlibSurface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
+        Surface::name(),
+        XlibSurface::name(),
     ]
 }
 // ------------------------------------------------------------------------
@@ -52,9 +49,9 @@ pub fn required_extension_names() -> Vec<*const i8> {
 
 // create surface ---------------------------------------------------------
 #[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
-pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
-    entry: &E,
-    instance: &I,
+pub unsafe fn create_surface(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
     window: &winit::window::Window,
 ) -> Result<vk::SurfaceKHR, vk::Result> {
     use std::ptr;
@@ -74,9 +71,9 @@ pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
 }
 
 #[cfg(target_os = "macos")]
-pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
-    entry: &E,
-    instance: &I,
+pub unsafe fn create_surface(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
     window: &winit::window::Window,
 ) -> Result<vk::SurfaceKHR, vk::Result> {
     use std::mem;
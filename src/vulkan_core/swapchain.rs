@@ -2,6 +2,9 @@ use std::ptr::{null};
 use ash::vk;
 use ash::vk::Bool32;
 use crate::vulkan_core::{QueueFamily, SurfaceInfo};
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
 
 
 pub struct SwapchainInfo {
@@ -11,6 +14,29 @@ pub struct SwapchainInfo {
     pub images: Vec<vk::Image>,
     pub image_views: Vec<vk::ImageView>,
     pub color_format: vk::Format,
+    device: SharedDevice
+}
+
+impl SwapchainInfo {
+    /// Safe to call more than once, dropping calls it as well.
+    pub fn destroy(&mut self) {
+        unsafe {
+            for image_view in self.image_views.drain(..) {
+                self.device.destroy_image_view(image_view, None);
+            }
+            if self.handle != vk::SwapchainKHR::null() {
+                self.loader.destroy_swapchain(self.handle, None);
+                self.handle = vk::SwapchainKHR::null();
+            }
+        }
+        self.images.clear();
+    }
+}
+
+impl Drop for SwapchainInfo {
+    fn drop(&mut self) {
+        self.destroy();
+    }
 }
 
 
@@ -23,25 +49,29 @@ pub fn create_swapchain(
     instance: &ash::Instance,
     surface: &SurfaceInfo,
     physical_device: vk::PhysicalDevice,
-    device: &ash::Device,
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
     images_total: u32,
     graphics_queue_family: &QueueFamily,
-    present_queue_family: &QueueFamily
-) -> SwapchainInfo {
+    present_queue_family: &QueueFamily,
+    // Lets the old swapchain stay alive until frames using it have finished
+    old_swapchain: vk::SwapchainKHR
+) -> Result<SwapchainInfo, VulkanError> {
     let capabilities = unsafe {
         surface.loader.get_physical_device_surface_capabilities(physical_device, surface.handle)
-    }.unwrap();
+            .context("vkGetPhysicalDeviceSurfaceCapabilitiesKHR", "surface")?
+    };
 
     let formats = unsafe {
         surface.loader
             .get_physical_device_surface_formats(physical_device, surface.handle)
-            .expect("MEH")
+            .context("vkGetPhysicalDeviceSurfaceFormatsKHR", "surface")?
     };
 
     let present_modes = unsafe {
         surface.loader
             .get_physical_device_surface_present_modes(physical_device, surface.handle)
-            .expect("MEH")
+            .context("vkGetPhysicalDeviceSurfacePresentModesKHR", "surface")?
     };
 
     let mut queue_family_indices: Vec<u32> = Vec::new();
@@ -57,7 +87,7 @@ pub fn create_swapchain(
         pre_transform = vk::SurfaceTransformFlagsKHR::IDENTITY;
     }
 
-    let surface_format = choose_surface_format(&formats);
+    let surface_format = choose_surface_format(&formats)?;
     let present_mode = choose_present_mode(present_modes);
 
     let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
@@ -73,36 +103,43 @@ pub fn create_swapchain(
         .pre_transform(pre_transform)
         .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
         .present_mode(present_mode)
-        .clipped(true);
+        .clipped(true)
+        .old_swapchain(old_swapchain);
 
     let swapchain_loader = ash::extensions::khr::Swapchain::new(instance, device);
     let swapchain_handle = unsafe {
         swapchain_loader
             .create_swapchain(&swapchain_create_info, None)
-            .expect("MEH")
+            .context("vkCreateSwapchainKHR", "swapchain")?
     };
+    debug_utils.set_name(swapchain_handle, "swapchain");
 
-    let images = create_images(swapchain_handle, &swapchain_loader);
-    let image_views = create_image_views(device, &images, surface_format.format);
+    let images = create_images(swapchain_handle, &swapchain_loader)?;
+    let image_views = create_image_views(device, &images, surface_format.format)?;
+    for (i, (image, image_view)) in images.iter().zip(&image_views).enumerate() {
+        debug_utils.set_name(*image, &format!("swapchain.image[{}]", i));
+        debug_utils.set_name(*image_view, &format!("swapchain.image_view[{}]", i));
+    }
 
-    return SwapchainInfo {
+    return Ok(SwapchainInfo {
         handle: swapchain_handle,
         loader: swapchain_loader,
         extent: capabilities.min_image_extent,
         images,
         image_views,
-        color_format: surface_format.format
-    }
+        color_format: surface_format.format,
+        device: device.clone()
+    });
 }
 
 
-fn choose_surface_format(formats: &Vec<vk::SurfaceFormatKHR>) -> &vk::SurfaceFormatKHR {
+fn choose_surface_format(formats: &Vec<vk::SurfaceFormatKHR>) -> Result<&vk::SurfaceFormatKHR, VulkanError> {
     for format in formats {
         if format.color_space == PREFERRED_COLOR_SPACE && PREFERRED_IMAGE_FORMATS.contains(&format.format) {
-            return format;
+            return Ok(format);
         }
     }
-    unimplemented!()
+    return Err(VulkanError::NoSuitableSurfaceFormat);
 }
 
 fn choose_present_mode(all_modes: Vec<vk::PresentModeKHR>) -> vk::PresentModeKHR {
@@ -114,17 +151,21 @@ fn choose_present_mode(all_modes: Vec<vk::PresentModeKHR>) -> vk::PresentModeKHR
     return current_best_mode;
 }
 
-fn create_images(swapchain_handle: vk::SwapchainKHR, swapchain_loader: &ash::extensions::khr::Swapchain) -> Vec<vk::Image> {
+fn create_images(
+    swapchain_handle: vk::SwapchainKHR, swapchain_loader: &ash::extensions::khr::Swapchain
+) -> Result<Vec<vk::Image>, VulkanError> {
     let images = unsafe {
         swapchain_loader
             .get_swapchain_images(swapchain_handle)
-            .expect("MEH")
+            .context("vkGetSwapchainImagesKHR", "swapchain")?
     };
 
-    return images;
+    return Ok(images);
 }
 
-fn create_image_views(device: &ash::Device, images: &Vec<vk::Image>, color_format: vk::Format) -> Vec<vk::ImageView> {
+fn create_image_views(
+    device: &ash::Device, images: &Vec<vk::Image>, color_format: vk::Format
+) -> Result<Vec<vk::ImageView>, VulkanError> {
     let image_views = images
         .iter()
         .map(|current_image| {
@@ -140,9 +181,11 @@ fn create_image_views(device: &ash::Device, images: &Vec<vk::Image>, color_forma
                     layer_count: 1,
                 });
 
-            return unsafe { device.create_image_view(&image_view_info, None).expect("MEH") };
+            return unsafe {
+                device.create_image_view(&image_view_info, None).context("vkCreateImageView", "swapchain image view")
+            };
         })
-        .collect::<Vec<vk::ImageView>>();
+        .collect::<Result<Vec<vk::ImageView>, VulkanError>>()?;
 
-    return image_views;
+    return Ok(image_views);
 }
\ No newline at end of file
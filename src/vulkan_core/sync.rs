@@ -1,17 +1,43 @@
 use ash::vk;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
 
 
-pub fn create_semaphore(device: &ash::Device) -> vk::Semaphore {
+pub fn create_semaphore(device: &ash::Device, debug_utils: &DebugUtilsDevice, name: &str) -> Result<vk::Semaphore, VulkanError> {
     let create_info = vk::SemaphoreCreateInfo::builder();
 
-    return unsafe { device.create_semaphore(&create_info, None).expect("MEH") };
+    let semaphore = unsafe { device.create_semaphore(&create_info, None).context("vkCreateSemaphore", name)? };
+    debug_utils.set_name(semaphore, name);
+    return Ok(semaphore);
 }
 
 
-pub fn create_fence(device: &ash::Device) -> vk::Fence {
+/// Needs the timeline semaphore feature, core since 1.2.
+pub fn create_timeline_semaphore(
+    device: &ash::Device, debug_utils: &DebugUtilsDevice, initial_value: u64, name: &str
+) -> Result<vk::Semaphore, VulkanError> {
+    let mut type_create_info = vk::SemaphoreTypeCreateInfo::builder()
+        .semaphore_type(vk::SemaphoreType::TIMELINE)
+        .initial_value(initial_value);
+    let create_info = vk::SemaphoreCreateInfo::builder()
+        .push_next(&mut type_create_info);
+
+    let semaphore = unsafe { device.create_semaphore(&create_info, None).context("vkCreateSemaphore", name)? };
+    debug_utils.set_name(semaphore, name);
+    return Ok(semaphore);
+}
+
+
+pub fn create_fence(device: &ash::Device, debug_utils: &DebugUtilsDevice, signaled: bool, name: &str) -> Result<vk::Fence, VulkanError> {
+    let flags = match signaled {
+        true => vk::FenceCreateFlags::SIGNALED,
+        false => vk::FenceCreateFlags::empty()
+    };
     let create_info = vk::FenceCreateInfo::builder()
-        .flags(vk::FenceCreateFlags::SIGNALED);
+        .flags(flags);
 
-    return unsafe { device.create_fence(&create_info, None).expect("MEH") };
+    let fence = unsafe { device.create_fence(&create_info, None).context("vkCreateFence", name)? };
+    debug_utils.set_name(fence, name);
+    return Ok(fence);
 }
 
@@ -0,0 +1,677 @@
+use std::path::Path;
+use ash::vk;
+use crate::vulkan_core::allocator::SharedAllocator;
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::VulkanError;
+use crate::vulkan_core::features::{DeviceFeature, EnabledFeatures};
+use crate::vulkan_core::image_factory::{create_image, mip_extent, ImageKind, VulkanImage, VulkanImageConfiguration};
+use crate::vulkan_core::memory_usage::MemoryUsage;
+use crate::vulkan_core::upload::UploadManager;
+
+
+/// Every level starts at this alignment inside `TextureData::data`, enough for any texel block size.
+const LEVEL_ALIGNMENT: usize = 16;
+
+const PNG_MAGIC: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const JPEG_MAGIC: &[u8] = &[0xff, 0xd8, 0xff];
+const KTX2_MAGIC: &[u8] = &[0xab, b'K', b'T', b'X', b' ', b'2', b'0', 0xbb, 0x0d, 0x0a, 0x1a, 0x0a];
+const DDS_MAGIC: &[u8] = b"DDS ";
+
+
+/// How 8 bit color from PNG and JPEG files, and DDS files without a DXGI format, is interpreted.
+/// Containers that state their format keep it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureColorSpace {
+    /// Color textures, filtering happens after conversion to linear.
+    Srgb,
+    /// Normal maps, roughness and other data.
+    Linear
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFileFormat {
+    Png,
+    Jpeg,
+    Ktx2,
+    Dds
+}
+
+impl TextureFileFormat {
+    /// Looks at the magic bytes, file extensions can't be trusted.
+    pub fn detect(bytes: &[u8]) -> Option<TextureFileFormat> {
+        return match bytes {
+            _ if bytes.starts_with(PNG_MAGIC) => Some(TextureFileFormat::Png),
+            _ if bytes.starts_with(JPEG_MAGIC) => Some(TextureFileFormat::Jpeg),
+            _ if bytes.starts_with(KTX2_MAGIC) => Some(TextureFileFormat::Ktx2),
+            _ if bytes.starts_with(DDS_MAGIC) => Some(TextureFileFormat::Dds),
+            _ => None
+        };
+    }
+}
+
+
+pub struct TextureLoadConfiguration {
+    pub color_space: TextureColorSpace,
+    /// SAMPLED and TRANSFER_DST are always added.
+    pub usage: vk::ImageUsageFlags,
+    /// Fills the full mip chain on the GPU when the file only has the base level.
+    pub generate_mipmaps: bool
+}
+
+impl Default for TextureLoadConfiguration {
+    fn default() -> Self {
+        return TextureLoadConfiguration {
+            color_space: TextureColorSpace::Srgb,
+            usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            generate_mipmaps: true
+        };
+    }
+}
+
+
+/// Decoded texels, ready to be copied into an image.
+pub struct TextureData {
+    pub kind: ImageKind,
+    pub extent: vk::Extent3D,
+    /// Number of cubes for cube textures, like `VulkanImageConfiguration::array_layers`.
+    pub array_layers: u32,
+    pub format: vk::Format,
+    /// Level after level, each holding all layers of that level. Starts of levels are `LEVEL_ALIGNMENT` aligned.
+    pub data: Vec<u8>,
+    pub level_offsets: Vec<usize>
+}
+
+impl TextureData {
+    pub fn mip_levels(&self) -> u32 {
+        return self.level_offsets.len() as u32;
+    }
+
+    /// All layers, six per cube for cube textures.
+    pub fn layer_count(&self) -> u32 {
+        return match self.kind {
+            ImageKind::Cube => self.array_layers * 6,
+            _ => self.array_layers
+        };
+    }
+
+    /// One copy per mip level, offsets relative to `data`.
+    pub fn copy_regions(&self) -> Vec<vk::BufferImageCopy> {
+        return self.level_offsets.iter()
+            .enumerate()
+            .map(|(mip_level, offset)| vk::BufferImageCopy {
+                buffer_offset: *offset as vk::DeviceSize,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: mip_level as u32,
+                    base_array_layer: 0,
+                    layer_count: self.layer_count()
+                },
+                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                image_extent: mip_extent(self.extent, mip_level as u32)
+            })
+            .collect();
+    }
+}
+
+
+/// Size and byte count of a format's texel blocks. Uncompressed formats have 1x1 blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatBlock {
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u32
+}
+
+impl FormatBlock {
+    /// Bytes of one layer of an image of `extent`, partial blocks at the edges count as whole ones.
+    pub fn layer_size(&self, extent: vk::Extent3D) -> usize {
+        let blocks_x = extent.width.div_ceil(self.width);
+        let blocks_y = extent.height.div_ceil(self.height);
+        return blocks_x as usize * blocks_y as usize * extent.depth as usize * self.bytes as usize;
+    }
+}
+
+const ASTC_BLOCK_SIZES: [(u32, u32); 14] = [
+    (4, 4), (5, 4), (5, 5), (6, 5), (6, 6), (8, 5), (8, 6), (8, 8), (10, 5), (10, 6), (10, 8), (10, 10), (12, 10), (12, 12)
+];
+
+/// The formats textures can be loaded in.
+pub fn format_block(format: vk::Format) -> Option<FormatBlock> {
+    let texel = |bytes| Some(FormatBlock { width: 1, height: 1, bytes });
+    let block = |bytes| Some(FormatBlock { width: 4, height: 4, bytes });
+    return match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => texel(1),
+        vk::Format::R8G8_UNORM | vk::Format::R16_UNORM | vk::Format::R16_SFLOAT => texel(2),
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB |
+        vk::Format::R16G16_SFLOAT | vk::Format::R32_SFLOAT |
+        vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::B10G11R11_UFLOAT_PACK32 => texel(4),
+        vk::Format::R16G16B16A16_UNORM | vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT => texel(8),
+        vk::Format::R32G32B32A32_SFLOAT => texel(16),
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK |
+        vk::Format::BC1_RGBA_UNORM_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK |
+        vk::Format::BC4_UNORM_BLOCK | vk::Format::BC4_SNORM_BLOCK => block(8),
+        vk::Format::BC2_UNORM_BLOCK | vk::Format::BC2_SRGB_BLOCK | vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK |
+        vk::Format::BC5_UNORM_BLOCK | vk::Format::BC5_SNORM_BLOCK | vk::Format::BC6H_UFLOAT_BLOCK | vk::Format::BC6H_SFLOAT_BLOCK |
+        vk::Format::BC7_UNORM_BLOCK | vk::Format::BC7_SRGB_BLOCK => block(16),
+        _ if is_astc_format(format) => {
+            // UNORM and SRGB alternate for each block size
+            let (width, height) = ASTC_BLOCK_SIZES[(format.as_raw() - vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw()) as usize / 2];
+            Some(FormatBlock { width, height, bytes: 16 })
+        }
+        _ => None
+    };
+}
+
+fn is_bc_format(format: vk::Format) -> bool {
+    return (vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::BC7_SRGB_BLOCK.as_raw()).contains(&format.as_raw());
+}
+
+fn is_astc_format(format: vk::Format) -> bool {
+    return (vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw()..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw()).contains(&format.as_raw());
+}
+
+/// Block-compressed formats are only usable with their device feature enabled.
+pub fn required_format_feature(format: vk::Format) -> Option<DeviceFeature> {
+    if is_bc_format(format) { return Some(DeviceFeature::TextureCompressionBc); }
+    if is_astc_format(format) { return Some(DeviceFeature::TextureCompressionAstcLdr); }
+    return None;
+}
+
+
+/// Concatenates levels, padding each start to `LEVEL_ALIGNMENT`.
+fn pack_levels<'a>(levels: impl Iterator<Item = &'a [u8]>) -> (Vec<u8>, Vec<usize>) {
+    let mut data = Vec::new();
+    let mut level_offsets = Vec::new();
+    for level in levels {
+        data.resize(data.len().div_ceil(LEVEL_ALIGNMENT) * LEVEL_ALIGNMENT, 0);
+        level_offsets.push(data.len());
+        data.extend_from_slice(level);
+    }
+    return (data, level_offsets);
+}
+
+fn rgba8_texture(width: u32, height: u32, rgba: Vec<u8>, color_space: TextureColorSpace) -> TextureData {
+    let format = match color_space {
+        TextureColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+        TextureColorSpace::Linear => vk::Format::R8G8B8A8_UNORM
+    };
+    return TextureData {
+        kind: ImageKind::Image2D,
+        extent: vk::Extent3D { width, height, depth: 1 },
+        array_layers: 1,
+        format,
+        data: rgba,
+        level_offsets: vec![0]
+    };
+}
+
+
+pub fn decode_png(bytes: &[u8], color_space: TextureColorSpace, name: &str) -> Result<TextureData, VulkanError> {
+    let corrupt = |error: png::DecodingError| VulkanError::CorruptTexture { name: name.to_string(), reason: error.to_string() };
+
+    let mut decoder = png::Decoder::new(bytes);
+    // Palettes, transparency chunks and low bit depths become plain 8 bit channels
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(corrupt)?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).map_err(corrupt)?;
+    pixels.truncate(info.buffer_size());
+
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|p| [*p, *p, *p, 255]).collect(),
+        png::ColorType::Indexed => return Err(VulkanError::UnsupportedTexture {
+            name: name.to_string(), reason: "palette was not expanded".to_string()
+        })
+    };
+
+    return Ok(rgba8_texture(info.width, info.height, rgba, color_space));
+}
+
+pub fn decode_jpeg(bytes: &[u8], color_space: TextureColorSpace, name: &str) -> Result<TextureData, VulkanError> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let pixels = decoder.decode()
+        .map_err(|error| VulkanError::CorruptTexture { name: name.to_string(), reason: error.to_string() })?;
+    let info = decoder.info().unwrap();
+
+    let rgba: Vec<u8> = match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        jpeg_decoder::PixelFormat::L8 => pixels.iter().flat_map(|p| [*p, *p, *p, 255]).collect(),
+        // Big endian, the high byte comes first
+        jpeg_decoder::PixelFormat::L16 => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], 255]).collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => return Err(VulkanError::UnsupportedTexture {
+            name: name.to_string(), reason: "CMYK JPEG".to_string()
+        })
+    };
+
+    return Ok(rgba8_texture(info.width as u32, info.height as u32, rgba, color_space));
+}
+
+/// KTX2 files carry their Vulkan format and mip chain. Supercompressed and Basis Universal files aren't supported.
+pub fn parse_ktx2(bytes: &[u8], name: &str) -> Result<TextureData, VulkanError> {
+    let corrupt = |reason: String| VulkanError::CorruptTexture { name: name.to_string(), reason };
+    let unsupported = |reason: String| VulkanError::UnsupportedTexture { name: name.to_string(), reason };
+
+    let reader = ktx2::Reader::new(bytes).map_err(|error| corrupt(error.to_string()))?;
+    let header = reader.header();
+    if let Some(scheme) = header.supercompression_scheme {
+        return Err(unsupported(format!("supercompression {:?}", scheme)));
+    }
+    let format = match header.format {
+        Some(format) => vk::Format::from_raw(format.value() as i32),
+        None => return Err(unsupported("no Vulkan format, probably Basis Universal".to_string()))
+    };
+    let block = format_block(format).ok_or_else(|| unsupported(format!("format {:?}", format)))?;
+
+    let kind = match (header.face_count, header.pixel_height, header.pixel_depth) {
+        (6, _, _) => ImageKind::Cube,
+        (1, 0, _) => ImageKind::Image1D,
+        (1, _, 0) => ImageKind::Image2D,
+        (1, _, _) => ImageKind::Image3D,
+        (face_count, _, _) => return Err(corrupt(format!("{} faces", face_count)))
+    };
+    let extent = vk::Extent3D {
+        width: header.pixel_width,
+        height: header.pixel_height.max(1),
+        depth: header.pixel_depth.max(1)
+    };
+    let array_layers = header.layer_count.max(1);
+    let layer_count = array_layers * header.face_count;
+
+    let levels: Vec<&[u8]> = reader.levels().map(|level| level.data).collect();
+    for (mip_level, level) in levels.iter().enumerate() {
+        let expected = block.layer_size(mip_extent(extent, mip_level as u32)) * layer_count as usize;
+        if level.len() != expected {
+            return Err(corrupt(format!("level {} has {} bytes instead of {}", mip_level, level.len(), expected)));
+        }
+    }
+    if levels.is_empty() {
+        return Err(corrupt("no mip levels".to_string()));
+    }
+
+    let (data, level_offsets) = pack_levels(levels.into_iter());
+    return Ok(TextureData { kind, extent, array_layers, format, data, level_offsets });
+}
+
+fn dxgi_to_vk_format(format: ddsfile::DxgiFormat) -> Option<vk::Format> {
+    use ddsfile::DxgiFormat;
+    return Some(match format {
+        DxgiFormat::R8_UNorm => vk::Format::R8_UNORM,
+        DxgiFormat::R8G8_UNorm => vk::Format::R8G8_UNORM,
+        DxgiFormat::R8G8B8A8_UNorm => vk::Format::R8G8B8A8_UNORM,
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => vk::Format::R8G8B8A8_SRGB,
+        DxgiFormat::B8G8R8A8_UNorm => vk::Format::B8G8R8A8_UNORM,
+        DxgiFormat::B8G8R8A8_UNorm_sRGB => vk::Format::B8G8R8A8_SRGB,
+        DxgiFormat::R10G10B10A2_UNorm => vk::Format::A2B10G10R10_UNORM_PACK32,
+        DxgiFormat::R11G11B10_Float => vk::Format::B10G11R11_UFLOAT_PACK32,
+        DxgiFormat::R16_Float => vk::Format::R16_SFLOAT,
+        DxgiFormat::R16G16_Float => vk::Format::R16G16_SFLOAT,
+        DxgiFormat::R16G16B16A16_Float => vk::Format::R16G16B16A16_SFLOAT,
+        DxgiFormat::R32_Float => vk::Format::R32_SFLOAT,
+        DxgiFormat::R32G32B32A32_Float => vk::Format::R32G32B32A32_SFLOAT,
+        DxgiFormat::BC1_UNorm => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        DxgiFormat::BC1_UNorm_sRGB => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        DxgiFormat::BC2_UNorm => vk::Format::BC2_UNORM_BLOCK,
+        DxgiFormat::BC2_UNorm_sRGB => vk::Format::BC2_SRGB_BLOCK,
+        DxgiFormat::BC3_UNorm => vk::Format::BC3_UNORM_BLOCK,
+        DxgiFormat::BC3_UNorm_sRGB => vk::Format::BC3_SRGB_BLOCK,
+        DxgiFormat::BC4_UNorm => vk::Format::BC4_UNORM_BLOCK,
+        DxgiFormat::BC4_SNorm => vk::Format::BC4_SNORM_BLOCK,
+        DxgiFormat::BC5_UNorm => vk::Format::BC5_UNORM_BLOCK,
+        DxgiFormat::BC5_SNorm => vk::Format::BC5_SNORM_BLOCK,
+        DxgiFormat::BC6H_UF16 => vk::Format::BC6H_UFLOAT_BLOCK,
+        DxgiFormat::BC6H_SF16 => vk::Format::BC6H_SFLOAT_BLOCK,
+        DxgiFormat::BC7_UNorm => vk::Format::BC7_UNORM_BLOCK,
+        DxgiFormat::BC7_UNorm_sRGB => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None
+    });
+}
+
+/// Legacy headers don't say whether color is sRGB, `color_space` decides.
+fn d3d_to_vk_format(format: ddsfile::D3DFormat, color_space: TextureColorSpace) -> Option<vk::Format> {
+    use ddsfile::D3DFormat;
+    let srgb = color_space == TextureColorSpace::Srgb;
+    return Some(match (format, srgb) {
+        (D3DFormat::A8B8G8R8, false) => vk::Format::R8G8B8A8_UNORM,
+        (D3DFormat::A8B8G8R8, true) => vk::Format::R8G8B8A8_SRGB,
+        (D3DFormat::A8R8G8B8, false) => vk::Format::B8G8R8A8_UNORM,
+        (D3DFormat::A8R8G8B8, true) => vk::Format::B8G8R8A8_SRGB,
+        (D3DFormat::DXT1, false) => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        (D3DFormat::DXT1, true) => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        (D3DFormat::DXT3, false) => vk::Format::BC2_UNORM_BLOCK,
+        (D3DFormat::DXT3, true) => vk::Format::BC2_SRGB_BLOCK,
+        (D3DFormat::DXT5, false) => vk::Format::BC3_UNORM_BLOCK,
+        (D3DFormat::DXT5, true) => vk::Format::BC3_SRGB_BLOCK,
+        (D3DFormat::L8, _) => vk::Format::R8_UNORM,
+        (D3DFormat::R16F, _) => vk::Format::R16_SFLOAT,
+        (D3DFormat::A16B16G16R16F, _) => vk::Format::R16G16B16A16_SFLOAT,
+        (D3DFormat::R32F, _) => vk::Format::R32_SFLOAT,
+        (D3DFormat::A32B32G32R32F, _) => vk::Format::R32G32B32A32_SFLOAT,
+        _ => return None
+    });
+}
+
+/// DDS stores all levels of one layer before the next layer, they get reordered level by level.
+pub fn parse_dds(bytes: &[u8], color_space: TextureColorSpace, name: &str) -> Result<TextureData, VulkanError> {
+    let corrupt = |reason: String| VulkanError::CorruptTexture { name: name.to_string(), reason };
+    let unsupported = |reason: String| VulkanError::UnsupportedTexture { name: name.to_string(), reason };
+
+    let dds = ddsfile::Dds::read(bytes).map_err(|error| corrupt(error.to_string()))?;
+    let format = match &dds.header10 {
+        Some(header10) => dxgi_to_vk_format(header10.dxgi_format)
+            .ok_or_else(|| unsupported(format!("DXGI format {:?}", header10.dxgi_format)))?,
+        None => dds.get_d3d_format()
+            .and_then(|format| d3d_to_vk_format(format, color_space))
+            .ok_or_else(|| unsupported(format!("pixel format {:?}", dds.header.spf)))?
+    };
+    let block = format_block(format).ok_or_else(|| unsupported(format!("format {:?}", format)))?;
+
+    let extent = vk::Extent3D { width: dds.get_width(), height: dds.get_height().max(1), depth: dds.get_depth().max(1) };
+    let (kind, array_layers) = match &dds.header10 {
+        Some(header10) => {
+            let array_layers = header10.array_size.max(1);
+            let kind = match header10.resource_dimension {
+                ddsfile::D3D10ResourceDimension::Texture1D => ImageKind::Image1D,
+                ddsfile::D3D10ResourceDimension::Texture3D => ImageKind::Image3D,
+                _ if header10.misc_flag.contains(ddsfile::MiscFlag::TEXTURECUBE) => ImageKind::Cube,
+                _ => ImageKind::Image2D
+            };
+            (kind, array_layers)
+        }
+        None if dds.header.caps2.contains(ddsfile::Caps2::CUBEMAP) => (ImageKind::Cube, 1),
+        None if dds.header.caps2.contains(ddsfile::Caps2::VOLUME) => (ImageKind::Image3D, 1),
+        None => (ImageKind::Image2D, 1)
+    };
+    let layer_count = match kind {
+        ImageKind::Cube => array_layers * 6,
+        _ => array_layers
+    } as usize;
+    let mip_levels = dds.get_num_mipmap_levels().max(1);
+
+    let level_sizes: Vec<usize> = (0..mip_levels).map(|mip_level| block.layer_size(mip_extent(extent, mip_level))).collect();
+    let layer_stride: usize = level_sizes.iter().sum();
+    if dds.data.len() < layer_stride * layer_count {
+        return Err(corrupt(format!("{} bytes of texel data instead of {}", dds.data.len(), layer_stride * layer_count)));
+    }
+
+    let mut levels = Vec::with_capacity(mip_levels as usize);
+    let mut level_start = 0;
+    for level_size in &level_sizes {
+        let mut level = Vec::with_capacity(level_size * layer_count);
+        for layer in 0..layer_count {
+            let start = layer * layer_stride + level_start;
+            level.extend_from_slice(&dds.data[start..start + level_size]);
+        }
+        levels.push(level);
+        level_start += level_size;
+    }
+
+    let (data, level_offsets) = pack_levels(levels.iter().map(|level| level.as_slice()));
+    return Ok(TextureData { kind, extent, array_layers, format, data, level_offsets });
+}
+
+/// `name` only shows up in errors.
+pub fn decode_texture(bytes: &[u8], color_space: TextureColorSpace, name: &str) -> Result<TextureData, VulkanError> {
+    return match TextureFileFormat::detect(bytes) {
+        Some(TextureFileFormat::Png) => decode_png(bytes, color_space, name),
+        Some(TextureFileFormat::Jpeg) => decode_jpeg(bytes, color_space, name),
+        Some(TextureFileFormat::Ktx2) => parse_ktx2(bytes, name),
+        Some(TextureFileFormat::Dds) => parse_dds(bytes, color_space, name),
+        None => Err(VulkanError::UnsupportedTexture {
+            name: name.to_string(), reason: "not a PNG, JPEG, KTX2 or DDS file".to_string()
+        })
+    };
+}
+
+pub fn read_texture_file(path: &Path, color_space: TextureColorSpace) -> Result<TextureData, VulkanError> {
+    let bytes = std::fs::read(path)
+        .map_err(|source| VulkanError::TextureFile { path: path.to_path_buf(), source })?;
+    return decode_texture(&bytes, color_space, &path.display().to_string());
+}
+
+
+/// Fails when `format` needs a device feature that isn't enabled, or can't be sampled and copied to.
+pub fn check_texture_format_support(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    enabled_features: &EnabledFeatures,
+    format: vk::Format,
+    required: vk::FormatFeatureFlags
+) -> Result<(), VulkanError> {
+    if let Some(feature) = required_format_feature(format) {
+        if !enabled_features.is_enabled(feature) {
+            return Err(VulkanError::MissingDeviceFeatures(vec![feature]));
+        }
+    }
+
+    let properties = unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+    if !properties.optimal_tiling_features.contains(required) {
+        return Err(VulkanError::UnsupportedFormat { format, required });
+    }
+    return Ok(());
+}
+
+/// What `create_texture` can't take from the texture data.
+pub struct TextureImageConfiguration {
+    /// Can exceed the texture's levels, the rest stay undefined for mipmap generation.
+    pub mip_levels: u32,
+    /// SAMPLED and TRANSFER_DST are always added.
+    pub usage: vk::ImageUsageFlags,
+    /// Layout of the uploaded levels once the upload has finished.
+    pub final_layout: vk::ImageLayout
+}
+
+
+/// Creates a sampled image for `texture` and records its upload. The upload still has to be submitted.
+pub fn create_texture(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    allocator: &SharedAllocator,
+    uploads: &mut UploadManager,
+    texture: &TextureData,
+    config: &TextureImageConfiguration,
+    name: &str
+) -> Result<VulkanImage, VulkanError> {
+    let image_config = VulkanImageConfiguration {
+        kind: texture.kind,
+        extent: texture.extent,
+        array_layers: texture.array_layers,
+        format: texture.format,
+        mip_levels: config.mip_levels.max(texture.mip_levels()),
+        samples: vk::SampleCountFlags::TYPE_1,
+        usage: config.usage | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        tiling: vk::ImageTiling::OPTIMAL,
+        memory_usage: MemoryUsage::GpuOnly
+    };
+    let image = create_image(device, debug_utils, allocator, &image_config, name)?;
+
+    let uploaded_range = vk::ImageSubresourceRange { level_count: texture.mip_levels(), ..image.subresource_range() };
+    uploads.upload_image(image.handle, uploaded_range, &texture.copy_regions(), config.final_layout, &texture.data)?;
+    return Ok(image);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddsfile::{AlphaMode, D3D10ResourceDimension, Dds, DxgiFormat, NewDxgiParams};
+
+    /// Every byte of a layer's level is `(layer << 4) | mip_level`, so the reordering can be checked.
+    fn tagged_dds(format: DxgiFormat, size: u32, layers: u32, mip_levels: u32, is_cubemap: bool) -> Vec<u8> {
+        let mut dds = Dds::new_dxgi(NewDxgiParams {
+            height: size,
+            width: size,
+            depth: None,
+            format,
+            mipmap_levels: Some(mip_levels),
+            array_layers: Some(layers),
+            caps2: None,
+            is_cubemap,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Unknown
+        }).unwrap();
+
+        let block = format_block(dxgi_to_vk_format(format).unwrap()).unwrap();
+        let extent = vk::Extent3D { width: size, height: size, depth: 1 };
+        let mut data = Vec::new();
+        for layer in 0..layers {
+            for mip_level in 0..mip_levels {
+                let level_size = block.layer_size(mip_extent(extent, mip_level));
+                data.extend(std::iter::repeat_n(((layer << 4) | mip_level) as u8, level_size));
+            }
+        }
+        assert_eq!(data.len(), dds.data.len());
+        dds.data = data;
+
+        let mut bytes = Vec::new();
+        dds.write(&mut bytes).unwrap();
+        return bytes;
+    }
+
+    /// An R8_UNORM 2D texture with the level sizes it's given, the data holds the level index.
+    fn ktx2_file(width: u32, height: u32, level_sizes: &[usize]) -> Vec<u8> {
+        let level_index_end = ktx2::Header::LENGTH + ktx2::LevelIndex::LENGTH * level_sizes.len();
+        // The reader wants data after the data format descriptor, its content isn't looked at
+        let dfd_length = 4;
+        let header = ktx2::Header {
+            format: ktx2::Format::new(vk::Format::R8_UNORM.as_raw() as u32),
+            type_size: 1,
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: 0,
+            layer_count: 0,
+            face_count: 1,
+            level_count: level_sizes.len() as u32,
+            supercompression_scheme: None,
+            index: ktx2::Index {
+                dfd_byte_offset: level_index_end as u32,
+                dfd_byte_length: dfd_length as u32,
+                kvd_byte_offset: 0,
+                kvd_byte_length: 0,
+                sgd_byte_offset: 0,
+                sgd_byte_length: 0
+            }
+        };
+
+        let mut bytes = header.as_bytes().to_vec();
+        let mut level_offset = level_index_end + dfd_length;
+        for level_size in level_sizes {
+            let level = ktx2::LevelIndex {
+                byte_offset: level_offset as u64,
+                byte_length: *level_size as u64,
+                uncompressed_byte_length: *level_size as u64
+            };
+            bytes.extend_from_slice(&level.as_bytes());
+            level_offset += level_size;
+        }
+        bytes.extend_from_slice(&[0; 4]);
+        for (mip_level, level_size) in level_sizes.iter().enumerate() {
+            bytes.extend(std::iter::repeat_n(mip_level as u8, *level_size));
+        }
+        return bytes;
+    }
+
+    fn level(texture: &TextureData, mip_level: usize, size: usize) -> &[u8] {
+        let start = texture.level_offsets[mip_level];
+        return &texture.data[start..start + size];
+    }
+
+    #[test]
+    fn dds_cube_is_reordered_level_by_level() {
+        let bytes = tagged_dds(DxgiFormat::R8G8B8A8_UNorm, 4, 6, 2, true);
+        let texture = parse_dds(&bytes, TextureColorSpace::Srgb, "cube.dds").unwrap();
+
+        assert_eq!(texture.kind, ImageKind::Cube);
+        assert_eq!(texture.array_layers, 1);
+        assert_eq!(texture.layer_count(), 6);
+        assert_eq!(texture.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(texture.level_offsets, [0, 384]);
+        for face in 0..6 {
+            assert!(level(&texture, 0, 384)[face * 64..(face + 1) * 64].iter().all(|byte| *byte == (face << 4) as u8));
+            assert!(level(&texture, 1, 96)[face * 16..(face + 1) * 16].iter().all(|byte| *byte == (face << 4 | 1) as u8));
+        }
+    }
+
+    #[test]
+    fn dds_array_levels_start_aligned() {
+        // BC1 levels of one layer are 32, 8 and 8 bytes
+        let bytes = tagged_dds(DxgiFormat::BC1_UNorm_sRGB, 8, 3, 3, false);
+        let texture = parse_dds(&bytes, TextureColorSpace::Linear, "array.dds").unwrap();
+
+        assert_eq!(texture.kind, ImageKind::Image2D);
+        assert_eq!(texture.array_layers, 3);
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        assert_eq!(texture.level_offsets, [0, 96, 128]);
+        assert_eq!(texture.data.len(), 152);
+        // The padding between the second and third level
+        assert!(texture.data[120..128].iter().all(|byte| *byte == 0));
+        for layer in 0..3 {
+            assert!(level(&texture, 0, 96)[layer * 32..(layer + 1) * 32].iter().all(|byte| *byte == (layer << 4) as u8));
+            assert!(level(&texture, 2, 24)[layer * 8..(layer + 1) * 8].iter().all(|byte| *byte == (layer << 4 | 2) as u8));
+        }
+    }
+
+    #[test]
+    fn truncated_dds_is_corrupt() {
+        let bytes = tagged_dds(DxgiFormat::R8G8B8A8_UNorm, 4, 6, 2, true);
+        let result = parse_dds(&bytes[..bytes.len() - 1], TextureColorSpace::Srgb, "cube.dds");
+        assert!(matches!(result, Err(VulkanError::CorruptTexture { .. })));
+    }
+
+    #[test]
+    fn ktx2_levels_are_packed() {
+        let bytes = ktx2_file(5, 5, &[25, 4, 1]);
+        let texture = parse_ktx2(&bytes, "texture.ktx2").unwrap();
+
+        assert_eq!(texture.kind, ImageKind::Image2D);
+        assert_eq!(texture.extent, vk::Extent3D { width: 5, height: 5, depth: 1 });
+        assert_eq!(texture.format, vk::Format::R8_UNORM);
+        assert_eq!(texture.level_offsets, [0, 32, 48]);
+        assert_eq!(level(&texture, 0, 25), [0; 25]);
+        assert_eq!(level(&texture, 1, 4), [1; 4]);
+        assert_eq!(level(&texture, 2, 1), [2; 1]);
+    }
+
+    #[test]
+    fn ktx2_with_wrong_level_size_is_corrupt() {
+        let bytes = ktx2_file(5, 5, &[25, 3, 1]);
+        let result = parse_ktx2(&bytes, "texture.ktx2");
+        assert!(matches!(result, Err(VulkanError::CorruptTexture { .. })));
+    }
+
+    #[test]
+    fn truncated_ktx2_is_corrupt() {
+        let bytes = ktx2_file(5, 5, &[25, 4, 1]);
+        let result = parse_ktx2(&bytes[..bytes.len() - 1], "texture.ktx2");
+        assert!(matches!(result, Err(VulkanError::CorruptTexture { .. })));
+    }
+
+    #[test]
+    fn astc_block_sizes() {
+        let cases = [
+            (vk::Format::ASTC_4X4_UNORM_BLOCK, 4, 4),
+            (vk::Format::ASTC_4X4_SRGB_BLOCK, 4, 4),
+            (vk::Format::ASTC_5X4_UNORM_BLOCK, 5, 4),
+            (vk::Format::ASTC_10X5_SRGB_BLOCK, 10, 5),
+            (vk::Format::ASTC_12X12_UNORM_BLOCK, 12, 12),
+            (vk::Format::ASTC_12X12_SRGB_BLOCK, 12, 12)
+        ];
+        for (format, width, height) in cases {
+            assert_eq!(format_block(format), Some(FormatBlock { width, height, bytes: 16 }), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn partial_blocks_count_as_whole_ones() {
+        let bc1 = format_block(vk::Format::BC1_RGBA_UNORM_BLOCK).unwrap();
+        assert_eq!(bc1.layer_size(vk::Extent3D { width: 5, height: 1, depth: 1 }), 16);
+        assert_eq!(bc1.layer_size(vk::Extent3D { width: 8, height: 8, depth: 1 }), 32);
+        let astc = format_block(vk::Format::ASTC_10X5_UNORM_BLOCK).unwrap();
+        assert_eq!(astc.layer_size(vk::Extent3D { width: 11, height: 5, depth: 2 }), 64);
+    }
+}
@@ -2,30 +2,43 @@ use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use ash::vk;
+use crate::vulkan_core::error::VulkanError;
 
 
-pub fn read_shader_code(shader_path: &Path) -> Vec<u32> {
-    let spv_file = File::open(shader_path).expect("MEH");
+pub fn read_shader_code(shader_path: &Path) -> Result<Vec<u32>, VulkanError> {
+    let shader_file_error = |source| VulkanError::ShaderFile { path: shader_path.to_path_buf(), source };
+
+    let spv_file = File::open(shader_path).map_err(shader_file_error)?;
     let byte_code: Vec<u8> = spv_file.bytes().filter_map(|byte| byte.ok()).collect();
 
     let mut cursor = std::io::Cursor::new(byte_code);
-    let spv = ash::util::read_spv(&mut cursor).expect("MEH");
-    return spv;
+    let spv = ash::util::read_spv(&mut cursor).map_err(shader_file_error)?;
+    return Ok(spv);
+}
+
+
+/// Rounds `value` up to a multiple of `alignment`, which doesn't have to be a power of two. 0 counts as 1.
+pub fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    let alignment = alignment.max(1);
+    return value.div_ceil(alignment) * alignment;
 }
 
 
-pub fn find_memory_type_index(
-    memory_requirements: vk::MemoryRequirements,
-    memory_properties: &vk::PhysicalDeviceMemoryProperties,
-    memory_property_flags: vk::MemoryPropertyFlags
-) -> u32 {
-    for i in 0..memory_properties.memory_type_count {
-        let property_flags = memory_properties.memory_types[0].property_flags;
-        let type_bits_satisfied = (memory_requirements.memory_type_bits & (1 << i)) != 0;
-        if property_flags.contains(memory_property_flags) && type_bits_satisfied {
-            return i
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_up_rounds_to_multiples() {
+        let cases = [
+            (0, 256, 0), (1, 256, 256), (256, 256, 256), (257, 256, 512),
+            // nonCoherentAtomSize and texel block sizes aren't always powers of two
+            (10, 12, 12), (24, 12, 24), (25, 12, 36),
+            (7, 1, 7), (7, 0, 7),
+            (u64::MAX - 255, 256, u64::MAX - 255)
+        ];
+        for (value, alignment, expected) in cases {
+            assert_eq!(align_up(value, alignment), expected, "align_up({}, {})", value, alignment);
         }
     }
-
-    panic!();
-}
\ No newline at end of file
+}
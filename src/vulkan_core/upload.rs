@@ -0,0 +1,596 @@
+use std::collections::VecDeque;
+use ash::vk;
+use crate::vulkan_core::QueueFamily;
+use crate::vulkan_core::allocator::SharedAllocator;
+use crate::vulkan_core::buffer_factory::{create_buffer, VulkanBuffer, VulkanBufferConfiguration};
+use crate::vulkan_core::cmd::{create_command_buffer, create_command_pool};
+use crate::vulkan_core::debug::DebugUtilsDevice;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::memory_usage::MemoryUsage;
+use crate::vulkan_core::queues::DeviceQueue;
+use crate::vulkan_core::sync::{create_fence, create_semaphore, create_timeline_semaphore};
+use crate::vulkan_core::tools::align_up;
+
+
+/// Works for every texel size that is a power of two, and for vkCmdCopyBuffer in general.
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+
+pub struct UploadManagerConfiguration {
+    /// Size of the staging ring. Uploads that don't fit get a temporary staging buffer.
+    pub staging_buffer_size: vk::DeviceSize
+}
+
+impl Default for UploadManagerConfiguration {
+    fn default() -> Self {
+        return UploadManagerConfiguration {
+            staging_buffer_size: 16 * 1024 * 1024
+        };
+    }
+}
+
+
+/// The queue that uses the uploaded resources and acquires their ownership.
+#[derive(Clone, Copy)]
+pub struct GraphicsQueue {
+    pub handle: vk::Queue,
+    pub family: QueueFamily
+}
+
+
+/// Identifies a submitted batch of uploads. Later batches have larger tickets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UploadTicket(pub u64);
+
+
+/// The uploads recorded since the last submit.
+struct RecordingBatch {
+    command_buffer: vk::CommandBuffer,
+    // Recorded on the graphics queue when the transfer queue belongs to another family
+    acquire_buffer_barriers: Vec<vk::BufferMemoryBarrier>,
+    acquire_image_barriers: Vec<vk::ImageMemoryBarrier>,
+    temporary_buffers: Vec<VulkanBuffer>,
+    callbacks: Vec<Box<dyn FnOnce()>>
+}
+
+struct SubmittedBatch {
+    ticket: UploadTicket,
+    command_buffer: vk::CommandBuffer,
+    acquire_command_buffer: Option<vk::CommandBuffer>,
+    ownership_semaphore: Option<vk::Semaphore>,
+    /// Only without timeline semaphores.
+    fence: Option<vk::Fence>,
+    staging_end: u64,
+    temporary_buffers: Vec<VulkanBuffer>,
+    callbacks: Vec<Box<dyn FnOnce()>>
+}
+
+
+/// Copies CPU data into device-local buffers and images through a staging ring on the transfer queue,
+/// or on the graphics queue when there is no separate transfer family. Resources end up owned by the graphics family.
+pub struct UploadManager {
+    device: SharedDevice,
+    debug_utils: DebugUtilsDevice,
+    allocator: SharedAllocator,
+
+    transfer_queue: vk::Queue,
+    transfer_family: QueueFamily,
+    transfer_command_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    graphics_family: QueueFamily,
+    graphics_command_pool: vk::CommandPool,
+
+    /// Signals the ticket value of every finished batch. Fences are used instead when it's `None`.
+    timeline_semaphore: Option<vk::Semaphore>,
+
+    staging_buffer: VulkanBuffer,
+    // Both only grow, the ring offset is the value modulo the staging buffer size
+    staging_head: u64,
+    staging_tail: u64,
+
+    recording: Option<RecordingBatch>,
+    submitted: VecDeque<SubmittedBatch>,
+    last_ticket: u64,
+    completed_ticket: u64
+}
+
+
+/// `timeline` is whether the timeline semaphore feature is enabled.
+pub fn create_upload_manager(
+    device: &SharedDevice,
+    debug_utils: &DebugUtilsDevice,
+    allocator: &SharedAllocator,
+    transfer_queue: &DeviceQueue,
+    graphics_queue: GraphicsQueue,
+    timeline: bool,
+    config: &UploadManagerConfiguration
+) -> Result<UploadManager, VulkanError> {
+    let staging_config = VulkanBufferConfiguration {
+        size: config.staging_buffer_size,
+        memory_usage: MemoryUsage::CpuOnly,
+        buffer_usage: vk::BufferUsageFlags::TRANSFER_SRC
+    };
+    let staging_buffer = create_buffer(device, debug_utils, allocator, &staging_config, "upload.staging_ring")?;
+
+    let transfer_command_pool = create_command_pool(device, debug_utils, &transfer_queue.family, "upload.transfer_command_pool")?;
+    let graphics_command_pool = create_command_pool(device, debug_utils, &graphics_queue.family, "upload.graphics_command_pool")?;
+    let timeline_semaphore = match timeline {
+        true => Some(create_timeline_semaphore(device, debug_utils, 0, "upload.timeline")?),
+        false => None
+    };
+
+    // A transfer queue of the graphics family gets no ordering against rendering without semaphores,
+    // the graphics queue itself orders the uploads before every later frame
+    let transfer_queue_handle = match transfer_queue.family.index == graphics_queue.family.index {
+        true => graphics_queue.handle,
+        false => transfer_queue.handle
+    };
+
+    return Ok(UploadManager {
+        device: device.clone(),
+        debug_utils: debug_utils.clone(),
+        allocator: allocator.clone(),
+        transfer_queue: transfer_queue_handle,
+        transfer_family: transfer_queue.family,
+        transfer_command_pool,
+        graphics_queue: graphics_queue.handle,
+        graphics_family: graphics_queue.family,
+        graphics_command_pool,
+        timeline_semaphore,
+        staging_buffer,
+        staging_head: 0,
+        staging_tail: 0,
+        recording: None,
+        submitted: VecDeque::new(),
+        last_ticket: 0,
+        completed_ticket: 0
+    });
+}
+
+
+impl UploadManager {
+    fn needs_ownership_transfer(&self) -> bool {
+        return self.transfer_family.index != self.graphics_family.index;
+    }
+
+    /// Copies `data` into `buffer` at `offset` once the batch is submitted.
+    /// The buffer needs TRANSFER_DST usage and must not be in use by the GPU.
+    pub fn upload_buffer(&mut self, buffer: &VulkanBuffer, offset: vk::DeviceSize, data: &[u8]) -> Result<(), VulkanError> {
+        if data.is_empty() { return Ok(()); }
+
+        let (staging_buffer, staging_offset) = self.stage(data)?;
+        let command_buffer = self.recording()?.command_buffer;
+        let region = vk::BufferCopy { src_offset: staging_offset, dst_offset: offset, size: data.len() as vk::DeviceSize };
+        unsafe { self.device.cmd_copy_buffer(command_buffer, staging_buffer, buffer.handle, &[region]) };
+
+        if !self.needs_ownership_transfer() { return Ok(()); }
+
+        let ownership_barrier = vk::BufferMemoryBarrier::builder()
+            .src_queue_family_index(self.transfer_family.index)
+            .dst_queue_family_index(self.graphics_family.index)
+            .buffer(buffer.handle)
+            .offset(offset)
+            .size(data.len() as vk::DeviceSize)
+            .build();
+        let release_barrier = vk::BufferMemoryBarrier { src_access_mask: vk::AccessFlags::TRANSFER_WRITE, ..ownership_barrier };
+        let acquire_barrier = vk::BufferMemoryBarrier { dst_access_mask: vk::AccessFlags::MEMORY_READ, ..ownership_barrier };
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(), &[], &[release_barrier], &[]
+            );
+        }
+        self.recording()?.acquire_buffer_barriers.push(acquire_barrier);
+        return Ok(());
+    }
+
+    /// Copies `data` into `image` and leaves it in `final_layout`. The region buffer offsets are relative to `data`.
+    /// Everything in `subresource_range` gets its previous contents discarded.
+    pub fn upload_image(
+        &mut self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        regions: &[vk::BufferImageCopy],
+        final_layout: vk::ImageLayout,
+        data: &[u8]
+    ) -> Result<(), VulkanError> {
+        let (staging_buffer, staging_offset) = self.stage(data)?;
+        let command_buffer = self.recording()?.command_buffer;
+
+        let regions: Vec<vk::BufferImageCopy> = regions.iter()
+            .map(|region| vk::BufferImageCopy { buffer_offset: region.buffer_offset + staging_offset, ..*region })
+            .collect();
+
+        let to_transfer_barrier = vk::ImageMemoryBarrier::builder()
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        // Without a transfer between families the layout transition happens right here,
+        // otherwise it is part of the release and acquire pair
+        let ownership = self.needs_ownership_transfer();
+        let (src_family, dst_family) = match ownership {
+            true => (self.transfer_family.index, self.graphics_family.index),
+            false => (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        };
+        let final_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(match ownership {
+                true => vk::AccessFlags::empty(),
+                false => vk::AccessFlags::MEMORY_READ
+            })
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(final_layout)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+        let final_stage = match ownership {
+            true => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            false => vk::PipelineStageFlags::ALL_COMMANDS
+        };
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(), &[], &[], &[to_transfer_barrier]
+            );
+            self.device.cmd_copy_buffer_to_image(
+                command_buffer, staging_buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions
+            );
+            self.device.cmd_pipeline_barrier(
+                command_buffer, vk::PipelineStageFlags::TRANSFER, final_stage,
+                vk::DependencyFlags::empty(), &[], &[], &[final_barrier]
+            );
+        }
+
+        if ownership {
+            let acquire_barrier = vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_access_mask: vk::AccessFlags::MEMORY_READ,
+                ..final_barrier
+            };
+            self.recording()?.acquire_image_barriers.push(acquire_barrier);
+        }
+        return Ok(());
+    }
+
+    /// Submits everything recorded so far. Returns the last ticket when there is nothing to submit.
+    pub fn submit(&mut self) -> Result<UploadTicket, VulkanError> {
+        let Some(recording) = self.recording.take() else { return Ok(UploadTicket(self.last_ticket)); };
+        let ticket = UploadTicket(self.last_ticket + 1);
+        let name = format!("upload[{}]", ticket.0);
+        let ownership = !recording.acquire_buffer_barriers.is_empty() || !recording.acquire_image_barriers.is_empty();
+
+        unsafe {
+            if !ownership {
+                // Same queue as rendering, this makes the copies visible to everything submitted later
+                let memory_barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                    .build();
+                self.device.cmd_pipeline_barrier(
+                    recording.command_buffer, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::DependencyFlags::empty(), &[memory_barrier], &[], &[]
+                );
+            }
+            self.device.end_command_buffer(recording.command_buffer)
+                .context("vkEndCommandBuffer", &name)?;
+        }
+
+        let fence = match self.timeline_semaphore {
+            Some(_) => None,
+            None => Some(create_fence(&self.device, &self.debug_utils, false, &format!("{}.fence", name))?)
+        };
+
+        let mut batch = SubmittedBatch {
+            ticket,
+            command_buffer: recording.command_buffer,
+            acquire_command_buffer: None,
+            ownership_semaphore: None,
+            fence,
+            staging_end: self.staging_head,
+            temporary_buffers: recording.temporary_buffers,
+            callbacks: recording.callbacks
+        };
+
+        if !ownership {
+            self.submit_signaling_ticket(self.transfer_queue, batch.command_buffer, None, ticket, batch.fence)?;
+        } else {
+            let ownership_semaphore = create_semaphore(&self.device, &self.debug_utils, &format!("{}.ownership", name))?;
+            batch.ownership_semaphore = Some(ownership_semaphore);
+
+            let command_buffers = [batch.command_buffer];
+            let signal_semaphores = [ownership_semaphore];
+            let transfer_submit = vk::SubmitInfo::builder()
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores);
+            unsafe {
+                self.device.queue_submit(self.transfer_queue, &[transfer_submit.build()], vk::Fence::null())
+                    .context("vkQueueSubmit", &name)?;
+            }
+
+            let acquire_command_buffer = create_command_buffer(
+                &self.device, &self.debug_utils, self.graphics_command_pool, vk::CommandBufferLevel::PRIMARY,
+                &format!("{}.acquire", name)
+            )?;
+            batch.acquire_command_buffer = Some(acquire_command_buffer);
+
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe {
+                self.device.begin_command_buffer(acquire_command_buffer, &begin_info)
+                    .context("vkBeginCommandBuffer", &name)?;
+                self.device.cmd_pipeline_barrier(
+                    acquire_command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::DependencyFlags::empty(), &[], &recording.acquire_buffer_barriers, &recording.acquire_image_barriers
+                );
+                self.device.end_command_buffer(acquire_command_buffer)
+                    .context("vkEndCommandBuffer", &name)?;
+            }
+
+            self.submit_signaling_ticket(
+                self.graphics_queue, acquire_command_buffer, Some(ownership_semaphore), ticket, batch.fence
+            )?;
+        }
+
+        self.last_ticket = ticket.0;
+        self.submitted.push_back(batch);
+        return Ok(ticket);
+    }
+
+    /// Submits and calls `callback` from `poll` once the uploads have finished.
+    pub fn submit_and_notify<F: FnOnce() + 'static>(&mut self, callback: F) -> Result<UploadTicket, VulkanError> {
+        self.recording()?.callbacks.push(Box::new(callback));
+        return self.submit();
+    }
+
+    pub fn submit_and_wait(&mut self) -> Result<(), VulkanError> {
+        let ticket = self.submit()?;
+        return self.wait(ticket);
+    }
+
+    /// Blocking version of `upload_buffer`.
+    pub fn upload_buffer_now(&mut self, buffer: &VulkanBuffer, offset: vk::DeviceSize, data: &[u8]) -> Result<(), VulkanError> {
+        self.upload_buffer(buffer, offset, data)?;
+        return self.submit_and_wait();
+    }
+
+    /// Blocking version of `upload_image`.
+    pub fn upload_image_now(
+        &mut self,
+        image: vk::Image,
+        subresource_range: vk::ImageSubresourceRange,
+        regions: &[vk::BufferImageCopy],
+        final_layout: vk::ImageLayout,
+        data: &[u8]
+    ) -> Result<(), VulkanError> {
+        self.upload_image(image, subresource_range, regions, final_layout, data)?;
+        return self.submit_and_wait();
+    }
+
+    pub fn is_complete(&mut self, ticket: UploadTicket) -> Result<bool, VulkanError> {
+        self.poll()?;
+        return Ok(ticket.0 <= self.completed_ticket);
+    }
+
+    pub fn wait(&mut self, ticket: UploadTicket) -> Result<(), VulkanError> {
+        if ticket.0 <= self.completed_ticket { return Ok(()); }
+
+        match self.timeline_semaphore {
+            Some(timeline_semaphore) => {
+                let semaphores = [timeline_semaphore];
+                let values = [ticket.0];
+                let wait_info = vk::SemaphoreWaitInfo::builder()
+                    .semaphores(&semaphores)
+                    .values(&values);
+                unsafe { self.device.wait_semaphores(&wait_info, u64::MAX).context("vkWaitSemaphores", "upload.timeline")? };
+            }
+            None => {
+                let fences: Vec<vk::Fence> = self.submitted.iter()
+                    .filter(|batch| batch.ticket <= ticket)
+                    .filter_map(|batch| batch.fence)
+                    .collect();
+                if !fences.is_empty() {
+                    unsafe { self.device.wait_for_fences(&fences, true, u64::MAX).context("vkWaitForFences", "upload")? };
+                }
+            }
+        }
+
+        return self.poll();
+    }
+
+    /// Releases finished batches and calls their callbacks. Called once per frame by the render base.
+    pub fn poll(&mut self) -> Result<(), VulkanError> {
+        let completed_ticket = match self.timeline_semaphore {
+            Some(timeline_semaphore) => unsafe {
+                self.device.get_semaphore_counter_value(timeline_semaphore)
+                    .context("vkGetSemaphoreCounterValue", "upload.timeline")?
+            },
+            None => {
+                let mut completed_ticket = self.completed_ticket;
+                for batch in &self.submitted {
+                    let Some(fence) = batch.fence else { break; };
+                    let signaled = unsafe { self.device.get_fence_status(fence).context("vkGetFenceStatus", "upload")? };
+                    if !signaled { break; }
+                    completed_ticket = batch.ticket.0;
+                }
+                completed_ticket
+            }
+        };
+
+        while self.submitted.front().is_some_and(|batch| batch.ticket.0 <= completed_ticket) {
+            let batch = self.submitted.pop_front().unwrap();
+            self.retire(batch);
+        }
+        self.completed_ticket = self.completed_ticket.max(completed_ticket);
+        return Ok(());
+    }
+
+    fn retire(&mut self, batch: SubmittedBatch) {
+        unsafe {
+            self.device.free_command_buffers(self.transfer_command_pool, &[batch.command_buffer]);
+            if let Some(acquire_command_buffer) = batch.acquire_command_buffer {
+                self.device.free_command_buffers(self.graphics_command_pool, &[acquire_command_buffer]);
+            }
+            if let Some(ownership_semaphore) = batch.ownership_semaphore {
+                self.device.destroy_semaphore(ownership_semaphore, None);
+            }
+            if let Some(fence) = batch.fence {
+                self.device.destroy_fence(fence, None);
+            }
+        }
+        self.staging_tail = batch.staging_end;
+        drop(batch.temporary_buffers);
+        for callback in batch.callbacks {
+            callback();
+        }
+    }
+
+    fn submit_signaling_ticket(
+        &self,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        wait_semaphore: Option<vk::Semaphore>,
+        ticket: UploadTicket,
+        fence: Option<vk::Fence>
+    ) -> Result<(), VulkanError> {
+        let command_buffers = [command_buffer];
+        let wait_semaphores: Vec<vk::Semaphore> = wait_semaphore.into_iter().collect();
+        let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+        // Binary semaphores ignore their value, but the counts have to match
+        let wait_values = vec![0; wait_semaphores.len()];
+        let signal_semaphores: Vec<vk::Semaphore> = self.timeline_semaphore.into_iter().collect();
+        let signal_values = vec![ticket.0; signal_semaphores.len()];
+
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+        let mut submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        if self.timeline_semaphore.is_some() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+
+        return unsafe {
+            self.device.queue_submit(queue, &[submit_info.build()], fence.unwrap_or(vk::Fence::null()))
+                .context("vkQueueSubmit", &format!("upload[{}]", ticket.0))
+        };
+    }
+
+    /// The batch being recorded, begun on first use.
+    fn recording(&mut self) -> Result<&mut RecordingBatch, VulkanError> {
+        if self.recording.is_none() {
+            let command_buffer = create_command_buffer(
+                &self.device, &self.debug_utils, self.transfer_command_pool, vk::CommandBufferLevel::PRIMARY,
+                &format!("upload[{}].transfer", self.last_ticket + 1)
+            )?;
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe {
+                self.device.begin_command_buffer(command_buffer, &begin_info)
+                    .context("vkBeginCommandBuffer", "upload")?;
+            }
+
+            self.recording = Some(RecordingBatch {
+                command_buffer,
+                acquire_buffer_barriers: Vec::new(),
+                acquire_image_barriers: Vec::new(),
+                temporary_buffers: Vec::new(),
+                callbacks: Vec::new()
+            });
+        }
+        return Ok(self.recording.as_mut().unwrap());
+    }
+
+    /// Copies `data` into staging memory and returns the buffer and offset to copy from.
+    fn stage(&mut self, data: &[u8]) -> Result<(vk::Buffer, vk::DeviceSize), VulkanError> {
+        let size = data.len() as vk::DeviceSize;
+        if size > self.staging_buffer.size {
+            return self.stage_temporary(data);
+        }
+
+        let ring_offset = self.reserve_staging(size)?;
+        let allocation = &self.staging_buffer.allocation;
+        unsafe {
+            let mapped = allocation.mapped_ptr().unwrap().cast::<u8>();
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped.add(ring_offset as usize), data.len());
+        }
+        allocation.flush(ring_offset, size)?;
+
+        return Ok((self.staging_buffer.handle, ring_offset));
+    }
+
+    fn stage_temporary(&mut self, data: &[u8]) -> Result<(vk::Buffer, vk::DeviceSize), VulkanError> {
+        let config = VulkanBufferConfiguration {
+            size: data.len() as vk::DeviceSize,
+            memory_usage: MemoryUsage::CpuOnly,
+            buffer_usage: vk::BufferUsageFlags::TRANSFER_SRC
+        };
+        let buffer = create_buffer(&self.device, &self.debug_utils, &self.allocator, &config, "upload.temporary_staging")?;
+        unsafe {
+            let mapped = buffer.allocation.mapped_ptr().unwrap().cast::<u8>();
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped, data.len());
+        }
+        buffer.allocation.flush(0, config.size)?;
+
+        let handle = buffer.handle;
+        self.recording()?.temporary_buffers.push(buffer);
+        return Ok((handle, 0));
+    }
+
+    /// Waits for older batches, and submits the current one, until `size` contiguous bytes are free.
+    fn reserve_staging(&mut self, size: vk::DeviceSize) -> Result<vk::DeviceSize, VulkanError> {
+        let ring_size = self.staging_buffer.size;
+        loop {
+            let mut start = align_up(self.staging_head, STAGING_ALIGNMENT);
+            if start % ring_size + size > ring_size {
+                // Doesn't fit before the end of the ring, skip to the start
+                start = align_up(start, ring_size);
+            }
+            if start + size - self.staging_tail <= ring_size {
+                self.staging_head = start + size;
+                return Ok(start % ring_size);
+            }
+
+            if let Some(oldest) = self.submitted.front() {
+                let ticket = oldest.ticket;
+                self.wait(ticket)?;
+            } else if self.recording.is_some() {
+                self.submit()?;
+            } else {
+                // Nothing uses the ring anymore
+                self.staging_head = align_up(self.staging_head, ring_size);
+                self.staging_tail = self.staging_head;
+            }
+        }
+    }
+}
+
+impl Drop for UploadManager {
+    /// The device has to be idle, the render base waits for it before its fields drop.
+    fn drop(&mut self) {
+        while let Some(batch) = self.submitted.pop_front() {
+            self.retire(batch);
+        }
+        unsafe {
+            if let Some(timeline_semaphore) = self.timeline_semaphore {
+                self.device.destroy_semaphore(timeline_semaphore, None);
+            }
+            // Destroying the pools frees the command buffers of unsubmitted batches
+            self.device.destroy_command_pool(self.transfer_command_pool, None);
+            self.device.destroy_command_pool(self.graphics_command_pool, None);
+        }
+    }
+}
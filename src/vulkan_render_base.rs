@@ -1,14 +1,71 @@
 #![allow(dead_code)]
 
+use std::path::Path;
 use std::ptr::{null, null_mut};
 use ash::vk;
-use ash::vk::QueueFlags;
 use crate::vulkan_core;
-use crate::vulkan_core::{create_device, create_physical_device, create_surface, get_unique_queue_families, QueueFamily, SurfaceInfo};
+use crate::vulkan_core::{MEMORY_BUDGET_EXTENSION, create_device, create_physical_device, create_surface, get_unique_queue_families, required_device_extensions, QueueFamily, SurfaceInfo};
+use crate::vulkan_core::allocator::{create_allocator, MemoryAllocatorConfiguration, SharedAllocator};
+use crate::vulkan_core::api_version::{effective_api_version, ApiPath, DeviceCommands};
 use crate::vulkan_core::buffer_factory::{VulkanBuffer, VulkanBufferConfiguration};
-use crate::vulkan_core::cmd::{create_command_buffer, create_command_pool};
+use crate::vulkan_core::image_factory::{mip_level_count, VulkanImage, VulkanImageConfiguration};
+use crate::vulkan_core::mapped_buffer::{create_mapped_buffer, MappedBuffer};
+use crate::vulkan_core::memory_report::{create_memory_report, query_heap_usage, MemoryBudgetConfiguration, MemoryBudgetWatcher, MemoryReport};
+use crate::vulkan_core::memory_usage::MemoryUsage;
+use crate::vulkan_core::debug::{create_debug_messenger, DebugMessenger, DebugMessengerConfiguration, DebugUtilsDevice};
+use crate::vulkan_core::error::{VkResultExt, VulkanError};
+use crate::vulkan_core::deletion_queue::DeletionQueue;
+use crate::vulkan_core::device::SharedDevice;
+use crate::vulkan_core::instance::{InstanceBuilder, InstanceInfo, SharedInstance, DEBUG_UTILS_EXTENSION};
+use crate::vulkan_core::frame_ring_buffer::{create_frame_ring_buffer, FrameRingBuffer, FrameRingBufferConfiguration};
+use crate::vulkan_core::features::{negotiate_features, query_supported_features, DeviceFeatureRequirements, EnabledFeatures};
+use crate::vulkan_core::offscreen::{create_offscreen_targets, OffscreenTargets};
+use crate::vulkan_core::cmd::{create_command_buffer, create_command_pool, submit_one_time_commands};
+use crate::vulkan_core::texture::{check_texture_format_support, create_texture, read_texture_file, TextureImageConfiguration, TextureLoadConfiguration};
+use crate::vulkan_core::mipmap::{create_mipmap_generator, MipmapGenerator};
+use crate::vulkan_core::sampler::{create_sampler_cache, SamplerCache};
+use crate::vulkan_core::bindless::{create_bindless_table, BindlessResourceType, BindlessTable, BindlessTableConfiguration};
+use crate::vulkan_core::descriptor::{create_descriptor_allocator, create_descriptor_layout_cache, DescriptorAllocator, DescriptorAllocatorConfiguration, DescriptorLayoutCache};
+use crate::vulkan_core::queues::{get_device_queue, DeviceQueue, QueuePlan, QueueRequests};
 use crate::vulkan_core::sync::{create_fence, create_semaphore};
 use crate::vulkan_core::swapchain::{create_swapchain, SwapchainInfo};
+use crate::vulkan_core::upload::{create_upload_manager, GraphicsQueue, UploadManager, UploadManagerConfiguration};
+
+
+pub struct VulkanRenderBaseConfiguration {
+    pub buffering_strategy: u32,
+    pub instance_builder: InstanceBuilder,
+    /// Only used when the instance has VK_EXT_debug_utils enabled, which validation does.
+    pub debug_messenger: DebugMessengerConfiguration,
+    pub device_features: DeviceFeatureRequirements,
+    pub queue_requests: QueueRequests,
+    pub allocator: MemoryAllocatorConfiguration,
+    pub uploads: UploadManagerConfiguration,
+    pub frame_ring_buffer: FrameRingBufferConfiguration,
+    /// Used for the persistent descriptor allocator and each frame's.
+    pub descriptor_allocator: DescriptorAllocatorConfiguration,
+    /// Only used when the DescriptorIndexing feature is enabled.
+    pub bindless: BindlessTableConfiguration,
+    pub memory_budget: MemoryBudgetConfiguration
+}
+
+impl Default for VulkanRenderBaseConfiguration {
+    fn default() -> Self {
+        return VulkanRenderBaseConfiguration {
+            buffering_strategy: 3,
+            instance_builder: InstanceBuilder::new(),
+            debug_messenger: DebugMessengerConfiguration::default(),
+            device_features: DeviceFeatureRequirements::default(),
+            queue_requests: QueueRequests::default(),
+            allocator: MemoryAllocatorConfiguration::default(),
+            uploads: UploadManagerConfiguration::default(),
+            frame_ring_buffer: FrameRingBufferConfiguration::default(),
+            descriptor_allocator: DescriptorAllocatorConfiguration::default(),
+            bindless: BindlessTableConfiguration::default(),
+            memory_budget: MemoryBudgetConfiguration::default()
+        };
+    }
+}
 
 
 pub struct FramePreparation {
@@ -22,21 +79,87 @@ pub struct FrameSubmitData {
 }
 
 
+/// What a frame renders into: the swapchain of a window or offscreen images in headless mode.
+pub enum RenderTarget {
+    Swapchain(SwapchainInfo),
+    Offscreen(OffscreenTargets)
+}
+
+impl RenderTarget {
+    pub fn extent(&self) -> vk::Extent2D {
+        return match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.extent,
+            RenderTarget::Offscreen(offscreen) => offscreen.extent
+        };
+    }
+
+    pub fn color_format(&self) -> vk::Format {
+        return match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.color_format,
+            RenderTarget::Offscreen(offscreen) => offscreen.color_format
+        };
+    }
+
+    pub fn image(&self, image_index: u32) -> vk::Image {
+        return match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.images[image_index as usize],
+            RenderTarget::Offscreen(offscreen) => offscreen.color_images[image_index as usize].handle
+        };
+    }
+
+    pub fn image_view(&self, image_index: u32) -> vk::ImageView {
+        return match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.image_views[image_index as usize],
+            RenderTarget::Offscreen(offscreen) => offscreen.color_images[image_index as usize].view
+        };
+    }
+
+    /// Only offscreen targets come with a depth buffer.
+    pub fn depth_image_view(&self) -> Option<vk::ImageView> {
+        return match self {
+            RenderTarget::Swapchain(_) => None,
+            RenderTarget::Offscreen(offscreen) => Some(offscreen.depth_image.view)
+        };
+    }
+
+    /// The layout the color image has to be in when the frame is submitted.
+    pub fn final_layout(&self) -> vk::ImageLayout {
+        return match self {
+            RenderTarget::Swapchain(_) => vk::ImageLayout::PRESENT_SRC_KHR,
+            RenderTarget::Offscreen(_) => vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+        };
+    }
+}
+
+
+/// Fields drop in declaration order after `Drop::drop` released the frame objects: the render target
+/// before its surface, the messenger late enough to report leaks, and the instance last.
 pub struct VulkanRenderBase {
-    pub instance: ash::Instance,
     pub physical_device: vk::PhysicalDevice,
-    pub device: ash::Device,
-    pub surface: SurfaceInfo,
-    pub swapchain: SwapchainInfo,
+    pub device: SharedDevice,
+    pub enabled_features: EnabledFeatures,
+    /// Dynamic rendering and synchronization2 commands, through core 1.3 or the KHR extensions.
+    pub commands: DeviceCommands,
+    pub debug_utils: DebugUtilsDevice,
+    pub render_target: RenderTarget,
+    pub surface: Option<SurfaceInfo>,
 
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Buffers and images sub-allocate their memory from here.
+    pub allocator: SharedAllocator,
+    pub memory_budget_watcher: MemoryBudgetWatcher,
 
     pub unique_queue_families: Vec<QueueFamily>,
     pub graphics_queue_family: QueueFamily,
     pub present_queue_family: QueueFamily,
     pub graphics_queue: vk::Queue,
-    pub compute_queue: vk::Queue,
     pub present_queue: vk::Queue,
+    /// Dedicated compute-only families are preferred, otherwise these share the graphics family.
+    pub compute_queues: Vec<DeviceQueue>,
+    /// Dedicated transfer-only families are preferred, otherwise these share a compute or the graphics family.
+    pub transfer_queues: Vec<DeviceQueue>,
+    /// Staging uploads into device-local buffers and images, on the first transfer queue.
+    pub uploads: UploadManager,
 
     pub command_pool: vk::CommandPool,
     pub command_buffers: Vec<vk::CommandBuffer>,
@@ -49,41 +172,86 @@ pub struct VulkanRenderBase {
     pub frames_in_flight: u32,
 
     pub frame_index: u32,
-    pub frame_in_flight_index: u32
+    pub frame_in_flight_index: u32,
+    /// Counts submitted frames, the frame being recorded has this number.
+    pub frame_number: u64,
+    /// Per in flight fence, the number of the frame it was last submitted with.
+    pub in_flight_frame_numbers: Vec<Option<u64>>,
+    /// Every frame below this number has finished on the GPU.
+    pub completed_frame_count: u64,
+    pub deletion_queue: DeletionQueue,
+    /// Transient uniform and vertex data, the region of the current frame in flight.
+    pub frame_ring_buffer: FrameRingBuffer,
+    /// Samplers are shared by state, get them from here instead of creating them.
+    pub sampler_cache: SamplerCache,
+    pub descriptor_layout_cache: DescriptorLayoutCache,
+    /// For sets that live as long as their resources.
+    pub descriptor_allocator: DescriptorAllocator,
+    /// One per frame in flight, reset in `prepare_frame`. Use `frame_descriptor_allocator`.
+    pub frame_descriptor_allocators: Vec<DescriptorAllocator>,
+    /// The global set of shaders/include/bindless.glsl, `None` without the DescriptorIndexing feature.
+    pub bindless: Option<BindlessTable>,
+    pub mipmap_generator: MipmapGenerator,
+
+    pub debug_messenger: Option<DebugMessenger>,
+    pub instance: SharedInstance
 }
 impl VulkanRenderBase {
-    pub fn prepare_frame(&mut self) -> FramePreparation { unsafe {
+    pub fn prepare_frame(&mut self) -> Result<FramePreparation, VulkanError> { unsafe {
+        if let Some(debug_messenger) = &self.debug_messenger {
+            debug_messenger.check()?;
+        }
         let wait_fence = self.in_flight_fences[self.frame_in_flight_index as usize];
         let wait_fences = [wait_fence];
         self.device.wait_for_fences(&wait_fences, true, u64::MAX)
-            .expect("MEH");
+            .context("vkWaitForFences", "in flight fence")?;
+        // A fence covers every earlier submission to the queue as well
+        if let Some(frame_number) = self.in_flight_frame_numbers[self.frame_in_flight_index as usize] {
+            self.completed_frame_count = self.completed_frame_count.max(frame_number + 1);
+        }
+        self.deletion_queue.collect(self.completed_frame_count);
+        self.frame_ring_buffer.begin_frame(self.frame_in_flight_index);
+        self.frame_descriptor_allocators[self.frame_in_flight_index as usize].reset()?;
+        if let Some(bindless) = &mut self.bindless {
+            bindless.collect(self.completed_frame_count);
+        }
+        if self.frame_number.is_multiple_of(self.memory_budget_watcher.config.check_interval.max(1)) {
+            self.check_memory_budget();
+        }
+        self.uploads.poll()?;
+
+        let swapchain = match &self.render_target {
+            RenderTarget::Swapchain(swapchain) => swapchain,
+            RenderTarget::Offscreen(_) => {
+                self.device.reset_fences(&[wait_fence])
+                    .context("vkResetFences", "in flight fence")?;
+                return Ok(FramePreparation { acquire_successful: true, image_index: self.frame_index });
+            }
+        };
 
         let available_semaphore = self.image_available_semaphores[self.frame_in_flight_index as usize];
-        let result_acquire = self.swapchain.loader
-            .acquire_next_image(self.swapchain.handle, u64::MAX, available_semaphore, vk::Fence::null())
-            .expect("MEH");
-
-        let image_index: u32 = result_acquire.0;
-        let out_of_date = result_acquire.1;
-
-        if out_of_date {
-            self.resize_swapchain();
-            return FramePreparation { acquire_successful: false, image_index };
-        }
+        let result_acquire = swapchain.loader
+            .acquire_next_image(swapchain.handle, u64::MAX, available_semaphore, vk::Fence::null());
+
+        // A suboptimal swapchain can still be rendered to, it gets recreated after present
+        let image_index: u32 = match result_acquire {
+            Ok((image_index, _suboptimal)) => image_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.resize_swapchain()?;
+                return Ok(FramePreparation { acquire_successful: false, image_index: 0 });
+            }
+            Err(result) => return Err(result).context("vkAcquireNextImageKHR", "swapchain")
+        };
 
         let reset_fence = self.in_flight_fences[self.frame_in_flight_index as usize];
         self.device.reset_fences(&[reset_fence])
-            .expect("MEH");
+            .context("vkResetFences", "in flight fence")?;
 
-        if !out_of_date {
-            return FramePreparation { acquire_successful: true, image_index };
-        }
-        println!("BBBB");
-        panic!()
+        return Ok(FramePreparation { acquire_successful: true, image_index });
     }}
 
-    pub fn submit_frame(&mut self, submit_data: FrameSubmitData) { unsafe {
-        if !submit_data.do_submit { return };
+    pub fn submit_frame(&mut self, submit_data: FrameSubmitData) -> Result<(), VulkanError> { unsafe {
+        if !submit_data.do_submit { return Ok(()) };
 
         let in_flight_index = self.frame_in_flight_index as usize;
 
@@ -92,22 +260,38 @@ impl VulkanRenderBase {
         let signal_semaphore = self.render_finished_semaphores[in_flight_index];
         let wait_stage = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
 
+        // Offscreen frames have no acquire to wait for and no present to signal
+        let semaphore_count = match self.render_target {
+            RenderTarget::Swapchain(_) => 1,
+            RenderTarget::Offscreen(_) => 0
+        };
+
         let submit_info = vk::SubmitInfo {
             s_type: vk::StructureType::SUBMIT_INFO,
             p_next: null(),
-            wait_semaphore_count: 1,
+            wait_semaphore_count: semaphore_count,
             p_wait_semaphores: &wait_semaphore,
             p_wait_dst_stage_mask: &wait_stage,
             command_buffer_count: 1,
             p_command_buffers: &command_buffer,
-            signal_semaphore_count: 1,
+            signal_semaphore_count: semaphore_count,
             p_signal_semaphores: &signal_semaphore,
         };
         let submit_infos = [submit_info];
 
         let submit_fence = self.in_flight_fences[in_flight_index].clone();
         self.device.queue_submit(self.graphics_queue, &submit_infos, submit_fence)
-            .expect("MEH");
+            .context("vkQueueSubmit", "graphics queue")?;
+        self.in_flight_frame_numbers[in_flight_index] = Some(self.frame_number);
+        self.frame_number += 1;
+
+        let swapchain = match &self.render_target {
+            RenderTarget::Swapchain(swapchain) => swapchain,
+            RenderTarget::Offscreen(_) => {
+                self.advance_frame();
+                return Ok(());
+            }
+        };
 
         let present_info = vk::PresentInfoKHR {
             s_type: vk::StructureType::PRESENT_INFO_KHR,
@@ -115,123 +299,353 @@ impl VulkanRenderBase {
             wait_semaphore_count: 1,
             p_wait_semaphores: &signal_semaphore,
             swapchain_count: 1,
-            p_swapchains: &self.swapchain.handle,
+            p_swapchains: &swapchain.handle,
             p_image_indices: &submit_data.image_index,
             p_results: null_mut(),
         };
 
-        let result_present = self.swapchain.loader.queue_present(self.present_queue, &present_info)
-            .expect("MEH") as i32;
-        if result_present == -1000001004 /* OUT OF DATE*/ || result_present == 1000001003 /* SUBOPTIMAL_KHR */ {
-            self.resize_swapchain();
+        let result_present = swapchain.loader.queue_present(self.present_queue, &present_info);
+        let needs_resize = match result_present {
+            Ok(suboptimal) => suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+            Err(result) => return Err(result).context("vkQueuePresentKHR", "present queue")
+        };
+
+        self.advance_frame();
+
+        if needs_resize {
+            self.resize_swapchain()?;
         }
 
+        return Ok(());
+    }}
+
+    pub fn compute_queue(&self) -> &DeviceQueue {
+        return &self.compute_queues[0];
+    }
+
+    pub fn transfer_queue(&self) -> &DeviceQueue {
+        return &self.transfer_queues[0];
+    }
+
+    fn advance_frame(&mut self) {
         self.frame_index = (self.frame_index + 1) % self.buffering_strategy;
         self.frame_in_flight_index = (self.frame_in_flight_index + 1) % self.frames_in_flight;
-    }}
+    }
 
-    pub fn create_buffer(&self, buffer_config: &VulkanBufferConfiguration) -> VulkanBuffer {
+    /// Destroys `resource` once the frame being recorded, and with it every earlier frame, has finished.
+    /// Dropping it right away could pull it out from under a frame in flight.
+    pub fn destroy_deferred<T: 'static>(&mut self, resource: T) {
+        self.deletion_queue.push(self.frame_number, resource);
+    }
+
+    /// Recycles the bindless `index` once the frame being recorded has finished. Release the resource
+    /// itself through `destroy_deferred` as well.
+    pub fn release_bindless(&mut self, resource_type: BindlessResourceType, index: u32) {
+        if let Some(bindless) = &mut self.bindless {
+            bindless.release(resource_type, index, self.frame_number);
+        }
+    }
+
+    /// Sets from here are valid until this frame in flight comes around again.
+    pub fn frame_descriptor_allocator(&mut self) -> &mut DescriptorAllocator {
+        return &mut self.frame_descriptor_allocators[self.frame_in_flight_index as usize];
+    }
+
+    pub fn create_buffer(&self, buffer_config: &VulkanBufferConfiguration, name: &str) -> Result<VulkanBuffer, VulkanError> {
         return vulkan_core::buffer_factory::create_buffer(
             &self.device,
-            &self.memory_properties,
-            buffer_config
+            &self.debug_utils,
+            &self.allocator,
+            buffer_config,
+            name
         );
     }
 
-    pub fn resize_swapchain(&mut self) {
-        unsafe { self.device.device_wait_idle().expect("MEH") };
+    pub fn create_image(&self, image_config: &VulkanImageConfiguration, name: &str) -> Result<VulkanImage, VulkanError> {
+        return vulkan_core::image_factory::create_image(
+            &self.device,
+            &self.debug_utils,
+            &self.allocator,
+            image_config,
+            name
+        );
+    }
+
+    /// Decodes a PNG, JPEG, KTX2 or DDS file and submits its upload. Frames submitted afterwards can sample it.
+    /// Files with only a base level get their mip chain generated, which waits for the graphics queue.
+    pub fn load_texture(&mut self, path: &Path, config: &TextureLoadConfiguration) -> Result<VulkanImage, VulkanError> {
+        let texture = read_texture_file(path, config.color_space)?;
+        check_texture_format_support(
+            &self.instance, self.physical_device, &self.enabled_features, texture.format,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST
+        )?;
+
+        let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let full_mip_levels = mip_level_count(texture.extent);
+        if !config.generate_mipmaps || texture.mip_levels() > 1 || full_mip_levels == 1 {
+            let image_config = TextureImageConfiguration {
+                mip_levels: texture.mip_levels(),
+                usage: config.usage,
+                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            };
+            let image = create_texture(
+                &self.device, &self.debug_utils, &self.allocator, &mut self.uploads, &texture, &image_config, &name
+            )?;
+            self.uploads.submit()?;
+            return Ok(image);
+        }
+
+        let method = self.mipmap_generator.select_method(texture.format, texture.kind)?;
+        let image_config = TextureImageConfiguration {
+            mip_levels: full_mip_levels,
+            usage: config.usage | method.required_usage(),
+            final_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL
+        };
+        let image = create_texture(
+            &self.device, &self.debug_utils, &self.allocator, &mut self.uploads, &texture, &image_config, &name
+        )?;
+        // The upload's last barrier is on the graphics queue, so the mipmap commands are ordered after it
+        self.uploads.submit()?;
+
+        // The image goes through the commands like the scratch resources, so a failed wait leaks it with them
+        let mipmap_generator = &mut self.mipmap_generator;
+        let (image, _scratch) = submit_one_time_commands(
+            &self.device, &self.debug_utils, self.command_pool, self.graphics_queue, &format!("{}.mipmaps", name),
+            move |command_buffer| {
+                let scratch = mipmap_generator.record(
+                    command_buffer, &image, method, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                )?;
+                return Ok((image, scratch));
+            }
+        )?;
+        return Ok(image);
+    }
+
+    /// Per heap budget and usage, and what the allocator's resources take up.
+    pub fn memory_report(&self) -> MemoryReport {
+        let budget_extension = self.device.is_extension_enabled(MEMORY_BUDGET_EXTENSION);
+        return create_memory_report(&self.instance, self.physical_device, &self.allocator, budget_extension);
+    }
+
+    /// Calls the warning hook of `memory_budget_watcher` for heaps that just crossed the threshold.
+    pub fn check_memory_budget(&mut self) {
+        let budget_extension = self.device.is_extension_enabled(MEMORY_BUDGET_EXTENSION);
+        let heaps = query_heap_usage(&self.instance, self.physical_device, &self.allocator, budget_extension);
+        self.memory_budget_watcher.check(&heaps);
+    }
+
+    pub fn create_mapped_buffer<T: bytemuck::Pod>(
+        &self, len: usize, memory_usage: MemoryUsage, buffer_usage: vk::BufferUsageFlags, name: &str
+    ) -> Result<MappedBuffer<T>, VulkanError> {
+        return create_mapped_buffer(&self.device, &self.debug_utils, &self.allocator, len, memory_usage, buffer_usage, name);
+    }
 
-        unsafe { self.swapchain.loader.destroy_swapchain(self.swapchain.handle, None) };
-        // on resize destroy
+    pub fn resize_swapchain(&mut self) -> Result<(), VulkanError> {
+        let (RenderTarget::Swapchain(old_swapchain), Some(surface)) = (&self.render_target, &self.surface) else {
+            return Ok(());
+        };
 
-        self.swapchain = create_swapchain(
+        let new_swapchain = create_swapchain(
             &self.instance,
-            &self.surface,
+            surface,
             self.physical_device,
             &self.device,
+            &self.debug_utils,
             self.buffering_strategy,
             &self.graphics_queue_family,
-            &self.present_queue_family
-        );
-        // on resize recreate
+            &self.present_queue_family,
+            old_swapchain.handle
+        )?;
+
+        // Frames in flight may still present from the old swapchain
+        let old_render_target = std::mem::replace(&mut self.render_target, RenderTarget::Swapchain(new_swapchain));
+        self.destroy_deferred(old_render_target);
+
+        return Ok(());
     }
 }
 
+impl Drop for VulkanRenderBase {
+    fn drop(&mut self) {
+        unsafe {
+            if let Err(error) = self.device.device_wait_idle().context("vkDeviceWaitIdle", "device") {
+                println!("{}", error);
+            }
+
+            // Swapchains in here need the surface, which is dropped with the fields
+            self.deletion_queue.flush();
+
+            for semaphore in self.image_available_semaphores.drain(..).chain(self.render_finished_semaphores.drain(..)) {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+            for fence in self.in_flight_fences.drain(..) {
+                self.device.destroy_fence(fence, None);
+            }
+            // Frees the command buffers as well
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
 
-pub fn initialize_vulkan(window: &winit::window::Window, buffering_strategy: u32) -> VulkanRenderBase {
-    let frames_in_flight = buffering_strategy - 1;
 
-    let entry = unsafe { ash::Entry::load().expect("Filed to initialize!") };
+pub fn initialize_vulkan(
+    window: &winit::window::Window, config: &VulkanRenderBaseConfiguration
+) -> Result<VulkanRenderBase, VulkanError> {
+    let entry = unsafe { ash::Entry::load().map_err(|e| VulkanError::Loading(e.to_string()))? };
 
-    let instance = vulkan_core::create_instance(&entry);
-    let surface_info = create_surface(&entry, &instance, window);
-    let physical_device = create_physical_device(&instance);
+    let instance = config.instance_builder.build(&entry)?;
+    let debug_messenger = create_messenger_if_enabled(&instance, config)?;
+    let surface_info = create_surface(&instance.handle, window)?;
 
-    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    return initialize_with_instance(instance, debug_messenger, Some(surface_info), config, vk::Extent2D::default());
+}
+
+/// Renders into offscreen images of the given extent instead of a swapchain.
+/// The instance builder should have surface support disabled on machines without a windowing system.
+pub fn initialize_vulkan_headless(
+    extent: vk::Extent2D, config: &VulkanRenderBaseConfiguration
+) -> Result<VulkanRenderBase, VulkanError> {
+    let entry = unsafe { ash::Entry::load().map_err(|e| VulkanError::Loading(e.to_string()))? };
+
+    let instance = config.instance_builder.build(&entry)?;
+    let debug_messenger = create_messenger_if_enabled(&instance, config)?;
+
+    return initialize_with_instance(instance, debug_messenger, None, config, extent);
+}
 
-    let unique_queue_families = get_unique_queue_families(&instance, &surface_info, physical_device);
-    let device = create_device(&instance, physical_device, &unique_queue_families);
+fn create_messenger_if_enabled(
+    instance: &InstanceInfo, config: &VulkanRenderBaseConfiguration
+) -> Result<Option<DebugMessenger>, VulkanError> {
+    if !instance.is_extension_enabled(DEBUG_UTILS_EXTENSION) {
+        return Ok(None);
+    }
+    return Ok(Some(create_debug_messenger(&instance.handle, &config.debug_messenger)?));
+}
+
+fn initialize_with_instance(
+    instance_info: InstanceInfo,
+    debug_messenger: Option<DebugMessenger>,
+    surface_info: Option<SurfaceInfo>,
+    config: &VulkanRenderBaseConfiguration,
+    offscreen_extent: vk::Extent2D
+) -> Result<VulkanRenderBase, VulkanError> {
+    let buffering_strategy = config.buffering_strategy;
+    let frames_in_flight = buffering_strategy - 1;
 
-    let graphics_queue_family = *unique_queue_families.iter()
-        .find(|q| q.flags.contains(QueueFlags::GRAPHICS))
-        .expect("MEH");
-    let present_queue_family = *unique_queue_families.iter()
-        .find(|q| q.present_supported)
-        .expect("MEH");
+    let debug_utils_enabled = instance_info.is_extension_enabled(DEBUG_UTILS_EXTENSION);
+    let instance = instance_info.handle;
+    let physical_device = create_physical_device(
+        &instance, instance_info.api_version, surface_info.as_ref(), &config.device_features
+    )?;
+    let device_properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let api_version = effective_api_version(instance_info.api_version, device_properties.api_version);
+    // The physical device was rejected unless one of the paths applies
+    let api_path = ApiPath::for_version(api_version).unwrap();
+    println!("API Path: {:?}", api_path);
 
-    let graphics_queue = get_first_queue_with_flags(&device, unique_queue_families.clone(), QueueFlags::GRAPHICS);
-    let compute_queue = get_first_queue_with_flags(&device, unique_queue_families.clone(), QueueFlags::COMPUTE);
-    let present_queue = get_queue(&device, present_queue_family.index, 0);
+    let supported_features = query_supported_features(&instance, physical_device, api_version)?;
+    let enabled_features = negotiate_features(&supported_features, &config.device_features)?;
 
-    let swapchain = create_swapchain(
-        &instance, &surface_info, physical_device, &device, buffering_strategy,
-        &graphics_queue_family, &present_queue_family
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    let unique_queue_families = get_unique_queue_families(&instance, surface_info.as_ref(), physical_device)?;
+    let device_extensions = required_device_extensions(surface_info.is_some());
+    // Headless there is nothing to present to, the graphics queue stands in for the present queue
+    let queue_plan = QueuePlan::new(&unique_queue_families, &config.queue_requests, surface_info.is_some())?;
+    let device = create_device(&instance, physical_device, &queue_plan, api_path, &device_extensions, &enabled_features)?;
+    let commands = DeviceCommands::new(&instance, &device, api_path);
+    let debug_utils = DebugUtilsDevice::new(&instance.entry, &instance, &device, debug_utils_enabled);
+    let allocator = create_allocator(
+        &device, &debug_utils, memory_properties, device_properties.limits.non_coherent_atom_size,
+        enabled_features.buffer_device_address, &config.allocator
     );
 
-    let command_pool = create_command_pool(&device, &graphics_queue_family);
+    let graphics_queue_family = queue_plan.graphics.family;
+    let present_queue_family = queue_plan.present.family;
+    let graphics_queue = get_queue(&device, queue_plan.graphics.family.index, queue_plan.graphics.queue_index);
+    let present_queue = get_queue(&device, queue_plan.present.family.index, queue_plan.present.queue_index);
+    debug_utils.set_name(graphics_queue, "graphics_queue");
+    if present_queue != graphics_queue {
+        debug_utils.set_name(present_queue, "present_queue");
+    }
+    let compute_queues = queue_plan.compute.iter().enumerate()
+        .map(|(i, slot)| get_device_queue(&device, &debug_utils, slot, &format!("compute[{}]", i)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let transfer_queues = queue_plan.transfer.iter().enumerate()
+        .map(|(i, slot)| get_device_queue(&device, &debug_utils, slot, &format!("transfer[{}]", i)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let uploads = create_upload_manager(
+        &device, &debug_utils, &allocator, &transfer_queues[0],
+        GraphicsQueue { handle: graphics_queue, family: graphics_queue_family },
+        enabled_features.timeline_semaphore, &config.uploads
+    )?;
+
+    let frame_ring_buffer = create_frame_ring_buffer(
+        &device, &debug_utils, &allocator, &device_properties.limits, frames_in_flight, &config.frame_ring_buffer
+    )?;
+
+    let sampler_cache = create_sampler_cache(
+        &device, &debug_utils, &device_properties.limits, enabled_features.sampler_anisotropy
+    );
+    let mut descriptor_layout_cache = create_descriptor_layout_cache(&device, &debug_utils);
+    let descriptor_allocator = create_descriptor_allocator(
+        &device, &debug_utils, config.descriptor_allocator.clone(), "descriptor_allocator"
+    );
+    let frame_descriptor_allocators = (0..frames_in_flight)
+        .map(|i| create_descriptor_allocator(
+            &device, &debug_utils, config.descriptor_allocator.clone(), &format!("frame[{}].descriptor_allocator", i)
+        ))
+        .collect();
+    let bindless = match enabled_features.descriptor_indexing {
+        true => Some(create_bindless_table(
+            &device, &debug_utils, physical_device, &enabled_features, &mut descriptor_layout_cache, &config.bindless
+        )?),
+        false => None
+    };
+    let mipmap_generator = create_mipmap_generator(&device, &debug_utils, physical_device, &enabled_features)?;
+
+    let render_target = match &surface_info {
+        Some(surface_info) => RenderTarget::Swapchain(create_swapchain(
+            &instance, surface_info, physical_device, &device, &debug_utils, buffering_strategy,
+            &graphics_queue_family, &present_queue_family, vk::SwapchainKHR::null()
+        )?),
+        None => RenderTarget::Offscreen(create_offscreen_targets(
+            &device, &debug_utils, &allocator, offscreen_extent,
+            vk::Format::R8G8B8A8_UNORM, vk::Format::D32_SFLOAT, buffering_strategy
+        )?)
+    };
+
+    let command_pool = create_command_pool(&device, &debug_utils, &graphics_queue_family, "graphics.command_pool")?;
     let mut command_buffers: Vec<vk::CommandBuffer> = Vec::new();
     let mut image_available_semaphores: Vec<vk::Semaphore> = Vec::new();
     let mut render_finished_semaphores: Vec<vk::Semaphore> = Vec::new();
     let mut in_flight_fences: Vec<vk::Fence> = Vec::new();
-    for _i in 0..frames_in_flight {
-        command_buffers.push(create_command_buffer(&device, command_pool, vk::CommandBufferLevel::PRIMARY));
-        image_available_semaphores.push(create_semaphore(&device));
-        render_finished_semaphores.push(create_semaphore(&device));
-        in_flight_fences.push(create_fence(&device));
-    }
-    unsafe {
-        command_buffers.set_len(frames_in_flight as usize);
-        image_available_semaphores.set_len(frames_in_flight as usize);
-        render_finished_semaphores.set_len(frames_in_flight as usize);
-        in_flight_fences.set_len(frames_in_flight as usize);
-    };
+    for i in 0..frames_in_flight {
+        command_buffers.push(create_command_buffer(
+            &device, &debug_utils, command_pool, vk::CommandBufferLevel::PRIMARY, &format!("frame[{}].command_buffer", i)
+        )?);
+        image_available_semaphores.push(create_semaphore(&device, &debug_utils, &format!("frame[{}].image_available", i))?);
+        render_finished_semaphores.push(create_semaphore(&device, &debug_utils, &format!("frame[{}].render_finished", i))?);
+        in_flight_fences.push(create_fence(&device, &debug_utils, true, &format!("frame[{}].in_flight", i))?);
+    }
 
-    return VulkanRenderBase {
-        instance, physical_device, device,
-        surface: surface_info, swapchain,
-        memory_properties,
+    return Ok(VulkanRenderBase {
+        instance, debug_messenger, physical_device, device, enabled_features, commands, debug_utils,
+        surface: surface_info, render_target,
+        memory_properties, allocator, memory_budget_watcher: MemoryBudgetWatcher::new(config.memory_budget.clone()),
         unique_queue_families, graphics_queue_family, present_queue_family,
-        graphics_queue, compute_queue, present_queue,
+        graphics_queue, present_queue, compute_queues, transfer_queues, uploads,
         command_pool: command_pool.clone(), command_buffers: command_buffers.clone(),
         image_available_semaphores, render_finished_semaphores, in_flight_fences,
-        buffering_strategy, frames_in_flight, frame_index: 0, frame_in_flight_index: 0
-    };
+        buffering_strategy, frames_in_flight, frame_index: 0, frame_in_flight_index: 0,
+        frame_number: 0, in_flight_frame_numbers: vec![None; frames_in_flight as usize], completed_frame_count: 0,
+        deletion_queue: DeletionQueue::new(), frame_ring_buffer, sampler_cache,
+        descriptor_layout_cache, descriptor_allocator, frame_descriptor_allocators, bindless, mipmap_generator
+    });
 }
 
 
-pub fn get_first_queue_with_flags(device: &ash::Device, queue_families: Vec<QueueFamily>, flags: QueueFlags) -> vk::Queue {
-    let mut queue_family_index: u32 = 0;
-    let queue_index: u32 = 0;
-    for queue_family in queue_families {
-        if queue_family.flags.contains(flags) {
-            queue_family_index = queue_family.index;
-            break;
-        }
-    }
-
-    return get_queue(device, queue_family_index, queue_index);
-}
-
 pub fn get_queue(device: &ash::Device, family_index: u32, queue_index: u32) -> vk::Queue {
     return unsafe { device.get_device_queue(family_index, queue_index) };
 }
\ No newline at end of file